@@ -0,0 +1,303 @@
+// Folder compression: scans a directory for matching files, queues one video job per file with
+// outputs in a mirrored directory tree, and reports a summary once every job of the batch is done.
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::{Emitter, Manager};
+
+use crate::error::CompressError;
+use crate::output_naming::CollisionPolicy;
+use crate::queue::{self, JobQueue, JobSettings, JobStatus, QueuedJob};
+use crate::video_codec::Container;
+use crate::{CompressionSettings, VIDEO_EXTENSIONS};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FolderScanOptions {
+    #[serde(default)]
+    pub recursive: bool,
+    #[serde(default)]
+    pub include: Vec<String>, // Glob patterns, e.g. "*.mov" or "day1/**/*.mp4"; all video files when empty
+    #[serde(default)]
+    pub exclude: Vec<String>, // Checked against directories too, so "proxies" skips a whole subtree
+    #[serde(default)]
+    pub collision: CollisionPolicy, // For outputs already in the output tree, e.g. from an earlier run
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchSummary {
+    #[serde(rename = "batchId")]
+    pub batch_id: String,
+    pub total: usize,
+    pub completed: usize,
+    pub failed: usize,
+    pub skipped: usize, // Outputs that already existed under the skip collision policy
+    pub pending: usize, // Queued, paused or still running
+    #[serde(rename = "originalSize")]
+    pub original_size: u64, // Bytes, over completed jobs only
+    #[serde(rename = "compressedSize")]
+    pub compressed_size: u64,
+    pub ratio: f64, // compressed / original over completed jobs
+    #[serde(rename = "elapsedSecs")]
+    pub elapsed_secs: f64, // Sum of job times; parallel jobs overlap, so this exceeds wall time
+    pub failures: Vec<BatchFailure>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchFailure {
+    #[serde(rename = "inputPath")]
+    pub input_path: String,
+    pub error: Option<CompressError>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+struct BatchCompletedPayload {
+    batch_id: String,
+    summary: BatchSummary,
+}
+
+// Queues every matching file under `input_dir` for compression into the same relative location
+// under `output_dir`, and returns the batch id carried by the jobs and the completion event. A
+// large folder takes a while to scan, so that runs on a blocking thread; output folders are
+// created as each job starts.
+#[tauri::command]
+pub async fn compress_folder(
+    app_handle: tauri::AppHandle,
+    input_dir: String,
+    output_dir: String,
+    settings: CompressionSettings,
+    options: Option<FolderScanOptions>,
+) -> Result<String, CompressError> {
+    let options = options.unwrap_or_default();
+    let input_root = PathBuf::from(&input_dir);
+    let output_root = PathBuf::from(&output_dir);
+    if !input_root.is_dir() {
+        return Err(CompressError::InputMissing { path: input_dir });
+    }
+    if same_path(&input_root, &output_root) {
+        return Err(CompressError::invalid_settings("outputDir", "must differ from the input folder, outputs would replace the originals"));
+    }
+
+    let (scan_root, scan_output, scan_options) = (input_root.clone(), output_root.clone(), options.clone());
+    let files = tauri::async_runtime::spawn_blocking(move || {
+        let mut files = Vec::new();
+        scan_dir(&scan_root, &scan_root, &scan_output, &scan_options, &mut files);
+        files.sort();
+        files
+    })
+    .await
+    .map_err(|e| CompressError::io("Folder scan failed", e))?;
+    if files.is_empty() {
+        return Err(CompressError::invalid_settings("include", format!("no matching files in {}", input_dir)));
+    }
+
+    let extension = settings.container.map_or("mp4", Container::extension);
+    let batch_id = crate::jobs::new_task_id();
+    let mut jobs = Vec::with_capacity(files.len());
+    for relative in files {
+        let output_path = output_root.join(&relative).with_extension(extension);
+        jobs.push(QueuedJob {
+            id: crate::jobs::new_task_id(),
+            input_path: input_root.join(&relative).to_string_lossy().into_owned(),
+            output_path: output_path.to_string_lossy().into_owned(),
+            settings: JobSettings::Video(Box::new(settings.clone())),
+            status: JobStatus::Queued,
+            message: None,
+            error: None,
+            result: None,
+            batch_id: Some(batch_id.clone()),
+            collision: options.collision,
+            output_claimed: false,
+        });
+    }
+
+    println!("Batch {}: queued {} file(s) from {} into {}", batch_id, jobs.len(), input_dir, output_dir);
+    queue::enqueue_all(&app_handle, jobs);
+    Ok(batch_id)
+}
+
+#[tauri::command]
+pub fn get_batch_summary(app_handle: tauri::AppHandle, batch_id: String) -> Result<BatchSummary, CompressError> {
+    summarize(&app_handle.state::<JobQueue>().snapshot().jobs, &batch_id)
+        .ok_or(CompressError::TaskNotFound { task_id: batch_id })
+}
+
+// Called after a batch job finishes; emits the summary once nothing of the batch is left to run
+pub fn report_if_finished(app_handle: &tauri::AppHandle, batch_id: &str) {
+    let Some(summary) = summarize(&app_handle.state::<JobQueue>().snapshot().jobs, batch_id) else { return };
+    if summary.pending > 0 {
+        return;
+    }
+    println!("Batch {}: {} completed, {} failed, {} skipped", batch_id, summary.completed, summary.failed, summary.skipped);
+    let payload = BatchCompletedPayload { batch_id: batch_id.to_string(), summary };
+    app_handle.emit("BATCH_COMPLETED_EVENT", payload)
+        .unwrap_or_else(|e| eprintln!("Failed to emit batch completion: {}", e));
+}
+
+fn summarize(jobs: &[QueuedJob], batch_id: &str) -> Option<BatchSummary> {
+    let mut summary = BatchSummary {
+        batch_id: batch_id.to_string(),
+        total: 0, completed: 0, failed: 0, skipped: 0, pending: 0,
+        original_size: 0, compressed_size: 0, ratio: 1.0, elapsed_secs: 0.0,
+        failures: Vec::new(),
+    };
+    for job in jobs.iter().filter(|job| job.batch_id.as_deref() == Some(batch_id)) {
+        summary.total += 1;
+        match job.status {
+            JobStatus::Completed => {
+                summary.completed += 1;
+                if let Some(result) = &job.result {
+                    summary.original_size += result.original_size;
+                    summary.compressed_size += result.compressed_size;
+                    summary.elapsed_secs += result.elapsed_secs;
+                }
+            }
+            JobStatus::Failed if matches!(job.error, Some(CompressError::OutputSkipped { .. })) => summary.skipped += 1,
+            JobStatus::Failed => {
+                summary.failed += 1;
+                summary.failures.push(BatchFailure { input_path: job.input_path.clone(), error: job.error.clone() });
+            }
+            JobStatus::Queued | JobStatus::Paused | JobStatus::Running => summary.pending += 1,
+        }
+    }
+    if summary.original_size > 0 {
+        summary.ratio = summary.compressed_size as f64 / summary.original_size as f64;
+    }
+    if summary.total == 0 { None } else { Some(summary) }
+}
+
+// Collects paths (relative to `root`) of matching files. Unreadable subfolders are skipped
+// rather than failing the whole batch.
+fn scan_dir(root: &Path, dir: &Path, output_root: &Path, options: &FolderScanOptions, files: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Skipping unreadable folder {}: {}", dir.display(), e);
+            return;
+        }
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        let Ok(relative) = path.strip_prefix(root) else { continue };
+        let relative_str = relative.to_string_lossy().replace('\\', "/");
+        if options.exclude.iter().any(|pattern| glob_match(pattern, &relative_str)) {
+            continue;
+        }
+        if path.is_dir() {
+            // An output folder inside the input folder must not be picked up again
+            if options.recursive && !same_path(&path, output_root) {
+                scan_dir(root, &path, output_root, options, files);
+            }
+        } else if is_included(options, &relative_str) {
+            files.push(relative.to_path_buf());
+        }
+    }
+}
+
+fn is_included(options: &FolderScanOptions, relative: &str) -> bool {
+    if options.include.is_empty() {
+        let extension = relative.rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase()).unwrap_or_default();
+        return VIDEO_EXTENSIONS.contains(&extension.as_str());
+    }
+    options.include.iter().any(|pattern| glob_match(pattern, relative))
+}
+
+fn same_path(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+// Matches a '/'-separated relative path against a glob with '*', '?' and '**' (any number of
+// folders). Patterns without a '/' only look at the file name, like .gitignore. Case-insensitive,
+// since cameras often write upper-case extensions.
+fn glob_match(pattern: &str, path: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let path = path.to_lowercase();
+    if pattern.contains('/') {
+        let pattern: Vec<&str> = pattern.split('/').filter(|s| !s.is_empty()).collect();
+        let path: Vec<&str> = path.split('/').collect();
+        match_segments(&pattern, &path)
+    } else {
+        match_segment(&pattern, path.rsplit('/').next().unwrap_or(&path))
+    }
+}
+
+fn match_segments(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| match_segments(rest, &path[skip..])),
+        Some((first, rest)) => match path.split_first() {
+            Some((name, path_rest)) => match_segment(first, name) && match_segments(rest, path_rest),
+            None => false,
+        },
+    }
+}
+
+fn match_segment(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None; // Pattern position after the last '*', and where it started matching
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p + 1, n));
+            p += 1;
+        } else if let Some((after_star, matched_from)) = star {
+            // Let the last '*' swallow one more character and retry
+            p = after_star;
+            n = matched_from + 1;
+            star = Some((after_star, n));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn double_star_matches_any_number_of_folders() {
+        assert!(glob_match("day1/**/*.mp4", "day1/a.mp4"));
+        assert!(glob_match("day1/**/*.mp4", "day1/cam1/take2/a.mp4"));
+        assert!(!glob_match("day1/**/*.mp4", "day2/cam1/a.mp4"));
+        assert!(glob_match("**/raw/*", "a/b/raw/clip.mov"));
+        assert!(!glob_match("**/raw/*", "a/b/raw/sub/clip.mov"));
+    }
+
+    #[test]
+    fn pattern_without_slash_matches_the_file_name() {
+        assert!(glob_match("*.mov", "a/b/clip.mov"));
+        assert!(glob_match("clip?.mp4", "clip1.mp4"));
+        assert!(!glob_match("clip?.mp4", "clip10.mp4"));
+        assert!(!glob_match("*.mov", "a/clip.mov.bak"));
+    }
+
+    #[test]
+    fn matching_ignores_case() {
+        assert!(glob_match("*.mp4", "DCIM/CLIP0001.MP4"));
+        assert!(glob_match("Day1/**/*.MOV", "day1/cam/clip.mov"));
+    }
+
+    #[test]
+    fn excluded_folder_skips_its_subtree() {
+        let root = std::env::temp_dir().join(format!("pressvid-scan-{}", crate::jobs::new_task_id()));
+        for dir in ["day1/proxies", "day1/cam"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in ["day1/proxies/a.mp4", "day1/cam/b.MP4", "day1/cam/notes.txt"] {
+            fs::write(root.join(file), b"").unwrap();
+        }
+        let options = FolderScanOptions { recursive: true, exclude: vec!["proxies".to_string()], ..Default::default() };
+        let mut files = Vec::new();
+        scan_dir(&root, &root, &root.join("out"), &options, &mut files);
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(files, vec![PathBuf::from("day1/cam/b.MP4")]);
+    }
+}
@@ -0,0 +1,139 @@
+// Cropping: a manual rectangle, or "auto", which runs ffmpeg's cropdetect over frames sampled
+// across the video and removes letterbox and pillarbox bars.
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::process::Command;
+
+use crate::error::CompressError;
+use crate::filter_graph::Filter;
+use crate::probe::VideoStreamInfo;
+use crate::resolution;
+use crate::trim::TrimPlan;
+
+// Points across the video where frames are checked; a single dark scene can't decide alone
+const SAMPLE_COUNT: usize = 5;
+const FRAMES_PER_SAMPLE: u32 = 12;
+
+// Luma up to which a pixel counts as black bar (of 255); above ffmpeg's default to ignore noise
+const BLACK_LIMIT: u32 = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum Crop {
+    #[default]
+    None,
+    Manual { x: u32, y: u32, width: u32, height: u32 }, // In displayed pixels, rotation applied
+    Auto,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CropRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl CropRect {
+    pub fn filter(&self) -> Filter {
+        Filter::new("crop").arg(self.width).arg(self.height).arg(self.x).arg(self.y)
+    }
+}
+
+// The rectangle to crop to, None when the whole frame is kept
+pub fn resolve(
+    crop: Crop,
+    ffmpeg: &Path,
+    input_path: &str,
+    trim: &TrimPlan,
+    source: &VideoStreamInfo,
+) -> Result<Option<CropRect>, CompressError> {
+    let (frame_width, frame_height) = resolution::display_size(source);
+    match crop {
+        Crop::None => Ok(None),
+        Crop::Manual { x, y, width, height } => {
+            // Even sizes, as with scaling; the offset may stay odd
+            let rect = CropRect { x, y, width: width / 2 * 2, height: height / 2 * 2 };
+            if rect.width < 2 || rect.height < 2
+                || x.saturating_add(rect.width) > frame_width || y.saturating_add(rect.height) > frame_height
+            {
+                return Err(CompressError::invalid_settings("crop", format!(
+                    "{}x{} at {},{} does not fit inside the {}x{} frame", width, height, x, y, frame_width, frame_height
+                )));
+            }
+            Ok(Some(rect).filter(|rect| (rect.width, rect.height) != (frame_width, frame_height)))
+        }
+        Crop::Auto => Ok(detect(ffmpeg, input_path, trim, (frame_width, frame_height))),
+    }
+}
+
+// Runs cropdetect at a few points of the (trimmed) video and keeps the smallest crop that holds
+// the picture of every sample, so a bright scene in a dark film doesn't lose its edges.
+fn detect(ffmpeg: &Path, input_path: &str, trim: &TrimPlan, frame: (u32, u32)) -> Option<CropRect> {
+    let duration = trim.duration().unwrap_or(0.0);
+    let detect_filter = Filter::new("cropdetect").opt("limit", BLACK_LIMIT).opt("round", 2).opt("reset", 0);
+    let mut union: Option<(u32, u32, u32, u32)> = None; // left, top, right, bottom
+    for i in 1..=SAMPLE_COUNT {
+        let (start, _) = trim.source_span(duration * i as f64 / (SAMPLE_COUNT + 1) as f64, 0.0);
+        let output = Command::new(ffmpeg)
+            .arg("-hide_banner")
+            .arg("-ss").arg(start.to_string())
+            .arg("-i").arg(input_path)
+            .arg("-vf").arg(detect_filter.to_string())
+            .arg("-frames:v").arg(FRAMES_PER_SAMPLE.to_string())
+            .arg("-an").arg("-f").arg("null").arg("-")
+            .output();
+        let Some(rect) = output.ok().and_then(|o| parse_cropdetect(&String::from_utf8_lossy(&o.stderr))) else { continue };
+        let (left, top, right, bottom) = (rect.x, rect.y, rect.x + rect.width, rect.y + rect.height);
+        union = Some(match union {
+            Some((l, t, r, b)) => (l.min(left), t.min(top), r.max(right), b.max(bottom)),
+            None => (left, top, right, bottom),
+        });
+    }
+
+    let (left, top, right, bottom) = union?;
+    let rect = CropRect {
+        x: left,
+        y: top,
+        width: right.min(frame.0).saturating_sub(left) / 2 * 2,
+        height: bottom.min(frame.1).saturating_sub(top) / 2 * 2,
+    };
+    // Bars thinner than a few pixels are more likely compression noise than letterboxing
+    let trims_enough = frame.0 - rect.width > 4 || frame.1 - rect.height > 4;
+    if rect.width < 2 || rect.height < 2 || !trims_enough {
+        return None;
+    }
+    println!("Detected crop {}x{} at {},{} in {}x{}", rect.width, rect.height, rect.x, rect.y, frame.0, frame.1);
+    Some(rect)
+}
+
+// cropdetect logs "... crop=1920:800:0:140" for every frame; with reset=0 the last one covers all
+// frames seen. All-black samples report negative sizes and are skipped.
+fn parse_cropdetect(stderr: &str) -> Option<CropRect> {
+    let value = &stderr[stderr.rfind("crop=")? + "crop=".len()..];
+    let mut numbers = value.split_whitespace().next()?.split(':').map(|n| n.parse::<u32>().ok());
+    let (width, height, x, y) = (numbers.next()??, numbers.next()??, numbers.next()??, numbers.next()??);
+    Some(CropRect { x, y, width, height }).filter(|rect| rect.width > 0 && rect.height > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_the_last_detected_crop() {
+        let stderr = "\
+[Parsed_cropdetect_0 @ 0x5581] x1:0 x2:1919 y1:138 y2:941 w:1920 h:800 x:0 y:140 pts:1 t:0.04 limit:0.094 crop=1920:800:0:140
+[Parsed_cropdetect_0 @ 0x5581] x1:0 x2:1919 y1:132 y2:947 w:1920 h:816 x:0 y:132 pts:2 t:0.08 limit:0.094 crop=1920:816:0:132
+frame=   12 fps=0.0 q=-0.0 Lsize=N/A time=00:00:00.48 bitrate=N/A speed=4.1x";
+        assert_eq!(parse_cropdetect(stderr), Some(CropRect { x: 0, y: 132, width: 1920, height: 816 }));
+    }
+
+    #[test]
+    fn black_or_missing_samples_give_nothing() {
+        let black = "[Parsed_cropdetect_0 @ 0x5581] x1:1919 x2:0 y1:1079 y2:0 w:-1904 h:-1064 x:1912 y:1072 crop=-1904:-1064:1912:1072";
+        assert_eq!(parse_cropdetect(black), None);
+        assert_eq!(parse_cropdetect("Invalid data found when processing input"), None);
+        assert_eq!(parse_cropdetect("crop=1920:800"), None);
+    }
+}
@@ -0,0 +1,59 @@
+// Error type shared by all commands and task events. Serialized with a "kind" tag so the
+// frontend can branch on the kind and pick its own wording.
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all_fields = "camelCase")]
+pub enum CompressError {
+    FfmpegNotFound { program: String },
+    InputMissing { path: String },
+    ProbeFailed { path: String, reason: String },
+    EncodeFailed { exit_code: Option<i32>, stderr_tail: String },
+    InvalidSettings { field: String, reason: String },
+    Cancelled { task_id: String },
+    NoFileSelected,
+    TaskNotFound { task_id: String },
+    TaskConflict { task_id: String, reason: String }, // The task exists but is in the wrong state for the request
+    Io { reason: String },                             // Filesystem or process failures outside of ffmpeg itself
+    OutputExists { path: String },                     // The collision policy forbids replacing this file
+    OutputSkipped { path: String },                    // Not compressed because the output already exists
+    NoMediaFiles { path: String },                     // A dropped folder holds nothing that can be compressed
+}
+
+impl CompressError {
+    pub fn invalid_settings(field: &str, reason: impl Into<String>) -> Self {
+        CompressError::InvalidSettings { field: field.to_string(), reason: reason.into() }
+    }
+
+    pub fn io(context: &str, error: impl fmt::Display) -> Self {
+        CompressError::Io { reason: format!("{}: {}", context, error) }
+    }
+}
+
+impl fmt::Display for CompressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompressError::FfmpegNotFound { program } => {
+                write!(f, "{} not found. Please ensure it is installed and in your PATH.", program)
+            }
+            CompressError::InputMissing { path } => write!(f, "Input file does not exist: {}", path),
+            CompressError::ProbeFailed { path, reason } => write!(f, "Could not read media info of '{}': {}", path, reason),
+            CompressError::EncodeFailed { exit_code: Some(code), stderr_tail } => {
+                write!(f, "FFmpeg exited with code {}: {}", code, stderr_tail)
+            }
+            CompressError::EncodeFailed { exit_code: None, stderr_tail } => write!(f, "FFmpeg was terminated: {}", stderr_tail),
+            CompressError::InvalidSettings { field, reason } => write!(f, "Invalid setting '{}': {}", field, reason),
+            CompressError::Cancelled { task_id } => write!(f, "Task cancelled: {}", task_id),
+            CompressError::NoFileSelected => write!(f, "No file selected"),
+            CompressError::TaskNotFound { task_id } => write!(f, "No task with id: {}", task_id),
+            CompressError::TaskConflict { task_id, reason } => write!(f, "Task {}: {}", task_id, reason),
+            CompressError::Io { reason } => write!(f, "{}", reason),
+            CompressError::OutputExists { path } => write!(f, "Output file already exists: {}", path),
+            CompressError::OutputSkipped { path } => write!(f, "Skipped, output file already exists: {}", path),
+            CompressError::NoMediaFiles { path } => write!(f, "No media files in folder: {}", path),
+        }
+    }
+}
+
+impl std::error::Error for CompressError {}
@@ -0,0 +1,282 @@
+// Finds the ffmpeg and ffprobe binaries to run, in order: the path chosen in the app, the
+// PRESSVID_FFMPEG / PRESSVID_FFPROBE environment variables, a sidecar bundled next to the app
+// executable (tauri.conf.json "externalBin"), and finally PATH. The result is cached together with
+// the version and the libraries ffmpeg was built with.
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+use crate::error::CompressError;
+
+const SETTINGS_FILE_NAME: &str = "ffmpeg.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ToolSource {
+    Settings,
+    Environment,
+    Sidecar,
+    Path,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FfmpegInstall {
+    pub ffmpeg: PathBuf,
+    pub ffprobe: PathBuf,
+    pub source: ToolSource, // Where ffmpeg was found
+    pub version: Option<String>, // e.g. "6.1.1" or "N-113529-g1ab2c3d" for git builds
+    pub libraries: Vec<String>, // From the --enable-* build flags, e.g. "libx265", "libvmaf"
+}
+
+impl FfmpegInstall {
+    // Builds that don't print their configuration are assumed to have everything, and ffmpeg
+    // reports a missing component itself when it gets used.
+    pub fn has_library(&self, library: &str) -> bool {
+        self.libraries.is_empty() || self.libraries.iter().any(|l| l == library)
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct LocatorSettings {
+    #[serde(default, rename = "ffmpegPath")]
+    ffmpeg_path: Option<PathBuf>, // The ffmpeg binary, or a folder holding ffmpeg and ffprobe
+}
+
+struct LocatorState {
+    settings_path: Option<PathBuf>,
+    explicit: Option<PathBuf>,
+    found: Option<FfmpegInstall>,
+}
+
+static STATE: Mutex<LocatorState> = Mutex::new(LocatorState { settings_path: None, explicit: None, found: None });
+
+// Restores the path chosen in an earlier session. Called once at startup.
+pub fn load_settings(data_dir: Option<PathBuf>) {
+    let settings_path = data_dir.map(|dir| dir.join(SETTINGS_FILE_NAME));
+    let settings: LocatorSettings = settings_path.as_ref()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|json| match serde_json::from_str::<LocatorSettings>(&json) {
+            Ok(settings) => Some(settings),
+            Err(e) => {
+                eprintln!("Ignoring unreadable ffmpeg settings file: {}", e);
+                None
+            }
+        })
+        .unwrap_or_default();
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    state.settings_path = settings_path;
+    state.explicit = settings.ffmpeg_path;
+    state.found = None;
+}
+
+pub fn locate() -> Result<FfmpegInstall, CompressError> {
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(found) = &state.found {
+        return Ok(found.clone());
+    }
+    let found = search(state.explicit.as_deref())?;
+    println!("Using ffmpeg {} from {:?}: {}", found.version.as_deref().unwrap_or("(unknown version)"), found.source, found.ffmpeg.display());
+    state.found = Some(found.clone());
+    Ok(found)
+}
+
+pub fn ffmpeg_path() -> Result<PathBuf, CompressError> {
+    locate().map(|install| install.ffmpeg)
+}
+
+pub fn ffprobe_path() -> Result<PathBuf, CompressError> {
+    locate().map(|install| install.ffprobe)
+}
+
+fn search(explicit: Option<&Path>) -> Result<FfmpegInstall, CompressError> {
+    let (ffmpeg, source) = find_tool("ffmpeg", "PRESSVID_FFMPEG", explicit)
+        .ok_or_else(|| CompressError::FfmpegNotFound { program: "ffmpeg".to_string() })?;
+    // ffprobe from the same folder as ffmpeg first, so the two always come from one build
+    let sibling = ffmpeg.parent().map(|dir| dir.join(binary_name("ffprobe"))).filter(|p| p.is_file());
+    let found_apart = sibling.is_none();
+    let ffprobe = sibling
+        .or_else(|| find_tool("ffprobe", "PRESSVID_FFPROBE", explicit).map(|(path, _)| path))
+        .ok_or_else(|| CompressError::FfmpegNotFound { program: "ffprobe".to_string() })?;
+
+    let version_text = version_output(&ffmpeg)?;
+    // One found elsewhere may come from another build, whose streams and options can differ
+    if found_apart {
+        let probe_text = version_output(&ffprobe)?;
+        if parse_version(&probe_text) != parse_version(&version_text) || parse_libraries(&probe_text) != parse_libraries(&version_text) {
+            return Err(CompressError::invalid_settings("ffmpegPath", format!(
+                "{} and {} come from different ffmpeg builds, put the matching ffprobe next to ffmpeg",
+                ffprobe.display(), ffmpeg.display()
+            )));
+        }
+    }
+    Ok(FfmpegInstall {
+        version: parse_version(&version_text),
+        libraries: parse_libraries(&version_text),
+        ffmpeg,
+        ffprobe,
+        source,
+    })
+}
+
+fn version_output(program: &Path) -> Result<String, CompressError> {
+    let output = Command::new(program).arg("-hide_banner").arg("-version").output()
+        .map_err(|_| CompressError::FfmpegNotFound { program: program.display().to_string() })?;
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn find_tool(name: &str, env_var: &str, explicit: Option<&Path>) -> Option<(PathBuf, ToolSource)> {
+    let from_settings = explicit.and_then(|path| {
+        if path.is_dir() {
+            Some(path.join(binary_name(name))).filter(|p| p.is_file())
+        } else {
+            // A chosen ffmpeg binary also points at the folder to look for ffprobe in
+            let candidate = if name == "ffmpeg" { path.to_path_buf() } else { path.with_file_name(binary_name(name)) };
+            Some(candidate).filter(|p| p.is_file())
+        }
+    });
+    if let Some(path) = from_settings {
+        return Some((path, ToolSource::Settings));
+    }
+    if let Some(path) = env::var_os(env_var).map(PathBuf::from).filter(|p| p.is_file()) {
+        return Some((path, ToolSource::Environment));
+    }
+    // Tauri installs externalBin sidecars next to the main executable, without the target triple
+    let sidecar = env::current_exe().ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join(binary_name(name))))
+        .filter(|p| p.is_file());
+    if let Some(path) = sidecar {
+        return Some((path, ToolSource::Sidecar));
+    }
+    let on_path = env::var_os("PATH")
+        .and_then(|paths| env::split_paths(&paths).map(|dir| dir.join(binary_name(name))).find(|p| p.is_file()));
+    on_path.map(|path| (path, ToolSource::Path))
+}
+
+fn binary_name(name: &str) -> String {
+    format!("{}{}", name, env::consts::EXE_SUFFIX)
+}
+
+// First line of `ffmpeg -version`: "ffmpeg version 6.1.1-3ubuntu5 Copyright (c) 2000-2023 ...",
+// or the same with "ffprobe"
+fn parse_version(output: &str) -> Option<String> {
+    output.lines()
+        .find_map(|line| line.strip_prefix("ffmpeg version ").or_else(|| line.strip_prefix("ffprobe version ")))
+        .and_then(|rest| rest.split_whitespace().next())
+        .map(str::to_string)
+}
+
+// "configuration: --prefix=/usr --enable-gpl --enable-libx264 --enable-libvmaf ..." from -version;
+// -buildconf lists the same flags one per line below a bare "configuration:"
+fn parse_libraries(output: &str) -> Vec<String> {
+    let mut lines = output.lines().skip_while(|line| !line.trim_start().starts_with("configuration:"));
+    let Some(config) = lines.next().and_then(|line| line.trim_start().strip_prefix("configuration:")) else {
+        return Vec::new();
+    };
+    let listed_below = lines.take_while(|line| line.trim_start().starts_with("--"));
+    config.split_whitespace()
+        .chain(listed_below.flat_map(str::split_whitespace))
+        .filter_map(|flag| flag.strip_prefix("--enable-"))
+        .filter(|name| name.starts_with("lib"))
+        .map(str::to_string)
+        .collect()
+}
+
+#[tauri::command]
+pub async fn get_ffmpeg_info() -> Result<FfmpegInstall, CompressError> {
+    locate()
+}
+
+// Chooses the ffmpeg to use (None returns to automatic lookup), checks that it runs, and
+// remembers the choice for later sessions
+#[tauri::command]
+pub async fn set_ffmpeg_path(path: Option<String>) -> Result<FfmpegInstall, CompressError> {
+    let explicit = path.map(PathBuf::from);
+    if let Some(path) = &explicit {
+        if !path.exists() {
+            return Err(CompressError::invalid_settings("ffmpegPath", format!("{} does not exist", path.display())));
+        }
+    }
+    let found = search(explicit.as_deref())?;
+    if explicit.is_some() && found.source != ToolSource::Settings {
+        return Err(CompressError::invalid_settings("ffmpegPath", "no ffmpeg binary at the chosen location"));
+    }
+    if explicit.is_some() && found.ffprobe.parent() != found.ffmpeg.parent() {
+        return Err(CompressError::invalid_settings("ffmpegPath", "no ffprobe next to the chosen ffmpeg"));
+    }
+
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(settings_path) = &state.settings_path {
+        save_settings(settings_path, &LocatorSettings { ffmpeg_path: explicit.clone() });
+    }
+    state.explicit = explicit;
+    state.found = Some(found.clone());
+    println!("ffmpeg set to {} ({:?})", found.ffmpeg.display(), found.source);
+    Ok(found)
+}
+
+fn save_settings(path: &Path, settings: &LocatorSettings) {
+    if let Some(dir) = path.parent() {
+        if let Err(e) = fs::create_dir_all(dir) {
+            eprintln!("Failed to create app data directory {}: {}", dir.display(), e);
+            return;
+        }
+    }
+    match serde_json::to_string_pretty(settings) {
+        Ok(json) => {
+            if let Err(e) = fs::write(path, json) {
+                eprintln!("Failed to save ffmpeg settings to {}: {}", path.display(), e);
+            }
+        }
+        Err(e) => eprintln!("Failed to serialize ffmpeg settings: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DISTRO_VERSION: &str = "\
+ffmpeg version 6.1.1-3ubuntu5 Copyright (c) 2000-2023 the FFmpeg developers
+built with gcc 13 (Ubuntu 13.2.0-23ubuntu3)
+configuration: --prefix=/usr --extra-version=3ubuntu5 --toolchain=hardened --enable-gpl --disable-stripping --enable-gnutls --enable-libaom --enable-libmp3lame --enable-libopus --enable-libwebp --enable-libx264 --enable-libx265 --enable-shared
+libavutil      58. 29.100 / 58. 29.100
+libavcodec     60. 31.102 / 60. 31.102
+";
+
+    const GIT_BUILDCONF: &str = "\
+ffmpeg version N-113529-g1ab2c3d4e5 Copyright (c) 2000-2024 the FFmpeg developers
+  built with gcc 13.2.0 (crosstool-NG 1.26.0.65_ecc5e41)
+  configuration: --prefix=/ffbuild/prefix --enable-gpl --enable-libsvtav1 --enable-libvmaf
+  libavutil      58. 36.101 / 58. 36.101
+
+  configuration:
+    --prefix=/ffbuild/prefix
+    --enable-gpl
+    --enable-libsvtav1
+    --enable-libvmaf
+";
+
+    #[test]
+    fn parses_release_and_git_versions() {
+        assert_eq!(parse_version(DISTRO_VERSION).as_deref(), Some("6.1.1-3ubuntu5"));
+        assert_eq!(parse_version(GIT_BUILDCONF).as_deref(), Some("N-113529-g1ab2c3d4e5"));
+        assert_eq!(parse_version("ffprobe version n7.0 Copyright (c) 2007-2024").as_deref(), Some("n7.0"));
+        assert_eq!(parse_version("not ffmpeg"), None);
+    }
+
+    #[test]
+    fn parses_enabled_libraries() {
+        assert_eq!(parse_libraries(DISTRO_VERSION), vec!["libaom", "libmp3lame", "libopus", "libwebp", "libx264", "libx265"]);
+        assert_eq!(parse_libraries(GIT_BUILDCONF), vec!["libsvtav1", "libvmaf"]);
+        assert!(parse_libraries("ffmpeg version 6.0").is_empty());
+    }
+
+    #[test]
+    fn parses_buildconf_flags_listed_one_per_line() {
+        let hidden_banner = "\n  configuration:\n    --enable-gpl\n    --enable-libx264\n    --enable-libvmaf\n";
+        assert_eq!(parse_libraries(hidden_banner), vec!["libx264", "libvmaf"]);
+    }
+}
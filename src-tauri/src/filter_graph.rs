@@ -0,0 +1,162 @@
+// Filter graphs built from typed filters instead of strings. Option values are escaped in one
+// place, so paths and expressions can't break the graph, and every encode of a task (both passes,
+// quality samples, the quality report) renders its filters from the same chain.
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    name: &'static str,
+    options: Vec<(Option<&'static str>, String)>, // Positional when the key is None
+}
+
+impl Filter {
+    pub fn new(name: &'static str) -> Self {
+        Filter { name, options: Vec::new() }
+    }
+
+    // Positional option, as in "scale=1280:720"
+    pub fn arg(mut self, value: impl ToString) -> Self {
+        self.options.push((None, value.to_string()));
+        self
+    }
+
+    pub fn opt(mut self, key: &'static str, value: impl ToString) -> Self {
+        self.options.push((Some(key), value.to_string()));
+        self
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name)?;
+        for (i, (key, value)) in self.options.iter().enumerate() {
+            f.write_str(if i == 0 { "=" } else { ":" })?;
+            if let Some(key) = key {
+                write!(f, "{}=", key)?;
+            }
+            f.write_str(&escape(value))?;
+        }
+        Ok(())
+    }
+}
+
+// Filters applied one after another to a single stream
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FilterChain {
+    filters: Vec<Filter>,
+}
+
+impl FilterChain {
+    // Appends a filter; None leaves the chain as it is, for filters that only apply sometimes
+    pub fn then(mut self, filter: impl Into<Option<Filter>>) -> Self {
+        self.filters.extend(filter.into());
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    // The chain as a -vf value, None when there is nothing to filter
+    pub fn to_vf(&self) -> Option<String> {
+        (!self.is_empty()).then(|| self.to_string())
+    }
+}
+
+impl From<Filter> for FilterChain {
+    fn from(filter: Filter) -> Self {
+        FilterChain { filters: vec![filter] }
+    }
+}
+
+// An empty chain renders as "null", ffmpeg's pass-through, so it can still sit between two pads
+impl fmt::Display for FilterChain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.filters.is_empty() {
+            return f.write_str("null");
+        }
+        for (i, filter) in self.filters.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}", filter)?;
+        }
+        Ok(())
+    }
+}
+
+// Chains connected through labelled pads, for -filter_complex and -lavfi
+#[derive(Debug, Clone, Default)]
+pub struct FilterGraph {
+    chains: Vec<String>,
+}
+
+impl FilterGraph {
+    // Adds `chain` reading the `inputs` pads ("0:v:0", or a label an earlier chain wrote) and
+    // writing the `outputs` pads
+    pub fn chain(&mut self, inputs: &[&str], chain: &FilterChain, outputs: &[&str]) -> &mut Self {
+        let pads = |labels: &[&str]| labels.iter().map(|label| format!("[{}]", label)).collect::<String>();
+        self.chains.push(format!("{}{}{}", pads(inputs), chain, pads(outputs)));
+        self
+    }
+}
+
+impl fmt::Display for FilterGraph {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.chains.join(";"))
+    }
+}
+
+// Escaped twice: once for the option parser (':' separates options, '=' keys from values) and once
+// for the graph parser (',' ';' '[' ']' separate filters and pads).
+fn escape(value: &str) -> String {
+    let escape_chars = |value: &str, special: &[char]| {
+        let mut escaped = String::with_capacity(value.len());
+        for c in value.chars() {
+            if special.contains(&c) {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        escaped
+    };
+    let option_value = escape_chars(value, &['\\', '\'', ':', '=']);
+    escape_chars(&option_value, &['\\', '\'', '[', ']', ',', ';'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_are_escaped_for_option_and_graph_parsers() {
+        let subtitles = Filter::new("subtitles").arg(r"C:\clips\it's.srt");
+        assert_eq!(subtitles.to_string(), r"subtitles=C\\:\\\\clips\\\\it\\\'s.srt");
+        let select = Filter::new("select").arg("eq(n,0)+gt(scene,0.4)");
+        assert_eq!(select.to_string(), r"select=eq(n\,0)+gt(scene\,0.4)");
+        let pad = Filter::new("drawtext").opt("text", "[a];b=c");
+        assert_eq!(pad.to_string(), r"drawtext=text=\[a\]\;b\\=c");
+    }
+
+    #[test]
+    fn chain_joins_filters_and_empty_chain_is_null() {
+        let chain = FilterChain::default()
+            .then(Filter::new("scale").arg(1280).arg(-2))
+            .then(None)
+            .then(Filter::new("fps").arg(30));
+        assert_eq!(chain.to_string(), "scale=1280:-2,fps=30");
+        assert_eq!(chain.to_vf().as_deref(), Some("scale=1280:-2,fps=30"));
+
+        let empty = FilterChain::default();
+        assert_eq!(empty.to_string(), "null");
+        assert_eq!(empty.to_vf(), None);
+    }
+
+    #[test]
+    fn graph_labels_pads_and_separates_chains() {
+        let mut graph = FilterGraph::default();
+        graph.chain(&["0:v:0"], &Filter::new("split").into(), &["a", "b"])
+            .chain(&["a", "b"], &FilterChain::default(), &["out"]);
+        assert_eq!(graph.to_string(), "[0:v:0]split[a][b];[a][b]null[out]");
+    }
+}
@@ -0,0 +1,106 @@
+// Output frame rate: keep the source's, cap it (60 fps screen recordings down to 30 for chat), or
+// convert to an exact rate. Changes go through the fps filter, which also turns variable frame
+// rate sources into constant frame rate output.
+use serde::{Deserialize, Serialize};
+
+use crate::error::CompressError;
+use crate::filter_graph::Filter;
+use crate::probe::VideoStreamInfo;
+
+const MIN_FPS: f64 = 1.0;
+const MAX_FPS: f64 = 240.0;
+
+// Assumed when ffprobe reports no rate, e.g. for some raw streams
+const FALLBACK_FPS: f64 = 30.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum FrameRate {
+    #[default]
+    Keep,
+    Cap { fps: f64 },   // Only sources above `fps` are converted
+    Exact { fps: f64 }, // Always converted, including up
+}
+
+impl FrameRate {
+    pub fn validate(self) -> Result<Self, CompressError> {
+        match self {
+            FrameRate::Cap { fps } | FrameRate::Exact { fps } if !(MIN_FPS..=MAX_FPS).contains(&fps) => {
+                Err(CompressError::invalid_settings("frameRate", format!("{} fps is outside {}-{}", fps, MIN_FPS, MAX_FPS)))
+            }
+            _ => Ok(self),
+        }
+    }
+
+    // The rate ffmpeg is asked to produce, None to leave the timing alone. A variable frame rate
+    // source is converted even when its average is under the cap, since its peaks may not be, but
+    // to its average rather than up to the cap.
+    fn converted_fps(self, source: &VideoStreamInfo) -> Option<f64> {
+        match self {
+            FrameRate::Keep => None,
+            FrameRate::Exact { fps } => Some(fps),
+            FrameRate::Cap { fps } => match source.frame_rate {
+                Some(source_fps) if source_fps <= fps + 0.01 && !source.variable_frame_rate => None,
+                Some(source_fps) => Some(fps.min(source_fps)),
+                None => Some(fps),
+            },
+        }
+    }
+
+    pub fn filter(self, source: &VideoStreamInfo) -> Option<Filter> {
+        self.converted_fps(source).map(|fps| Filter::new("fps").arg(fps))
+    }
+
+    // Frames per second the output will have, for budgeting per-frame costs
+    pub fn output_fps(self, source: &VideoStreamInfo) -> f64 {
+        self.converted_fps(source).or(source.frame_rate).unwrap_or(FALLBACK_FPS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(frame_rate: Option<f64>, variable_frame_rate: bool) -> VideoStreamInfo {
+        VideoStreamInfo {
+            index: 0, codec: "h264".to_string(), profile: None, width: 1920, height: 1080,
+            frame_rate, variable_frame_rate, frame_count: None, bit_rate: None, pixel_format: None,
+            rotation: 0, attached_pic: false, hdr: None,
+        }
+    }
+
+    #[test]
+    fn keep_leaves_the_timing_alone() {
+        assert_eq!(FrameRate::Keep.filter(&source(Some(59.94), true)), None);
+        assert_eq!(FrameRate::Keep.output_fps(&source(Some(59.94), true)), 59.94);
+        assert_eq!(FrameRate::Keep.output_fps(&source(None, false)), FALLBACK_FPS);
+    }
+
+    #[test]
+    fn cap_only_converts_faster_sources() {
+        let cap = FrameRate::Cap { fps: 30.0 };
+        assert_eq!(cap.filter(&source(Some(60.0), false)).map(|f| f.to_string()).as_deref(), Some("fps=30"));
+        assert_eq!(cap.output_fps(&source(Some(60.0), false)), 30.0);
+        assert_eq!(cap.filter(&source(Some(29.97), false)), None);
+        assert_eq!(cap.output_fps(&source(Some(29.97), false)), 29.97);
+        assert_eq!(cap.output_fps(&source(None, false)), 30.0);
+    }
+
+    #[test]
+    fn variable_source_under_the_cap_keeps_its_average() {
+        let cap = FrameRate::Cap { fps: 60.0 };
+        assert_eq!(cap.filter(&source(Some(29.97), true)).map(|f| f.to_string()).as_deref(), Some("fps=29.97"));
+        assert_eq!(cap.output_fps(&source(Some(29.97), true)), 29.97);
+        assert_eq!(cap.output_fps(&source(Some(90.0), true)), 60.0);
+    }
+
+    #[test]
+    fn exact_converts_in_both_directions() {
+        let exact = FrameRate::Exact { fps: 24.0 };
+        assert_eq!(exact.output_fps(&source(Some(60.0), false)), 24.0);
+        assert_eq!(exact.output_fps(&source(Some(12.0), false)), 24.0);
+        assert!(FrameRate::Exact { fps: 0.5 }.validate().is_err());
+        assert!(FrameRate::Cap { fps: 300.0 }.validate().is_err());
+        assert!(FrameRate::Cap { fps: 30.0 }.validate().is_ok());
+    }
+}
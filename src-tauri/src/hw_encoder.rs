@@ -0,0 +1,338 @@
+// Hardware video encoders (NVENC, QSV, VAAPI, VideoToolbox, AMF): detecting which ones this ffmpeg
+// and machine can use, and mapping our quality and speed settings onto each one's rate control.
+use serde::{Deserialize, Serialize};
+use std::process::{Command, Stdio};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::error::CompressError;
+use crate::ffmpeg_locator;
+use crate::filter_graph::{Filter, FilterChain};
+use crate::video_codec::{Container, VideoCodec};
+
+// Render node used for VAAPI; the first GPU on nearly every Linux system
+const VAAPI_DEVICE: &str = "/dev/dri/renderD128";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HwBackend {
+    Auto, // First backend that works, in the order of PREFERENCE
+    Nvenc,
+    Qsv,
+    Vaapi,
+    #[serde(rename = "videotoolbox")]
+    VideoToolbox,
+    Amf,
+}
+
+const PREFERENCE: [HwBackend; 5] = [HwBackend::Nvenc, HwBackend::VideoToolbox, HwBackend::Qsv, HwBackend::Amf, HwBackend::Vaapi];
+
+impl HwBackend {
+    fn suffix(self) -> &'static str {
+        match self {
+            HwBackend::Auto => "auto",
+            HwBackend::Nvenc => "nvenc",
+            HwBackend::Qsv => "qsv",
+            HwBackend::Vaapi => "vaapi",
+            HwBackend::VideoToolbox => "videotoolbox",
+            HwBackend::Amf => "amf",
+        }
+    }
+
+    // ffmpeg encoder name for a codec on this backend, e.g. "hevc_nvenc"
+    fn encoder_name(self, codec: VideoCodec) -> Option<String> {
+        let family = match codec {
+            VideoCodec::H264 => "h264",
+            VideoCodec::H265 => "hevc",
+            VideoCodec::Vp9 => "vp9",
+            VideoCodec::Av1Aom | VideoCodec::Av1Svt => "av1",
+        };
+        let supported = match self {
+            HwBackend::Auto => false,
+            HwBackend::Nvenc | HwBackend::Amf => family != "vp9",
+            HwBackend::Qsv | HwBackend::Vaapi => true,
+            HwBackend::VideoToolbox => matches!(family, "h264" | "hevc"),
+        };
+        supported.then(|| format!("{}_{}", family, self.suffix()))
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EncoderCapabilities {
+    pub hwaccels: Vec<String>, // From `ffmpeg -hwaccels`, e.g. "cuda", "vaapi"
+    #[serde(rename = "hardwareEncoders")]
+    pub hardware_encoders: Vec<HardwareEncoder>, // Listed by ffmpeg and passed a test encode
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HardwareEncoder {
+    pub backend: HwBackend,
+    pub codec: VideoCodec,
+    pub encoder: String,
+}
+
+// The encoder a video task runs: a software codec, or that codec on a hardware backend
+#[derive(Debug, Clone, Copy)]
+pub struct VideoEncoder {
+    pub codec: VideoCodec,
+    pub hardware: Option<HwBackend>, // Never Auto; resolved by select_encoder
+}
+
+impl VideoEncoder {
+    pub fn software(codec: VideoCodec) -> Self {
+        VideoEncoder { codec, hardware: None }
+    }
+
+    pub fn encoder_name(&self) -> String {
+        self.hardware
+            .and_then(|backend| backend.encoder_name(self.codec))
+            .unwrap_or_else(|| self.codec.encoder_name().to_string())
+    }
+
+    // Constant-quality rate control for an x264-scale CRF. Hardware encoders have no CRF, so each
+    // gets its closest constant-quality mode.
+    pub fn crf_args(&self, x264_crf: u8) -> Vec<String> {
+        let mut crf = x264_crf.min(51) as u32;
+        // The AV1 quantizer of QSV and VAAPI runs 0-255 rather than 0-51
+        if matches!(self.codec, VideoCodec::Av1Aom | VideoCodec::Av1Svt) && matches!(self.hardware, Some(HwBackend::Qsv | HwBackend::Vaapi)) {
+            crf = (crf * 255 + 25) / 51;
+        }
+        match self.hardware {
+            None | Some(HwBackend::Auto) => self.codec.crf_args(x264_crf),
+            Some(HwBackend::Nvenc) => vec!["-rc".into(), "vbr".into(), "-cq".into(), crf.to_string(), "-b:v".into(), "0".into()],
+            Some(HwBackend::Qsv) => vec!["-global_quality".into(), crf.to_string()],
+            Some(HwBackend::Vaapi) => vec!["-rc_mode".into(), "CQP".into(), "-qp".into(), crf.to_string()],
+            // -q:v runs 1-100 with higher meaning better; CRF 23 lands at 58
+            Some(HwBackend::VideoToolbox) => {
+                let quality = (100.0 - crf as f32 * 1.8).clamp(1.0, 100.0) as u8;
+                vec!["-q:v".into(), quality.to_string()]
+            }
+            Some(HwBackend::Amf) => vec![
+                "-rc".into(), "cqp".into(),
+                "-qp_i".into(), crf.to_string(),
+                "-qp_p".into(), crf.to_string(),
+                "-qp_b".into(), crf.to_string(),
+            ],
+        }
+    }
+
+    // Encoder speed for one of the x264 preset names we use ("fast", "medium", "slow")
+    pub fn speed_args(&self, speed: &str) -> Vec<String> {
+        let pick = |fast: &str, medium: &str, slow: &str| match speed {
+            "fast" => fast.to_string(),
+            "slow" => slow.to_string(),
+            _ => medium.to_string(),
+        };
+        match self.hardware {
+            None | Some(HwBackend::Auto) => self.codec.speed_args(speed),
+            Some(HwBackend::Nvenc) => vec!["-preset".into(), pick("p2", "p4", "p6")],
+            Some(HwBackend::Qsv) => vec!["-preset".into(), pick("veryfast", "medium", "veryslow")],
+            Some(HwBackend::Vaapi) => vec!["-compression_level".into(), pick("6", "4", "1")],
+            Some(HwBackend::VideoToolbox) => vec!["-prio_speed".into(), pick("1", "0", "0")],
+            Some(HwBackend::Amf) => vec!["-quality".into(), pick("speed", "balanced", "quality")],
+        }
+    }
+
+    pub fn is_hardware(&self) -> bool {
+        self.hardware.is_some()
+    }
+
+    pub fn compatibility_args(&self, container: Container) -> Vec<String> {
+        self.codec.compatibility_args(container)
+    }
+
+    // Options that must come before -i, such as the VAAPI device
+    pub fn input_args(&self) -> Vec<String> {
+        match self.hardware {
+            Some(HwBackend::Vaapi) => vec!["-vaapi_device".into(), VAAPI_DEVICE.into()],
+            _ => Vec::new(),
+        }
+    }
+
+    // The filters this encoder is fed through. VAAPI encodes from GPU surfaces, so frames are
+    // uploaded after any software filtering.
+    pub fn video_filter(&self, chain: &FilterChain) -> FilterChain {
+        match self.hardware {
+            Some(HwBackend::Vaapi) => chain.clone()
+                .then(Filter::new("format").arg("nv12"))
+                .then(Filter::new("hwupload")),
+            _ => chain.clone(),
+        }
+    }
+}
+
+// Picks the hardware encoder for a task from the detected `capabilities`. A request that this
+// machine can't serve falls back to the software encoder of the same codec (libx264 by default),
+// so settings stay portable.
+pub fn select_encoder(codec: VideoCodec, requested: HwBackend, capabilities: &EncoderCapabilities) -> VideoEncoder {
+    let candidates: Vec<HwBackend> = if requested == HwBackend::Auto { PREFERENCE.to_vec() } else { vec![requested] };
+    let found = candidates.into_iter().find(|backend| {
+        capabilities.hardware_encoders.iter().any(|hw| hw.backend == *backend && hw.codec == codec)
+    });
+    match found {
+        Some(backend) => VideoEncoder { codec, hardware: Some(backend) },
+        None => {
+            println!("No usable {:?} encoder for {}, encoding in software", requested, codec.encoder_name());
+            VideoEncoder::software(codec)
+        }
+    }
+}
+
+// Detected once per ffmpeg binary: listing and test-encoding takes a few seconds with several GPUs
+pub fn capabilities() -> Result<EncoderCapabilities, CompressError> {
+    static CACHE: Mutex<Option<(PathBuf, EncoderCapabilities)>> = Mutex::new(None);
+    let ffmpeg = ffmpeg_locator::ffmpeg_path()?;
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((path, capabilities)) = cache.as_ref() {
+        if *path == ffmpeg {
+            return Ok(capabilities.clone());
+        }
+    }
+    let detected = detect(&ffmpeg)?;
+    *cache = Some((ffmpeg, detected.clone()));
+    Ok(detected)
+}
+
+fn detect(ffmpeg: &Path) -> Result<EncoderCapabilities, CompressError> {
+    let listed = parse_encoder_list(&ffmpeg_stdout(ffmpeg, &["-hide_banner", "-encoders"])?);
+    let hwaccels = parse_hwaccels(&ffmpeg_stdout(ffmpeg, &["-hide_banner", "-hwaccels"])?);
+
+    let mut hardware_encoders = Vec::new();
+    for backend in PREFERENCE {
+        for codec in [VideoCodec::H264, VideoCodec::H265, VideoCodec::Av1Svt, VideoCodec::Vp9] {
+            let Some(encoder) = backend.encoder_name(codec) else { continue };
+            // Being compiled in doesn't mean a GPU is present, so each one has to encode a few frames
+            if listed.contains(&encoder) && test_encode(ffmpeg, VideoEncoder { codec, hardware: Some(backend) }) {
+                println!("Hardware encoder available: {}", encoder);
+                hardware_encoders.push(HardwareEncoder { backend, codec, encoder });
+            }
+        }
+    }
+    // The AV1 entry stands for both AV1 codec choices
+    let av1: Vec<HardwareEncoder> = hardware_encoders.iter()
+        .filter(|hw| hw.codec == VideoCodec::Av1Svt)
+        .map(|hw| HardwareEncoder { codec: VideoCodec::Av1Aom, ..hw.clone() })
+        .collect();
+    hardware_encoders.extend(av1);
+
+    Ok(EncoderCapabilities { hwaccels, hardware_encoders })
+}
+
+fn ffmpeg_stdout(ffmpeg: &Path, args: &[&str]) -> Result<String, CompressError> {
+    let output = Command::new(ffmpeg).args(args).output().map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => CompressError::FfmpegNotFound { program: ffmpeg.display().to_string() },
+        _ => CompressError::io("Failed to run ffmpeg", e),
+    })?;
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// `ffmpeg -encoders` lists " V....D h264_nvenc   NVIDIA NVENC H.264 encoder" below a " ------" line
+fn parse_encoder_list(output: &str) -> Vec<String> {
+    output.lines()
+        .skip_while(|line| !line.trim_start().starts_with("---"))
+        .skip(1)
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let flags = fields.next()?;
+            flags.starts_with('V').then(|| fields.next().map(str::to_string)).flatten()
+        })
+        .collect()
+}
+
+// `ffmpeg -hwaccels` prints a heading followed by one method per line
+fn parse_hwaccels(output: &str) -> Vec<String> {
+    output.lines()
+        .skip_while(|line| !line.starts_with("Hardware acceleration methods"))
+        .skip(1)
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn test_encode(ffmpeg: &Path, encoder: VideoEncoder) -> bool {
+    let mut command = Command::new(ffmpeg);
+    command.arg("-hide_banner").arg("-v").arg("error")
+        .args(encoder.input_args())
+        .arg("-f").arg("lavfi").arg("-i").arg("color=black:s=256x256:r=25:d=0.2");
+    if let Some(vf) = encoder.video_filter(&FilterChain::default()).to_vf() {
+        command.arg("-vf").arg(vf);
+    }
+    command.arg("-c:v").arg(encoder.encoder_name())
+        .arg("-f").arg("null").arg("-")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    command.status().map(|status| status.success()).unwrap_or(false)
+}
+
+// Lets the frontend show which hardware encoders can be chosen
+#[tauri::command]
+pub async fn detect_encoders() -> Result<EncoderCapabilities, CompressError> {
+    capabilities()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_encoders(encoders: &[(HwBackend, VideoCodec)]) -> EncoderCapabilities {
+        EncoderCapabilities {
+            hwaccels: Vec::new(),
+            hardware_encoders: encoders.iter()
+                .map(|&(backend, codec)| HardwareEncoder { backend, codec, encoder: backend.encoder_name(codec).unwrap() })
+                .collect(),
+        }
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn unavailable_backend_falls_back_to_software() {
+        let capabilities = with_encoders(&[(HwBackend::Vaapi, VideoCodec::H264)]);
+        let encoder = select_encoder(VideoCodec::H264, HwBackend::Nvenc, &capabilities);
+        assert_eq!(encoder.hardware, None);
+        assert_eq!(encoder.encoder_name(), "libx264");
+        // Available for another codec only
+        let encoder = select_encoder(VideoCodec::H265, HwBackend::Vaapi, &capabilities);
+        assert_eq!(encoder.encoder_name(), "libx265");
+    }
+
+    #[test]
+    fn auto_takes_the_preferred_backend() {
+        let capabilities = with_encoders(&[(HwBackend::Vaapi, VideoCodec::H265), (HwBackend::Qsv, VideoCodec::H265)]);
+        let encoder = select_encoder(VideoCodec::H265, HwBackend::Auto, &capabilities);
+        assert_eq!(encoder.hardware, Some(HwBackend::Qsv));
+        assert_eq!(encoder.encoder_name(), "hevc_qsv");
+    }
+
+    #[test]
+    fn crf_maps_to_each_backends_quality_mode() {
+        let encoder = |hardware| VideoEncoder { codec: VideoCodec::H264, hardware: Some(hardware) };
+        assert_eq!(encoder(HwBackend::Nvenc).crf_args(23), args(&["-rc", "vbr", "-cq", "23", "-b:v", "0"]));
+        assert_eq!(encoder(HwBackend::Qsv).crf_args(23), args(&["-global_quality", "23"]));
+        assert_eq!(encoder(HwBackend::Vaapi).crf_args(60), args(&["-rc_mode", "CQP", "-qp", "51"]));
+        assert_eq!(encoder(HwBackend::VideoToolbox).crf_args(23), args(&["-q:v", "58"]));
+        assert_eq!(encoder(HwBackend::Amf).crf_args(28), args(&["-rc", "cqp", "-qp_i", "28", "-qp_p", "28", "-qp_b", "28"]));
+    }
+
+    #[test]
+    fn av1_quantizer_is_scaled_for_qsv_and_vaapi() {
+        let encoder = |hardware| VideoEncoder { codec: VideoCodec::Av1Svt, hardware: Some(hardware) };
+        assert_eq!(encoder(HwBackend::Vaapi).crf_args(51), args(&["-rc_mode", "CQP", "-qp", "255"]));
+        assert_eq!(encoder(HwBackend::Qsv).crf_args(23), args(&["-global_quality", "115"]));
+        assert_eq!(encoder(HwBackend::Nvenc).crf_args(23), args(&["-rc", "vbr", "-cq", "23", "-b:v", "0"]));
+    }
+
+    #[test]
+    fn speed_maps_to_each_backends_preset() {
+        let encoder = |hardware| VideoEncoder { codec: VideoCodec::H264, hardware: Some(hardware) };
+        assert_eq!(encoder(HwBackend::Nvenc).speed_args("slow"), args(&["-preset", "p6"]));
+        assert_eq!(encoder(HwBackend::Qsv).speed_args("fast"), args(&["-preset", "veryfast"]));
+        assert_eq!(encoder(HwBackend::Vaapi).speed_args("medium"), args(&["-compression_level", "4"]));
+        assert_eq!(encoder(HwBackend::VideoToolbox).speed_args("fast"), args(&["-prio_speed", "1"]));
+        assert_eq!(encoder(HwBackend::Amf).speed_args("unknown"), args(&["-quality", "balanced"]));
+        assert_eq!(VideoEncoder::software(VideoCodec::H264).speed_args("slow"), args(&["-preset", "slow"]));
+    }
+}
@@ -0,0 +1,132 @@
+// Registry of running compression jobs, keyed by task id, so they can be cancelled from the UI.
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::{Child, ExitStatus};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::error::CompressError;
+use crate::output_naming::{self, CollisionPolicy};
+
+// Unique id for one compression run. The same input can be compressed several times at once
+// (e.g. 720p and 480p), so the input path cannot serve as the id.
+pub fn new_task_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+    let seq = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("task-{}-{}", millis, seq)
+}
+
+// One running job. Holds whichever ffmpeg child is currently active (pass 1 or pass 2 of a
+// two-pass encode), so cancelling always hits the process that is actually running.
+pub struct JobHandle {
+    child: Mutex<Option<Child>>,
+    cancelled: AtomicBool,
+    output_path: String,
+    started_at: Instant,
+}
+
+impl JobHandle {
+    pub fn output_path(&self) -> &str {
+        &self.output_path
+    }
+
+    // Wall time since the job was registered, covering every pass
+    pub fn elapsed(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    // Stores the freshly spawned child. If the job was cancelled before the process existed
+    // (e.g. while probing duration), the child is killed straight away.
+    pub fn attach(&self, child: Child) {
+        let mut slot = self.child.lock().unwrap_or_else(|e| e.into_inner());
+        *slot = Some(child);
+        if self.is_cancelled() {
+            if let Some(child) = slot.as_mut() {
+                let _ = child.kill();
+            }
+        }
+    }
+
+    // Reaps the attached child. Only call this once its -progress stdout has hit EOF, i.e. the
+    // process has exited, otherwise the lock would be held while ffmpeg is still running and block
+    // cancel. stderr is drained on its own thread and says nothing about that.
+    pub fn wait(&self) -> std::io::Result<ExitStatus> {
+        let mut slot = self.child.lock().unwrap_or_else(|e| e.into_inner());
+        match slot.take() {
+            Some(mut child) => child.wait(),
+            None => Err(std::io::Error::new(std::io::ErrorKind::NotFound, "no ffmpeg process attached")),
+        }
+    }
+
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        let mut slot = self.child.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(child) = slot.as_mut() {
+            if let Err(e) = child.kill() {
+                eprintln!("Failed to kill ffmpeg process: {}", e);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct JobRegistry {
+    jobs: Mutex<HashMap<String, Arc<JobHandle>>>,
+}
+
+impl JobRegistry {
+    // Applies the collision policy to the planned output and registers the task under the
+    // resulting path in one step, so two tasks starting together can't claim the same free name.
+    // Paths that running tasks write to count as taken even before the file appears.
+    pub fn register(
+        &self,
+        task_id: &str,
+        input_path: &str,
+        planned_output: &str,
+        collision: CollisionPolicy,
+    ) -> Result<Arc<JobHandle>, CompressError> {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        if jobs.contains_key(task_id) {
+            return Err(CompressError::TaskConflict { task_id: task_id.to_string(), reason: "already running".to_string() });
+        }
+        let in_use = |path: &Path| {
+            let path = path.to_string_lossy();
+            jobs.values().any(|job| job.output_path == path)
+        };
+        let output_path = output_naming::resolve_collision(planned_output.into(), input_path, collision, in_use)?;
+        let handle = Arc::new(JobHandle {
+            child: Mutex::new(None),
+            cancelled: AtomicBool::new(false),
+            output_path: output_path.to_string_lossy().into_owned(),
+            started_at: Instant::now(),
+        });
+        jobs.insert(task_id.to_string(), handle.clone());
+        Ok(handle)
+    }
+
+    pub fn get(&self, task_id: &str) -> Option<Arc<JobHandle>> {
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner()).get(task_id).cloned()
+    }
+
+    pub fn remove(&self, task_id: &str) {
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner()).remove(task_id);
+    }
+
+    // Marks the job as cancelled and kills its running ffmpeg process. Returns false if no job
+    // with that id is running.
+    pub fn cancel(&self, task_id: &str) -> bool {
+        match self.get(task_id) {
+            Some(handle) => {
+                handle.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
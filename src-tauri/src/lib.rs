@@ -1,23 +1,50 @@
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
-// use tauri::Manager; // Add this line
+mod batch;
+mod crop;
+mod error;
+mod ffmpeg_locator;
+mod filter_graph;
+mod frame_rate;
+mod hw_encoder;
+mod jobs;
+mod output_naming;
+mod probe;
+mod quality_report;
+mod quality_search;
+mod queue;
+mod resolution;
+mod target_size;
+mod trim;
+mod video_codec;
+
+use tauri::Manager; // For app_handle.state()
 use tauri::Emitter; // Add this line to bring emit into scope
 use std::path::Path;
 use serde::{Deserialize, Serialize};
-use std::process::{Command, Stdio};
+use std::process::{ChildStderr, ChildStdout, Command, Stdio};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use error::CompressError;
+use frame_rate::FrameRate;
+use filter_graph::{Filter, FilterChain, FilterGraph};
+use hw_encoder::{HwBackend, VideoEncoder};
+use jobs::{JobHandle, JobRegistry};
+use output_naming::{CollisionPolicy, OutputOptions, TemplateValues};
+use quality_search::QualityMetric;
+use queue::{JobQueue, JobSettings};
+use video_codec::{Container, VideoCodec};
 use tauri_plugin_dialog::DialogExt; // Added DialogExt
 // use tauri::Window; // Added for command signatures
-use regex::Regex; // Added for video compression progress
 use std::io::{BufRead, BufReader}; // Added for reading ffmpeg output
 use std::fs; // Add this line for file system operations
-// Note: tokio::time::sleep is used directly in the functions where needed.
-// If used more broadly, 'use tokio::time::sleep;' could be added here.
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 struct CompressionSettings {
     preset: String, // e.g., "small", "balanced", "high", "vbr_default", "cbr_default", etc.
     #[serde(rename = "presetType")] // Matches the TypeScript naming
-    preset_type: String, // "quality", "vbr", "cbr", "scale", "targetSize"
+    preset_type: String, // "quality", "vbr", "cbr", "scale", "targetSize", "perceptual"
     resolution: String, // e.g., "480p", "720p", "1080p", "original"
+    #[allow(dead_code)]
     bitrate: String, // Original bitrate field, might be used as fallback or for non-CRF custom if any
     #[serde(rename = "audioQuality")]
     audio_quality: String,
@@ -31,643 +58,476 @@ struct CompressionSettings {
     scale_percentage: Option<String>, // For Scale
     #[serde(rename = "targetSizeMB")]
     target_size_mb: Option<String>, // For Target Size (String to parse later, allows flexibility)
+    #[serde(default)]
+    codec: VideoCodec, // Video encoder, libx264 unless the frontend picks another
+    #[serde(default, rename = "keepRanges")]
+    keep_ranges: Vec<trim::KeepRange>, // Parts of the source to keep, in seconds; everything when empty
+    #[serde(default, rename = "frameRate")]
+    frame_rate: FrameRate, // {"mode": "keep"}, {"mode": "cap", "fps": 30} or {"mode": "exact", "fps": 24}
+    #[serde(default)]
+    crop: crop::Crop, // {"mode": "none"}, {"mode": "auto"} or {"mode": "manual", "x", "y", "width", "height"}
+    #[serde(default, rename = "hardwareEncoder")]
+    hardware_encoder: Option<HwBackend>, // Encode `codec` on the GPU when available, software otherwise
+    #[serde(default)]
+    container: Option<Container>, // "mp4", "mkv", "webm", "mov"; inferred from the output extension when absent
+    #[serde(default, rename = "qualityMetric")]
+    quality_metric: QualityMetric, // For Perceptual: "vmaf" or "ssim"
+    #[serde(default, rename = "qualityTarget")]
+    quality_target: Option<f64>, // For Perceptual: score the samples must reach, on the metric's scale
+    #[serde(default, rename = "verifyQuality")]
+    verify_quality: bool, // Score the output against the source with PSNR/SSIM/VMAF once encoded
 }
 
-#[derive(Debug, Deserialize, Serialize)] // Added Serialize for settings to be passed from frontend if needed, and for println
+#[derive(Debug, Clone, Deserialize, Serialize)] // Serialize is needed to persist queued jobs
 struct AudioCompressionSettings {
-    quality: String,
+    quality: String, // "low", "medium", "high"
+    #[serde(default)]
+    format: Option<String>, // "aac", "opus", "mp3", "flac"; inferred from the output extension when absent
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 struct ImageCompressionSettings {
     quality: u8, // Quality for images, typically 0-100
+    #[serde(default)]
+    format: Option<String>, // "jpeg", "png", "webp", "avif"; inferred from the output extension when absent
+    #[serde(default, rename = "maxWidth")]
+    max_width: Option<u32>, // Optional bounding box, images are only ever scaled down
+    #[serde(default, rename = "maxHeight")]
+    max_height: Option<u32>,
+    #[serde(default = "default_strip_metadata", rename = "stripMetadata")]
+    strip_metadata: bool,
+}
+
+fn default_strip_metadata() -> bool {
+    true
 }
 
 #[derive(Debug, Serialize, Clone)]
 struct ProgressPayload {
     task_id: String,
     progress: f32,
+    pass: Option<u8>, // 1 or 2 during a two-pass encode, None otherwise
+    #[serde(flatten)]
+    stats: EncodeStats,
 }
 
-#[tauri::command]
-fn greet(name: &str) -> String {
-    format!("Hello, {}! You've been greeted from Rust!", name)
+// Live encoder figures from ffmpeg's -progress output. Empty before the first report.
+#[derive(Debug, Default, Serialize, Clone)]
+struct EncodeStats {
+    fps: Option<f32>,
+    speed: Option<f32>, // Multiple of realtime, e.g. 2.5 for "2.5x"
+    eta_secs: Option<f64>, // Estimated time left for the whole task
+    bytes_written: Option<u64>,
+    bitrate_kbps: Option<f64>, // Bitrate of the output so far
 }
 
-#[tauri::command]
-async fn select_video_file(app: tauri::AppHandle) -> Result<String, String> {
-    println!("调用select_video_file命令");
-    
-    let (tx, rx) = std::sync::mpsc::channel();
-    app.dialog()
-        .file()
-        .add_filter("视频文件", &["mp4", "mov", "avi", "mkv", "wmv", "flv"])
-        .pick_file(move |file_path_option| {
-            tx.send(file_path_option).unwrap_or_else(|e| eprintln!("Failed to send file_path_option: {}",e));
-        });
-
-    // Note: .recv() is blocking. For a truly async command, you might use tokio::sync::oneshot
-    // or ensure this command is running in a way that blocking is acceptable (Tauri often handles this).
-    match rx.recv() {
-        Ok(Some(file_path_enum)) => {
-            let path_str = match file_path_enum {
-                tauri_plugin_dialog::FilePath::Path(pb) => pb.to_string_lossy().into_owned(),
-                tauri_plugin_dialog::FilePath::Url(uri_str) => uri_str.to_string(),
-            };
-            println!("选择的文件路径: {}", path_str);
-            Ok(path_str)
-        },
-        Ok(None) => {
-            println!("用户取消了文件选择");
-            Err("未选择文件".to_string())
-        }
-        Err(e) => {
-            eprintln!("Failed to receive file path from channel: {}", e);
-            Err("文件选择时发生内部错误".to_string())
-        }
-    }
+#[derive(Debug, Serialize, Clone)]
+struct CancelledPayload {
+    task_id: String,
 }
 
-#[tauri::command]
-async fn handle_dropped_file(file_path: String) -> Result<String, String> {
-    println!("处理拖放文件: {}", file_path);
-    
-    // 检查文件是否存在
-    if !Path::new(&file_path).exists() {
-        return Err(format!("文件不存在: {}", file_path));
-    }
-    
-    // 返回文件路径
-    Ok(file_path)
+// How one ffmpeg run maps onto the task's overall 0-100% progress
+#[derive(Debug, Clone, Copy)]
+struct ProgressSpan {
+    duration_secs: Option<f64>, // Input duration from the probe; without it only the final 100% is reported
+    offset: f32,
+    scale: f32,
+    pass: Option<u8>,
 }
 
-#[tauri::command]
-async fn compress_video(
-    app_handle: tauri::AppHandle,
-    input_path: String,
-    output_path: String,
-    settings: CompressionSettings,
-) -> Result<String, String> {
-    println!("开始压缩视频");
-    println!("输入路径: {}", input_path);
-    println!("输出路径: {}", output_path);
-    println!("压缩设置: {:?}", settings);
+// Share of a two-pass encode's progress bar given to pass 1. It skips audio and only gathers
+// statistics, so it usually finishes quicker than pass 2.
+const FIRST_PASS_SHARE: f32 = 0.4;
 
-    // 检查输入文件是否存在
-    if !Path::new(&input_path).exists() {
-        let error_msg = format!("输入文件不存在: {}", input_path);
-        println!("错误: {}", error_msg);
-        return Err(error_msg);
-    }
+// Share of a perceptual encode's progress bar given to the CRF search on samples
+const QUALITY_SEARCH_SHARE: f32 = 0.3;
 
-    // 获取输入文件大小
-    let input_metadata = fs::metadata(&input_path)
-        .map_err(|e| format!("无法获取输入文件元数据: {}", e))?;
-    let original_size = input_metadata.len();
-    println!("原始文件大小: {} 字节", original_size);
+impl ProgressSpan {
+    fn full(duration_secs: Option<f64>) -> Self {
+        ProgressSpan { duration_secs, offset: 0.0, scale: 1.0, pass: None }
+    }
 
-    // 检查ffmpeg是否可用
-    let ffmpeg_check = Command::new("ffmpeg")
-        .arg("-version")
-        .output();
+    fn two_pass(duration_secs: f64, pass: u8) -> Self {
+        let (offset, scale) = if pass == 1 {
+            (0.0, FIRST_PASS_SHARE)
+        } else {
+            (FIRST_PASS_SHARE * 100.0, 1.0 - FIRST_PASS_SHARE)
+        };
+        ProgressSpan { duration_secs: Some(duration_secs), offset, scale, pass: Some(pass) }
+    }
 
-    if let Err(e) = ffmpeg_check {
-        let error_msg = format!("无法找到ffmpeg: {}", e);
-        println!("错误: {}", error_msg);
-        return Err(error_msg);
+    // One sample run of the CRF search, `run` of `runs` expected in total
+    fn quality_search(sample_secs: f64, run: usize, runs: usize) -> Self {
+        let share = QUALITY_SEARCH_SHARE / runs as f32;
+        ProgressSpan { duration_secs: Some(sample_secs), offset: run as f32 * share * 100.0, scale: share, pass: None }
     }
 
-    // 构建FFmpeg命令
-    let mut command = Command::new("ffmpeg");
-    command.arg("-i").arg(&input_path);
+    // The final encode once the CRF search is done
+    fn after_quality_search(duration_secs: Option<f64>) -> Self {
+        ProgressSpan { duration_secs, offset: QUALITY_SEARCH_SHARE * 100.0, scale: 1.0 - QUALITY_SEARCH_SHARE, pass: None }
+    }
 
-    // --- Video Settings ---
-    command.arg("-c:v").arg("libx264");
-
-    // Resolution base settings
-    let mut scale_filter: Option<String> = None;
-    if settings.resolution != "original" && !settings.custom_settings { // Apply preset resolution if not custom and not original
-        match settings.resolution.as_str() {
-            "480p" => scale_filter = Some("scale=854:480".to_string()),
-            "720p" => scale_filter = Some("scale=1280:720".to_string()),
-            "1080p" => scale_filter = Some("scale=1920:1080".to_string()),
-            _ => {} // original or other custom values handled by custom_settings or scale preset
-        }
+    fn emit(&self, app_handle: &tauri::AppHandle, task_id: &str, pass_progress: f32, stats: EncodeStats) {
+        emit_progress(app_handle, task_id, self.offset + pass_progress * self.scale, self.pass, stats);
     }
 
-    if settings.custom_settings && settings.resolution != "original" { // Custom resolution
-         match settings.resolution.as_str() {
-            "480p" => scale_filter = Some("scale=854:480".to_string()),
-            "720p" => scale_filter = Some("scale=1280:720".to_string()),
-            "1080p" => scale_filter = Some("scale=1920:1080".to_string()),
-            // If "original" is selected with custom_settings, no scale filter is applied here.
-            // Potentially allow custom widthxheight string in the future.
-            _ => {}
+    // Time left at the given encode speed. During pass 1 the whole of pass 2 is still ahead,
+    // assumed to run at a similar speed.
+    fn eta_secs(&self, out_time_secs: f64, speed: f32) -> Option<f64> {
+        let duration_secs = self.duration_secs?;
+        if speed <= 0.0 {
+            return None;
         }
+        let remaining_media = (duration_secs - out_time_secs).max(0.0)
+            + if self.pass == Some(1) { duration_secs } else { 0.0 };
+        Some(remaining_media / speed as f64)
     }
+}
 
+// Extensions offered in the file dialog and picked up from dropped or batch-compressed folders.
+// Dropped and picked files are routed by their probed content, not by these lists.
+const VIDEO_EXTENSIONS: &[&str] = &["mp4", "mov", "avi", "mkv", "wmv", "flv", "webm", "m4v", "ts", "mts", "m2ts", "3gp"];
+const AUDIO_EXTENSIONS: &[&str] = &["mp3", "m4a", "aac", "wav", "flac", "ogg", "opus", "wma"];
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp", "avif", "bmp", "tif", "tiff", "gif"];
 
-    match settings.preset_type.as_str() {
-        "quality" => { // Existing quality presets (small, balanced, high)
-            let crf = match settings.preset.as_str() {
-                "small" => "28",
-                "balanced" => "23",
-                "high" => "18",
-                _ => "23", // Default
-            };
-            command.arg("-crf").arg(crf);
-            let ffmpeg_preset = match settings.preset.as_str() {
-                "small" => "fast",
-                "balanced" => "medium",
-                "high" => "slow",
-                _ => "medium",
-            };
-            command.arg("-preset").arg(ffmpeg_preset);
-        }
-        "vbr" => {
-            // For VBR, CRF is king. Resolution might be set by custom settings or kept original.
-            // If custom_settings is true, crf_value from UI is used. Otherwise, a default or preset-specific one.
-            let crf_to_use = if settings.custom_settings {
-                settings.crf_value.to_string()
-            } else {
-                // Default VBR CRF if not customized. Can be made more granular with VBR sub-presets.
-                settings.crf_value.to_string() // Uses the value set in handlePresetChange (e.g. 22)
-            };
-            command.arg("-crf").arg(crf_to_use);
-            command.arg("-preset").arg("medium"); // Default preset for VBR, can be customized too
-            println!("VBR Mode: CRF set to {}", crf_to_use);
-        }
-        "cbr" => {
-            if let Some(target_br) = &settings.target_bitrate {
-                if let Ok(target_br_val) = target_br.parse::<u32>() {
-                    if target_br_val > 0 {
-                        let br_str = format!("{}k", target_br_val);
-                        command.arg("-b:v").arg(&br_str)
-                               .arg("-minrate").arg(&br_str)
-                               .arg("-maxrate").arg(&br_str)
-                               .arg("-bufsize").arg(format!("{}k", target_br_val * 2)); // Common practice: bufsize = 2 * bitrate
-                        println!("CBR Mode: Target Bitrate set to {}", br_str);
-                    } else {
-                         println!("CBR Mode: Invalid target bitrate value {}", target_br_val);
-                         // Fallback to a default CRF to avoid error, or handle error more explicitly
-                         command.arg("-crf").arg("23").arg("-preset").arg("medium");
-                    }
-                } else {
-                    println!("CBR Mode: Failed to parse target bitrate value '{}'", target_br);
-                    command.arg("-crf").arg("23").arg("-preset").arg("medium");
-                }
-            } else {
-                println!("CBR Mode: Target bitrate not provided, falling back to default CRF.");
-                command.arg("-crf").arg("23").arg("-preset").arg("medium");
-            }
-            // Resolution for CBR is typically original unless specified by custom settings
-        }
-        "scale" => {
-            // Scale preset primarily changes resolution. Other quality params (like CRF) can be default or from custom.
-            if let Some(percentage_str) = &settings.scale_percentage {
-                if let Ok(percentage) = percentage_str.parse::<f32>() {
-                    if percentage > 0.0 && percentage <= 100.0 {
-                         // Format as scale=iw*0.5:ih*0.5 for 50%
-                        scale_filter = Some(format!("scale=iw*{}:ih*{}", percentage / 100.0, percentage / 100.0));
-                        println!("Scale Mode: Scaling to {}%", percentage);
-                    } else {
-                        println!("Scale Mode: Invalid scale percentage {}", percentage);
-                    }
-                } else {
-                     println!("Scale Mode: Failed to parse scale percentage '{}'", percentage_str);
-                }
-            }
-            // Apply a default CRF if no custom CRF is set for scaling, to maintain quality.
-            let crf_to_use = if settings.custom_settings && settings.preset_type != "cbr" { // Respect custom CRF if not CBR
-                 settings.crf_value.to_string()
-            } else {
-                "23" // Default CRF for scaling
-            };
-            command.arg("-crf").arg(crf_to_use);
-            command.arg("-preset").arg("medium");
-        }
-        "targetSize" => {
-            // This will be handled by two-pass encoding. Placeholder for now.
-            // For the first pass (and potentially single pass fallback if duration is unknown)
-            println!("Target Size Mode: Placeholder - Will use two-pass. Applying default CRF for now.");
-            // --- Target Size Mode ---
-            "targetSize" => {
-                println!("Target Size Mode selected.");
-                if let Some(size_str) = &settings.target_size_mb {
-                    if let Ok(target_size_mb_val) = size_str.parse::<f64>() {
-                        if target_size_mb_val > 0.0 {
-                            // Run two-pass encoding for target size
-                            // This requires getting video duration first, which can be done via ffprobe or parsing ffmpeg's initial output.
-                            // For simplicity in this step, we'll assume duration is known or estimate it.
-                            // A more robust solution would run ffprobe.
-
-                            // Placeholder: Get duration (e.g., by running a quick ffmpeg info pass or ffprobe)
-                            // We need a way to get duration. Let's try to parse it from an initial ffmpeg run.
-                            let duration_secs = get_video_duration(&input_path, &app_handle, &task_id_for_progress).await?;
-                            if duration_secs <= 0.0 {
-                                return Err("Could not determine video duration for target size encoding.".to_string());
-                            }
-
-                            let audio_br_kbps = match settings.audio_quality.as_str() {
-                                "low" => 96,
-                                "medium" => 128,
-                                "high" => 192,
-                                _ => 128,
-                            };
-
-                            // Calculate target video bitrate
-                            // Target total bits = target_size_MB * 1024 * 1024 * 8
-                            // Target video bits = Target total bits - (audio_bitrate_kbps * 1000 * duration_seconds)
-                            // Target video bitrate kbps = (Target video bits / duration_seconds) / 1000
-                            let target_total_bits = target_size_mb_val * 1024.0 * 1024.0 * 8.0;
-                            let audio_total_bits = audio_br_kbps as f64 * 1000.0 * duration_secs;
-                            let target_video_bits = target_total_bits - audio_total_bits;
-
-                            if target_video_bits <= 0.0 {
-                                return Err(format!(
-                                    "Target size ({}MB) is too small for the audio track at {}kbps for a {}s video. Try a larger target size or lower audio quality.",
-                                    target_size_mb_val, audio_br_kbps, duration_secs
-                                ));
-                            }
+// How many trailing ffmpeg stderr lines to keep for error messages
+const STDERR_TAIL_LINES: usize = 20;
 
-                            let calculated_video_bitrate_kbps = (target_video_bits / duration_secs) / 1000.0;
-                            let calculated_video_bitrate_str = format!("{:.0}k", calculated_video_bitrate_kbps);
-
-                            println!("Target Size: Calculated video bitrate: {} for {}MB target, {}s duration, audio {}kbps",
-                                calculated_video_bitrate_str, target_size_mb_val, duration_secs, audio_br_kbps);
-
-                            // --- First Pass ---
-                            let mut cmd_pass1 = Command::new("ffmpeg");
-                            cmd_pass1.arg("-y").arg("-i").arg(&input_path);
-                            if let Some(sf) = &scale_filter { cmd_pass1.arg("-vf").arg(sf); }
-                            cmd_pass1.arg("-c:v").arg("libx264")
-                                .arg("-b:v").arg(&calculated_video_bitrate_str)
-                                .arg("-preset").arg(if settings.custom_settings { "medium" } else { "medium" }) // Or from settings
-                                .arg("-pass").arg("1")
-                                .arg("-an") // No audio for the first pass
-                                .arg("-f").arg("null"); // Output to null device
-
-                            // Platform specific null output
-                            if cfg!(windows) { cmd_pass1.arg("NUL"); } else { cmd_pass1.arg("/dev/null"); }
-
-                            println!("Executing FFmpeg Pass 1: {:?}", cmd_pass1);
-                            let output_pass1 = cmd_pass1.output().map_err(|e| format!("FFmpeg Pass 1 failed: {}", e))?;
-                            if !output_pass1.status.success() {
-                                let stderr = String::from_utf8_lossy(&output_pass1.stderr);
-                                return Err(format!("FFmpeg Pass 1 execution failed: {}", stderr));
-                            }
-                            println!("FFmpeg Pass 1 successful.");
-                            emit_progress(&app_handle, &task_id_for_progress, 50.0); // Emit 50% after pass 1
+// Pass 2 runs of a target-size encode, including re-encodes after overshooting the target
+const MAX_TARGET_SIZE_ATTEMPTS: u32 = 3;
 
+#[derive(Debug, Serialize, Clone)]
+struct CompletedPayload {
+    task_id: String,
+    result: CompressionResult,
+}
 
-                            // --- Second Pass ---
-                            command.arg("-c:v").arg("libx264")
-                                   .arg("-b:v").arg(&calculated_video_bitrate_str)
-                                   .arg("-preset").arg(if settings.custom_settings { "medium" } else { "medium" })
-                                   .arg("-pass").arg("2");
-                            // Audio settings will be added after the match block
-                            // Scale filter also added after match block
+// Outcome of a successful compression, for the results table and CSV export
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CompressionResult {
+    #[serde(rename = "outputPath")]
+    output_path: String,
+    #[serde(rename = "originalSize")]
+    original_size: u64, // Bytes
+    #[serde(rename = "compressedSize")]
+    compressed_size: u64,
+    ratio: f64, // compressed / original, so 0.4 means the output is 40% of the input
+    #[serde(rename = "elapsedSecs")]
+    elapsed_secs: f64, // Wall time of the whole task, both passes included
+    speed: Option<f64>, // Seconds of media encoded per second of wall time
+    bitrate: Option<u64>, // Overall output bitrate in bits per second
+    width: Option<u32>,
+    height: Option<u32>,
+    codec: Option<String>, // Output video codec, or the audio codec for audio-only output
+    #[serde(rename = "ffmpegArgs")]
+    ffmpeg_args: Vec<String>, // Arguments of the final ffmpeg run
+    #[serde(default)]
+    quality: Option<quality_report::QualityReport>, // Set when the video settings asked for verification
+}
 
-                            // Set two_pass_active, so the main command execution logic knows
-                            // This structure means command is configured here, then audio & output path added later
-                            // The main command execution loop should handle progress for the second pass.
+#[derive(Debug, Serialize, Clone)]
+struct FailedPayload {
+    task_id: String,
+    error: CompressError,
+}
 
-                        } else {
-                            println!("Target Size Mode: Invalid target size value {}", target_size_mb_val);
-                            return Err(format!("Invalid target size: {}", target_size_mb_val));
-                        }
-                    } else {
-                        println!("Target Size Mode: Failed to parse target size value '{}'", size_str);
-                        return Err(format!("Could not parse target size: {}", size_str));
-                    }
-                } else {
-                    println!("Target Size Mode: Target size not provided.");
-                    return Err("Target size not specified for Target Size mode.".to_string());
-                }
-            }
-            _ => { // Fallback for unknown preset_type
-                println!("Unknown preset type: '{}'. Falling back to default CRF.", settings.preset_type);
-                command.arg("-crf").arg("23").arg("-preset").arg("medium");
-            }
-        }
-    } else { // This else block is for settings.custom_settings = true
-        // Custom settings: User controls CRF and Resolution directly if not using a specific preset logic like CBR.
-        // This part is slightly different now as preset_type guides the primary logic.
-        // If custom_settings is true, it usually means overriding parts of a selected preset_type.
-        
-        // If custom_settings is true, and it's not a preset that dictates bitrate (like CBR), allow CRF override.
-        if settings.preset_type != "cbr" {
-             command.arg("-crf").arg(settings.crf_value.to_string());
-             println!("Custom Settings: CRF set to {}", settings.crf_value);
-        }
-        // Custom resolution is handled by scale_filter logic at the start
-        // If it's a preset like 'scale' with custom_settings, scale_percentage would have been used.
-        // If it's 'quality' with custom_settings, the custom resolution and CRF are applied.
-    }
+// Keeps a job in the registry for the lifetime of a compression task, whatever way it ends
+struct JobGuard {
+    app_handle: tauri::AppHandle,
+    task_id: String,
+    job: Arc<JobHandle>,
+}
 
-    // Apply scale filter if defined (needs to be after preset_type specific video codec options)
-    if let Some(sf) = scale_filter {
-        command.arg("-vf").arg(sf);
+impl Drop for JobGuard {
+    fn drop(&mut self) {
+        self.app_handle.state::<JobRegistry>().remove(&self.task_id);
     }
+}
 
-    // --- Audio Settings ---
-    // Applied universally unless a preset specifically needs to override them.
-    let audio_bitrate_str = match settings.audio_quality.as_str() {
-        "low" => "96k",
-        "medium" => "128k",
-        "high" => "192k",
-        _ => "128k",
-    };
-    command.arg("-c:a").arg("aac").arg("-b:a").arg(audio_bitrate_str);
-
-    // --- Output ---
-    command.arg("-y").arg(&output_path);
-
-    println!("Final FFmpeg command to execute: {:?}", command);
-
-    let task_id = input_path.clone(); // Used for progress reporting.
-                                      // If two-pass, task_id_for_progress was already used for pass 1.
-                                      // The main progress loop will use this task_id.
+// Registers a task as it starts. The collision policy is applied to its planned output now, as the
+// file may have appeared since the task was queued; the guard's job holds the path it settled on.
+fn register_job(
+    app_handle: &tauri::AppHandle,
+    task_id: &str,
+    input_path: &str,
+    planned_output: &str,
+    collision: CollisionPolicy,
+) -> Result<JobGuard, CompressError> {
+    let job = app_handle.state::<JobRegistry>().register(task_id, input_path, planned_output, collision)?;
+    Ok(JobGuard { app_handle: app_handle.clone(), task_id: task_id.to_string(), job })
+}
 
-    execute_ffmpeg_command(command, app_handle, task_id, original_size, output_path).await
+// Runs a registered task and emits its completion or failure event. A cancelled task has
+// already emitted TASK_CANCELLED_EVENT, so it gets no failure event on top.
+fn run_registered_task(
+    guard: JobGuard,
+    work: impl FnOnce() -> Result<CompressionResult, CompressError>,
+) -> Result<CompressionResult, CompressError> {
+    let result = work();
+    match &result {
+        Ok(result) => {
+            let payload = CompletedPayload { task_id: guard.task_id.clone(), result: result.clone() };
+            guard.app_handle.emit("TASK_COMPLETED_EVENT", payload).unwrap_or_else(|e| eprintln!("Failed to emit completion: {}", e));
+        }
+        Err(_) if guard.job.is_cancelled() => {}
+        Err(error) => emit_task_failed(&guard.app_handle, &guard.task_id, error),
+    }
+    result
 }
 
+fn emit_task_failed(app_handle: &tauri::AppHandle, task_id: &str, error: &CompressError) {
+    let payload = FailedPayload { task_id: task_id.to_string(), error: error.clone() };
+    app_handle.emit("TASK_FAILED_EVENT", payload).unwrap_or_else(|e| eprintln!("Failed to emit failure: {}", e));
+}
 
-// Helper function to execute ffmpeg command and handle progress
-async fn execute_ffmpeg_command(
-    mut command: Command,
-    app_handle: tauri::AppHandle,
-    task_id: String,
-    original_size: u64,
-    output_path_str: String, // Pass as String to avoid lifetime issues with &Path
-) -> Result<String, String> {
+#[tauri::command]
+fn greet(name: &str) -> String {
+    format!("Hello, {}! You've been greeted from Rust!", name)
+}
 
-    let mut child = command.stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .map_err(|e| format!("Failed to execute FFmpeg command: {}", e))?;
-
-    let stderr = child.stderr.take().ok_or_else(|| "Could not capture ffmpeg stderr.".to_string())?;
-    let reader = BufReader::new(stderr);
-
-    // Regex for duration and time are defined globally or passed if needed
-    let re_duration = Regex::new(r"Duration: (\d{2}):(\d{2}):(\d{2})\.(\d{2})").unwrap();
-    let re_time = Regex::new(r"time=(\d{2}):(\d{2}):(\d{2})\.(\d{2})").unwrap();
-    let mut total_duration_secs: Option<f32> = None;
-
-    // If this is the second pass of a two-pass encode, total_duration_secs might already be known.
-    // However, ffmpeg often prints Duration again for the second pass.
-    // For simplicity, we re-parse it here. A more optimized way might pass it.
-
-    for line_result in reader.lines() {
-        match line_result {
-            Ok(line) => {
-                println!("FFMPEG_STDERR: {}", line);
-
-                if total_duration_secs.is_none() {
-                    if let Some(caps) = re_duration.captures(&line) {
-                        let h = caps.get(1).unwrap().as_str().parse::<f32>().unwrap_or(0.0);
-                        let m = caps.get(2).unwrap().as_str().parse::<f32>().unwrap_or(0.0);
-                        let s = caps.get(3).unwrap().as_str().parse::<f32>().unwrap_or(0.0);
-                        let ss = caps.get(4).unwrap().as_str().parse::<f32>().unwrap_or(0.0) / 100.0;
-                        total_duration_secs = Some(h * 3600.0 + m * 60.0 + s + ss);
-                        println!("Total duration from current pass: {:?} seconds", total_duration_secs);
-                    }
-                }
+// A file picked or dropped by the user, with the compressor its content belongs to
+#[derive(Debug, Serialize, Clone)]
+struct SelectedFile {
+    path: String,
+    kind: probe::MediaKind,
+}
 
-                if let Some(duration_secs) = total_duration_secs {
-                    if duration_secs > 0.0 { // Avoid division by zero if duration is not found or is zero
-                        if let Some(caps) = re_time.captures(&line) {
-                            let h = caps.get(1).unwrap().as_str().parse::<f32>().unwrap_or(0.0);
-                            let m = caps.get(2).unwrap().as_str().parse::<f32>().unwrap_or(0.0);
-                            let s = caps.get(3).unwrap().as_str().parse::<f32>().unwrap_or(0.0);
-                            let ss = caps.get(4).unwrap().as_str().parse::<f32>().unwrap_or(0.0) / 100.0;
-                            let current_time_secs = h * 3600.0 + m * 60.0 + s + ss;
-
-                            let mut current_progress = (current_time_secs / duration_secs) * 100.0;
-                            // If this is part of a two-pass, adjust progress (e.g., pass 2 is 50-100%)
-                            // This needs to be passed or known via context if we want to adjust it here.
-                            // For now, assuming single pass or second pass reports 0-100 for its own duration.
-                            // The caller of execute_ffmpeg_command (e.g. targetSize logic) handles the 0-50% for pass 1.
-
-                            current_progress = current_progress.min(100.0); // Cap at 100%
-
-                            // If this is the second pass of targetSize, this progress is from 0-100 for the second pass.
-                            // We need to map it to 50-100% of the total task.
-                            // This requires knowing the context. For now, the `emit_progress` helper can take base_progress.
-                            // Let's assume for now the caller of execute_ffmpeg_command handles this adjustment if needed.
-                            // Or, we add a parameter `progress_offset: f32` to this function.
-                            // For the sake of this step, we'll keep it simple.
-                            emit_progress(&app_handle, &task_id, current_progress);
-                        }
-                    }
-                }
+// Probes each path and keeps the ones that are video, audio or images. Fails only when none are.
+fn classify_files(paths: Vec<String>) -> Result<Vec<SelectedFile>, CompressError> {
+    let mut selected = Vec::new();
+    let mut first_error = None;
+    for path in paths {
+        match probe::probe(&path).and_then(|info| info.kind()
+            .ok_or_else(|| CompressError::ProbeFailed { path: path.clone(), reason: "no video, audio or image content".to_string() }))
+        {
+            Ok(kind) => selected.push(SelectedFile { path, kind }),
+            Err(e) => {
+                eprintln!("跳过无法识别的文件 {}: {}", path, e);
+                first_error.get_or_insert(e);
             }
-            Err(e) => eprintln!("Error reading ffmpeg stderr line: {}", e),
         }
     }
+    match first_error {
+        Some(e) if selected.is_empty() => Err(e),
+        _ => Ok(selected),
+    }
+}
 
-    let output_status = child.wait_with_output().map_err(|e| format!("Failed to wait for FFmpeg command: {}", e))?;
+#[tauri::command]
+async fn select_files(app: tauri::AppHandle) -> Result<Vec<SelectedFile>, CompressError> {
+    println!("调用select_files命令");
 
-    if output_status.status.success() {
-        emit_progress(&app_handle, &task_id, 100.0); // Ensure 100% is sent
+    let all_media: Vec<&str> = [VIDEO_EXTENSIONS, AUDIO_EXTENSIONS, IMAGE_EXTENSIONS].concat();
+    let (tx, rx) = std::sync::mpsc::channel();
+    app.dialog()
+        .file()
+        .add_filter("媒体文件", &all_media)
+        .add_filter("视频文件", VIDEO_EXTENSIONS)
+        .add_filter("音频文件", AUDIO_EXTENSIONS)
+        .add_filter("图片文件", IMAGE_EXTENSIONS)
+        .pick_files(move |file_paths_option| {
+            tx.send(file_paths_option).unwrap_or_else(|e| eprintln!("Failed to send file_paths_option: {}",e));
+        });
 
-        let output_path = Path::new(&output_path_str);
-        let output_metadata = fs::metadata(output_path)
-            .map_err(|e| format!("Could not get output file metadata: {}", e))?;
-        let compressed_size = output_metadata.len();
-        println!("Compressed file size: {} bytes", compressed_size);
+    // Note: .recv() is blocking. For a truly async command, you might use tokio::sync::oneshot
+    // or ensure this command is running in a way that blocking is acceptable (Tauri often handles this).
+    match rx.recv() {
+        Ok(Some(file_path_enums)) => {
+            let paths: Vec<String> = file_path_enums.into_iter().map(|file_path_enum| match file_path_enum {
+                tauri_plugin_dialog::FilePath::Path(pb) => pb.to_string_lossy().into_owned(),
+                tauri_plugin_dialog::FilePath::Url(uri_str) => uri_str.to_string(),
+            }).collect();
+            println!("选择的文件路径: {:?}", paths);
+            classify_files(paths)
+        },
+        Ok(None) => {
+            println!("用户取消了文件选择");
+            Err(CompressError::NoFileSelected)
+        }
+        Err(e) => {
+            eprintln!("Failed to receive file path from channel: {}", e);
+            Err(CompressError::io("File dialog failed", e))
+        }
+    }
+}
 
-        let change_percentage = if original_size > 0 {
-            ((compressed_size as f64 - original_size as f64) / original_size as f64) * 100.0
-        } else {
-            0.0
-        };
-        let size_change_str = if change_percentage > 0.0 {
-            format!("increased by {:.2}%", change_percentage)
-        } else if change_percentage < 0.0 {
-            format!("reduced by {:.2}%", -change_percentage)
-        } else {
-            "remained the same".to_string()
-        };
+// Resolves a dropped path to the media files it stands for: the file itself, or every media
+// file inside a dropped folder and its subfolders
+#[tauri::command]
+async fn handle_dropped_file(file_path: String) -> Result<Vec<SelectedFile>, CompressError> {
+    println!("处理拖放文件: {}", file_path);
+    
+    // 检查文件是否存在
+    let path = Path::new(&file_path);
+    if !path.exists() {
+        return Err(CompressError::InputMissing { path: file_path });
+    }
+    if !path.is_dir() {
+        return classify_files(vec![file_path]);
+    }
 
-        let success_msg = format!(
-            "Video compression successful! Output: {} (Size: {} bytes, {} compared to original)",
-            output_path.display(), compressed_size, size_change_str
-        );
-        println!("{}", success_msg);
-        Ok(success_msg)
-    } else {
-        let stderr_output = String::from_utf8_lossy(&output_status.stderr);
-        let error_msg = format!("FFmpeg command execution failed: {}", stderr_output);
-        println!("Error: {}", error_msg);
-        Err(error_msg)
+    // 文件夹：按扩展名预先筛选，避免对无关文件逐个运行 ffprobe
+    let mut paths = Vec::new();
+    collect_media_files(path, &mut paths);
+    paths.sort();
+    if paths.is_empty() {
+        return Err(CompressError::NoMediaFiles { path: file_path });
     }
+    classify_files(paths)
 }
 
-// Helper to emit progress
-fn emit_progress(app_handle: &tauri::AppHandle, task_id: &str, progress: f32) {
-    let payload = ProgressPayload {
-        task_id: task_id.to_string(),
-        progress: progress.min(100.0).max(0.0), // Ensure progress is between 0 and 100
+fn collect_media_files(dir: &Path, paths: &mut Vec<String>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        eprintln!("Skipping unreadable folder {}", dir.display());
+        return;
     };
-    app_handle.emit("PROGRESS_EVENT", payload).unwrap_or_else(|e| eprintln!("Failed to emit progress: {}", e));
-}
-
-
-// Helper function to get video duration using ffprobe or ffmpeg
-// This is a simplified version. A robust solution would use ffprobe and parse its JSON output.
-async fn get_video_duration(
-    input_path: &str,
-    app_handle: &tauri::AppHandle, // For emitting progress if this takes time
-    task_id: &str // For associating progress with a task
-) -> Result<f32, String> {
-    println!("Attempting to get video duration for: {}", input_path);
-    emit_progress(app_handle, task_id, 5.0); // Arbitrary small progress for duration check
-
-    let mut cmd = Command::new("ffmpeg");
-    cmd.arg("-i").arg(input_path);
-
-    // We don't want to actually encode, so we can try to make it fail quickly after printing info.
-    // Or use ffprobe if available. For now, let's parse ffmpeg's stderr.
-    // Adding arguments that make it error out after showing info can be one way.
-    // For example, specifying an invalid output format or non-existent output.
-    // However, just running `ffmpeg -i input` will print info to stderr and then wait for more args.
-    // We need to capture stderr.
-
-    let child = cmd.stdout(Stdio::null()) // We don't need stdout
-                   .stderr(Stdio::piped())
-                   .spawn()
-                   .map_err(|e| format!("Failed to spawn ffmpeg for duration check: {}", e))?;
-
-    let stderr_capture = child.stderr.ok_or_else(|| "Could not capture stderr for duration check".to_string())?;
-    let reader = BufReader::new(stderr_capture);
-    let re_duration = Regex::new(r"Duration: (\d{2}):(\d{2}):(\d{2})\.(\d{2})").unwrap();
-    let mut duration_found_secs: Option<f32> = None;
-
-    // It's important that ffmpeg exits after printing the duration or this will hang.
-    // For `ffmpeg -i <input>`, it will typically error due to no output file specified.
-    // We can read lines for a short period or until "Press [q] to stop" or similar.
-
-    // Reading stderr line by line. Ffmpeg might not exit immediately.
-    // A timeout or specific parsing to break the loop might be needed if ffmpeg hangs.
-    // For now, assume ffmpeg will eventually exit or print enough lines.
-    // Consider `child.wait_with_output()` if ffmpeg exits quickly.
-
-    for line_result in reader.lines() {
-        if let Ok(line) = line_result {
-            // println!("FFMPEG_DURATION_CHECK_STDERR: {}", line); // Debug
-            if let Some(caps) = re_duration.captures(&line) {
-                let h = caps.get(1).unwrap().as_str().parse::<f32>().unwrap_or(0.0);
-                let m = caps.get(2).unwrap().as_str().parse::<f32>().unwrap_or(0.0);
-                let s = caps.get(3).unwrap().as_str().parse::<f32>().unwrap_or(0.0);
-                let ss = caps.get(4).unwrap().as_str().parse::<f32>().unwrap_or(0.0) / 100.0;
-                duration_found_secs = Some(h * 3600.0 + m * 60.0 + s + ss);
-                println!("Duration found: {:?} seconds", duration_found_secs);
-                break; // Found duration, no need to parse further for this purpose.
-            }
-            // Heuristic to break if ffmpeg seems to be waiting for input or stuck
-            if line.contains("Press [q] to stop") || line.contains("At least one output file must be specified") {
-                break;
-            }
-        } else {
-            break; // Error reading line
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue; // Hidden files and folders, e.g. .DS_Store or ._ resource forks
         }
-    }
-
-    // Ensure ffmpeg process is terminated if it hasn't exited (e.g. if it's stuck)
-    // This is a bit tricky. `child.kill()` could be used.
-    // `child.wait()` or `child.try_wait()` would be better before kill.
-    // For now, we hope ffmpeg exits due to lack of output file.
-
-    match duration_found_secs {
-        Some(d) if d > 0.0 => {
-            emit_progress(app_handle, task_id, 10.0); // Progress after finding duration
-            Ok(d)
+        if path.is_dir() {
+            collect_media_files(&path, paths);
+            continue;
+        }
+        let extension = path.extension().map(|ext| ext.to_string_lossy().to_ascii_lowercase()).unwrap_or_default();
+        if [VIDEO_EXTENSIONS, AUDIO_EXTENSIONS, IMAGE_EXTENSIONS].iter().any(|list| list.contains(&extension.as_str())) {
+            paths.push(path.to_string_lossy().into_owned());
         }
-        _ => Err("Could not parse video duration from ffmpeg output.".to_string()),
     }
 }
 
-
-// Original compress_video function signature and initial checks remain the same.
-// The main change is how `command` is built and then passed to `execute_ffmpeg_command`.
-
+// Queues a video compression and returns its task id right away. It starts once the queue has a
+// free worker; progress, completion and failure are reported through events carrying that id.
+// Without an output path the name comes from the output template.
 #[tauri::command]
 async fn compress_video(
     app_handle: tauri::AppHandle,
     input_path: String,
+    output_path: Option<String>,
+    settings: CompressionSettings,
+    output: Option<OutputOptions>,
+) -> Result<String, CompressError> {
+    let output = output.unwrap_or_default();
+    let output_path = plan_output_path(&input_path, output_path, output.template.as_deref(),
+        |with_dimensions| video_template_values(&input_path, &settings, with_dimensions))?;
+    Ok(queue::enqueue(&app_handle, input_path, output_path, JobSettings::Video(Box::new(settings)), output.collision))
+}
+
+fn run_video_compression(
+    app_handle: tauri::AppHandle,
+    task_id: String,
+    input_path: String,
     output_path: String,
     settings: CompressionSettings,
-) -> Result<String, String> {
-    println!("Beginning video compression process...");
-    println!("Input: {}, Output: {}", input_path, output_path);
-    println!("Settings: {:?}", settings);
+) -> Result<CompressionResult, CompressError> {
+    println!("[{}] Beginning video compression process...", task_id);
+    println!("[{}] Input: {}, Output: {}", task_id, input_path, output_path);
+    println!("[{}] Settings: {:?}", task_id, settings);
 
     if !Path::new(&input_path).exists() {
-        return Err(format!("Input file does not exist: {}", input_path));
+        return Err(CompressError::InputMissing { path: input_path });
     }
 
     let input_metadata = fs::metadata(&input_path)
-        .map_err(|e| format!("Cannot get input file metadata: {}", e))?;
+        .map_err(|e| CompressError::io("Cannot get input file metadata", e))?;
     let original_size = input_metadata.len();
-    println!("Original file size: {} bytes", original_size);
-
-    if Command::new("ffmpeg").arg("-version").output().is_err() {
-        return Err("ffmpeg command not found. Please ensure ffmpeg is installed and in your PATH.".to_string());
+    println!("[{}] Original file size: {} bytes", task_id, original_size);
+
+    let ffmpeg = ffmpeg_locator::locate()?;
+
+    let media = probe::probe(&input_path)?;
+    let source = media.primary_video()
+        .ok_or_else(|| CompressError::ProbeFailed { path: input_path.clone(), reason: "no video stream".to_string() })?;
+    println!("[{}] Source: {} {}x{} @ {:?} fps, duration {:?}s, {} audio stream(s)",
+             task_id, source.codec, source.width, source.height, source.frame_rate,
+             media.duration_secs, media.audio_streams.len());
+    let has_audio = !media.audio_streams.is_empty();
+
+    // Output duration after trimming, used for progress and the target size budget
+    let trim = trim::TrimPlan::new(&settings.keep_ranges, media.duration_secs)?;
+    let duration_secs = trim.duration();
+    if trim.is_trimmed() {
+        println!("[{}] Keeping {} range(s), {:?}s in total", task_id, settings.keep_ranges.len(), duration_secs);
     }
 
-    let task_id_for_progress = input_path.clone(); // Use input_path as a unique ID for progress reporting
+    let codec = settings.codec;
+    let container = video_codec::resolve_container(settings.container, codec, &output_path)?;
+    let audio_encoder = container.audio_encoder();
+    let encoder = if settings.preset_type == "targetSize" && settings.hardware_encoder.is_some() {
+        // Hardware encoders have no two-pass mode, which target size depends on
+        println!("[{}] Target size uses {} instead of a hardware encoder", task_id, codec.encoder_name());
+        VideoEncoder::software(codec)
+    } else if let Some(requested) = settings.hardware_encoder {
+        match hw_encoder::capabilities() {
+            Ok(capabilities) => hw_encoder::select_encoder(codec, requested, &capabilities),
+            Err(e) => {
+                eprintln!("[{}] Hardware encoder detection failed, using {}: {}", task_id, codec.encoder_name(), e);
+                VideoEncoder::software(codec)
+            }
+        }
+    } else {
+        VideoEncoder::software(codec)
+    };
+    if encoder.is_hardware() {
+        println!("[{}] Encoding with {}", task_id, encoder.encoder_name());
+    } else if !ffmpeg.has_library(codec.library()) {
+        return Err(CompressError::invalid_settings("codec", format!(
+            "this ffmpeg build has no {} (--enable-{}), choose another codec", codec.encoder_name(), codec.library()
+        )));
+    }
 
     // --- Base Command Setup ---
-    let mut command = Command::new("ffmpeg");
+    let mut command = Command::new(&ffmpeg.ffmpeg);
+    command.args(encoder.input_args());
+    command.args(trim.input_args());
     command.arg("-i").arg(&input_path);
 
     // --- Video Settings ---
-    // Resolution base settings - determine if a scale filter is needed from general settings or custom.
-    let mut scale_filter: Option<String> = None;
-    if settings.custom_settings { // Custom settings override preset resolution choices
-        if settings.resolution != "original" {
-            match settings.resolution.as_str() {
-                "480p" => scale_filter = Some("scale=854:480".to_string()),
-                "720p" => scale_filter = Some("scale=1280:720".to_string()),
-                "1080p" => scale_filter = Some("scale=1920:1080".to_string()),
-                _ => {} // "original" or other unhandled custom strings
-            }
-        }
-    } else { // Not custom settings, use resolution if it's part of a 'quality' preset, or if 'scale' preset itself
-        if settings.preset_type == "quality" && settings.resolution != "original" {
-             match settings.resolution.as_str() {
-                "480p" => scale_filter = Some("scale=854:480".to_string()),
-                "720p" => scale_filter = Some("scale=1280:720".to_string()),
-                "1080p" => scale_filter = Some("scale=1920:1080".to_string()),
-                _ => {}
-            }
-        }
-        // Scale preset type will define its own scale_filter logic inside the match block
+    let crop = crop::resolve(settings.crop, &ffmpeg.ffmpeg, &input_path, &trim, source)?;
+    if let Some(rect) = &crop {
+        println!("[{}] Cropping: {}", task_id, rect.filter());
     }
-
+    // Output size from the resolution or scale settings, planned on what is left after cropping
+    let frame = crop.map_or(resolution::display_size(source), |rect| (rect.width, rect.height));
+    let resolution = resolution::plan(resolution::ResolutionTarget::from_settings(&settings)?, frame);
+    if resolution.scaled {
+        println!("[{}] Scaling to {}x{}", task_id, resolution.width, resolution.height);
+    }
+    let frame_rate = settings.frame_rate.validate()?;
+    let fps_filter = frame_rate.filter(source);
+    if let Some(fps) = &fps_filter {
+        println!("[{}] Frame rate conversion: {}", task_id, fps);
+    }
+    // Frame rate conversion first, so dropped frames aren't cropped and scaled for nothing. The
+    // quality report repeats everything but the scale on the source.
+    let source_filter = FilterChain::default().then(fps_filter).then(crop.map(|rect| rect.filter()));
+    let filter_chain = source_filter.clone().then(resolution.scale_filter());
+    // What the encoder is fed, shared by every encode of this task so the passes can't drift apart
+    let video_filters = encoder.video_filter(&filter_chain);
 
     // --- Preset Type Specific Logic ---
+    let mut progress = ProgressSpan::full(duration_secs);
     let is_two_pass = settings.preset_type == "targetSize"; // Flag to know if we should use the common execution path or if targetSize handled it.
 
     if !is_two_pass { // For all non-targetSize presets, or if targetSize fails to setup two-pass
-        command.arg("-c:v").arg("libx264"); // Common video codec
+        command.arg("-c:v").arg(encoder.encoder_name());
 
+        // CRF values below are on the x264 scale; crf_args translates them for the chosen codec
         match settings.preset_type.as_str() {
             "quality" => {
                 let crf = match settings.preset.as_str() { // preset here is "small", "balanced", "high"
-                    "small" => "28", "balanced" => "23", "high" => "18", _ => "23",
+                    "small" => 28, "balanced" => 23, "high" => 18, _ => 23,
                 };
-                command.arg("-crf").arg(if settings.custom_settings { settings.crf_value.to_string() } else { crf.to_string() });
-                command.arg("-preset").arg(match settings.preset.as_str() {
+                command.args(encoder.crf_args(if settings.custom_settings { settings.crf_value } else { crf }));
+                command.args(encoder.speed_args(match settings.preset.as_str() {
                     "small" => "fast", "balanced" => "medium", "high" => "slow", _ => "medium",
-                });
+                }));
             }
             "vbr" => {
-                command.arg("-crf").arg(settings.crf_value.to_string());
-                command.arg("-preset").arg("medium"); // Default, could be made customizable
-                println!("VBR Mode: CRF {}", settings.crf_value);
+                command.args(encoder.crf_args(settings.crf_value));
+                command.args(encoder.speed_args("medium")); // Default, could be made customizable
+                println!("[{}] VBR Mode: CRF {} (x264 scale)", task_id, settings.crf_value);
             }
             "cbr" => {
                 if let Some(br_str) = &settings.target_bitrate {
@@ -676,49 +536,53 @@ async fn compress_video(
                             let k_br = format!("{}k", br_val);
                             command.arg("-b:v").arg(&k_br).arg("-minrate").arg(&k_br).arg("-maxrate").arg(&k_br)
                                    .arg("-bufsize").arg(format!("{}k", br_val * 2));
-                            println!("CBR Mode: Bitrate {}k", br_val);
-                        } else { command.arg("-crf").arg(settings.crf_value.to_string()); /* fallback */ }
-                    } else { command.arg("-crf").arg(settings.crf_value.to_string()); /* fallback */ }
-                } else { command.arg("-crf").arg(settings.crf_value.to_string()); /* fallback */ }
+                            println!("[{}] CBR Mode: Bitrate {}k", task_id, br_val);
+                        } else { command.args(encoder.crf_args(settings.crf_value)); /* fallback */ }
+                    } else { command.args(encoder.crf_args(settings.crf_value)); /* fallback */ }
+                } else { command.args(encoder.crf_args(settings.crf_value)); /* fallback */ }
             }
             "scale" => {
-                if let Some(p_str) = &settings.scale_percentage {
-                    if let Ok(p) = p_str.parse::<f32>() {
-                        if p > 0.0 && p <= 100.0 {
-                            // Override any previous scale_filter for "scale" preset type
-                            scale_filter = Some(format!("scale=iw*{}:ih*{}", p / 100.0, p / 100.0));
-                            println!("Scale Mode: Scaling to {}%", p);
-                        }
-                    }
-                }
-                command.arg("-crf").arg(if settings.custom_settings && settings.preset_type != "cbr" { settings.crf_value.to_string() } else { "23".to_string() });
-                command.arg("-preset").arg("medium");
+                // The size itself comes from the resolution plan above
+                command.args(encoder.crf_args(if settings.custom_settings && settings.preset_type != "cbr" { settings.crf_value } else { 23 }));
+                command.args(encoder.speed_args("medium"));
+            }
+            "perceptual" => {
+                let crf = search_quality_crf(&app_handle, &task_id, &input_path, &trim, encoder, &filter_chain, &settings)?;
+                command.args(encoder.crf_args(crf));
+                command.args(encoder.speed_args("medium"));
+                progress = ProgressSpan::after_quality_search(duration_secs);
             }
             // targetSize is handled in the more complex block below, this is a fallback.
             "targetSize" => {
-                println!("TargetSize selected, but two-pass logic not initiated or failed. Using fallback CRF.");
-                command.arg("-crf").arg("23").arg("-preset").arg("medium");
+                println!("[{}] TargetSize selected, but two-pass logic not initiated or failed. Using fallback CRF.", task_id);
+                command.args(encoder.crf_args(23));
+                command.args(encoder.speed_args("medium"));
             }
             _ => { // Fallback for unknown preset_type
-                println!("Unknown preset type: '{}'. Using CRF 23.", settings.preset_type);
-                command.arg("-crf").arg("23").arg("-preset").arg("medium");
+                println!("[{}] Unknown preset type: '{}'. Using CRF 23.", task_id, settings.preset_type);
+                command.args(encoder.crf_args(23));
+                command.args(encoder.speed_args("medium"));
             }
         }
 
-        // Apply scale filter if defined from custom or quality presets
-        if let Some(sf) = &scale_filter {
-            command.arg("-vf").arg(sf);
-        }
+        // Apply frame rate and scale filters if the settings change either
+        add_video_filters(&mut command, &trim, &video_filters, has_audio);
 
         // Audio settings for single-pass modes
         let audio_bitrate_str = match settings.audio_quality.as_str() {
             "low" => "96k", "medium" => "128k", "high" => "192k", _ => "128k",
         };
-        command.arg("-c:a").arg("aac").arg("-b:a").arg(audio_bitrate_str);
+        command.arg("-c:a").arg(audio_encoder).arg("-b:a").arg(audio_bitrate_str);
+        command.args(encoder.compatibility_args(container));
+        command.args(container.muxer_args());
         command.arg("-y").arg(&output_path);
 
-        println!("Executing single-pass/standard FFmpeg command: {:?}", command);
-        return execute_ffmpeg_command(command, app_handle, task_id_for_progress, original_size, output_path).await;
+        println!("[{}] Executing single-pass/standard FFmpeg command: {:?}", task_id, command);
+        let result = execute_ffmpeg_command(command, app_handle.clone(), task_id.clone(), original_size, output_path, progress)?;
+        if settings.verify_quality {
+            return attach_quality_report(&app_handle, &task_id, &input_path, &trim, &source_filter, result);
+        }
+        return Ok(result);
     }
     // --- Target Size Two-Pass Encoding Logic ---
     // This 'else' corresponds to `if is_two_pass` (i.e. settings.preset_type == "targetSize")
@@ -726,414 +590,934 @@ async fn compress_video(
 
     // Ensure this section is only for targetSize
     if settings.preset_type == "targetSize" {
-        println!("Target Size Mode selected. Initiating two-pass encoding.");
+        println!("[{}] Target Size Mode selected. Initiating two-pass encoding.", task_id);
+        if !codec.supports_two_pass() {
+            return Err(CompressError::invalid_settings("codec", format!(
+                "target size mode needs a two-pass encoder, {} does not support it", codec.encoder_name()
+            )));
+        }
         if let Some(size_str) = &settings.target_size_mb {
             if let Ok(target_size_mb_val) = size_str.parse::<f64>() {
                 if target_size_mb_val <= 0.0 {
-                    return Err(format!("Invalid target size: {}MB. Must be > 0.", target_size_mb_val));
+                    return Err(CompressError::invalid_settings("targetSizeMB", format!("{}MB must be greater than 0", target_size_mb_val)));
                 }
 
-                emit_progress(&app_handle, &task_id_for_progress, 2.0); // Initial progress
-                let duration_secs = get_video_duration(&input_path, &app_handle, &task_id_for_progress).await?;
-                emit_progress(&app_handle, &task_id_for_progress, 10.0); // Progress after getting duration
+                let duration_secs = duration_secs
+                    .ok_or_else(|| CompressError::ProbeFailed {
+                        path: input_path.clone(),
+                        reason: "could not determine video duration for target size encoding".to_string(),
+                    })?;
 
-                if duration_secs <= 0.0 {
-                    return Err("Could not determine video duration for target size encoding.".to_string());
-                }
+                let plan = target_size::plan(target_size_mb_val, duration_secs, frame_rate.output_fps(source), &media, &settings.audio_quality, container)?;
+                println!("[{}] Target Size: Duration {:.2}s, {} bytes, Video Bitrate: {}bps, Audio Bitrate: {}",
+                         task_id, duration_secs, plan.target_bytes, plan.video_bitrate_bps,
+                         plan.audio_bitrate_kbps.map_or("none".to_string(), |kbps| format!("{}kbps", kbps)));
 
-                let audio_br_kbps = match settings.audio_quality.as_str() {
-                    "low" => 96, "medium" => 128, "high" => 192, _ => 128,
-                };
-
-                let target_total_bits = target_size_mb_val * 1024.0 * 1024.0 * 8.0;
-                let audio_total_bits = audio_br_kbps as f64 * 1000.0 * duration_secs;
-                let target_video_bits = target_total_bits - audio_total_bits;
-
-                if target_video_bits <= 0.0 {
-                    return Err(format!(
-                        "Target size ({}MB) is too small for the audio track ({}kbps) and video duration ({}s). Try a larger target size or lower audio quality.",
-                        target_size_mb_val, audio_br_kbps, duration_secs
-                    ));
-                }
-
-                let calculated_video_bitrate_kbps = (target_video_bits / duration_secs) / 1000.0;
-                if calculated_video_bitrate_kbps < 10.0 { // Heuristic: if video bitrate is extremely low, it's probably not feasible
-                     return Err(format!(
-                        "Calculated video bitrate ({:.2}kbps) is too low for target size {}MB. This may result in very poor quality or errors. Increase target size.",
-                        calculated_video_bitrate_kbps, target_size_mb_val
-                    ));
-                }
-                let calculated_video_bitrate_str = format!("{:.0}k", calculated_video_bitrate_kbps.max(10.0)); // Ensure at least 10k
-
-                println!("Target Size: Duration {:.2}s, Target Video Bitrate: {}, Audio Bitrate: {}kbps",
-                         duration_secs, calculated_video_bitrate_str, audio_br_kbps);
+                // Per-task pass log, so parallel two-pass jobs don't overwrite each other's ffmpeg2pass-0.log
+                let passlog_prefix = two_pass_log_prefix(&task_id);
 
                 // --- First Pass ---
-                let mut cmd_pass1 = Command::new("ffmpeg");
-                cmd_pass1.arg("-y").arg("-i").arg(&input_path);
-                if let Some(sf) = &scale_filter { cmd_pass1.arg("-vf").arg(sf); } // Apply scaling in pass 1 if set
-                cmd_pass1.arg("-c:v").arg("libx264")
-                    .arg("-b:v").arg(&calculated_video_bitrate_str)
-                    .arg("-preset").arg("medium") // Consider a faster preset for pass 1, e.g., "fast" or "medium"
-                    .arg("-pass").arg("1")
+                let mut cmd_pass1 = Command::new(&ffmpeg.ffmpeg);
+                cmd_pass1.arg("-y").args(trim.input_args()).arg("-i").arg(&input_path);
+                add_video_filters(&mut cmd_pass1, &trim, &video_filters, false); // Same filters in both passes, so the stats match
+                cmd_pass1.arg("-c:v").arg(codec.encoder_name())
+                    .arg("-b:v").arg(plan.video_bitrate_arg())
+                    .args(codec.speed_args("medium")) // Consider a faster preset for pass 1, e.g., "fast" or "medium"
+                    .args(codec.two_pass_args(1, &passlog_prefix))
                     .arg("-an") // No audio for the first pass
                     .arg("-f").arg("null");
                 if cfg!(windows) { cmd_pass1.arg("NUL"); } else { cmd_pass1.arg("/dev/null"); }
 
-                println!("Executing FFmpeg Pass 1: {:?}", cmd_pass1);
-                // For pass 1, we don't need to parse its progress in the same way, but we need to wait for it.
-                // We can emit a fixed progress update or try to parse its simple progress if available.
-                // For simplicity, just wait for it to complete.
-                let output_pass1 = cmd_pass1.output().map_err(|e| format!("FFmpeg Pass 1 command failed to start: {}", e))?;
-                if !output_pass1.status.success() {
-                    let stderr = String::from_utf8_lossy(&output_pass1.stderr);
-                    return Err(format!("FFmpeg Pass 1 execution failed: {}", stderr));
+                println!("[{}] Executing FFmpeg Pass 1: {:?}", task_id, cmd_pass1);
+                // Pass 1 goes through the job registry too, so a cancel can kill it
+                if let Err(e) = run_ffmpeg_pass(cmd_pass1, &app_handle, &task_id, ProgressSpan::two_pass(duration_secs, 1)) {
+                    remove_two_pass_logs(&passlog_prefix);
+                    return Err(e);
                 }
-                println!("FFmpeg Pass 1 successful.");
-                emit_progress(&app_handle, &task_id_for_progress, 50.0); // Progress after pass 1
-
-
-                // --- Second Pass (command is already initialized as `command`) ---
-                command.arg("-c:v").arg("libx264")
-                       .arg("-b:v").arg(&calculated_video_bitrate_str)
-                       .arg("-preset").arg("medium") // Use a preset that balances quality and speed for pass 2
-                       .arg("-pass").arg("2");
-
-                if let Some(sf) = &scale_filter { command.arg("-vf").arg(sf); } // Apply scaling in pass 2 as well
+                println!("[{}] FFmpeg Pass 1 successful.", task_id);
+
+                // --- Second Pass ---
+                // Rebuilt per attempt: an output over the target is encoded again from the same pass 1
+                // stats with a lower video bitrate.
+                let build_pass2 = |plan: &target_size::TargetSizePlan| {
+                    let mut command = Command::new(&ffmpeg.ffmpeg);
+                    command.args(trim.input_args()).arg("-i").arg(&input_path);
+                    command.arg("-c:v").arg(codec.encoder_name())
+                           .arg("-b:v").arg(plan.video_bitrate_arg())
+                           .args(codec.speed_args("medium")) // Use a preset that balances quality and speed for pass 2
+                           .args(codec.two_pass_args(2, &passlog_prefix));
+                    add_video_filters(&mut command, &trim, &video_filters, plan.audio_bitrate_kbps.is_some());
+                    match plan.audio_bitrate_kbps {
+                        Some(kbps) => { command.arg("-c:a").arg(audio_encoder).arg("-b:a").arg(format!("{}k", kbps)); }
+                        None => { command.arg("-an"); }
+                    }
+                    command.args(codec.compatibility_args(container));
+                    command.args(container.muxer_args());
+                    command.arg("-y").arg(&output_path);
+                    command
+                };
 
-                let audio_bitrate_str_pass2 = match settings.audio_quality.as_str() {
-                     "low" => "96k", "medium" => "128k", "high" => "192k", _ => "128k",
+                let mut plan = plan;
+                let mut attempt = 1;
+                let result = loop {
+                    let command = build_pass2(&plan);
+                    println!("[{}] Executing FFmpeg Pass 2 (attempt {}): {:?}", task_id, attempt, command);
+                    let result = match execute_ffmpeg_command(command, app_handle.clone(), task_id.clone(), original_size, output_path.clone(), ProgressSpan::two_pass(duration_secs, 2)) {
+                        Ok(result) => result,
+                        Err(e) => break Err(e),
+                    };
+                    if result.compressed_size <= plan.target_bytes {
+                        break Ok(result);
+                    }
+                    let corrected = if attempt >= MAX_TARGET_SIZE_ATTEMPTS {
+                        Err(CompressError::invalid_settings("targetSizeMB", format!(
+                            "output is still {} bytes after {} attempts, over the {} byte target",
+                            result.compressed_size, attempt, plan.target_bytes
+                        )))
+                    } else {
+                        plan.corrected(result.compressed_size)
+                    };
+                    plan = match corrected {
+                        Ok(corrected) => corrected,
+                        Err(e) => {
+                            // An output over the target is of no use, so it isn't left behind
+                            if let Err(remove_error) = fs::remove_file(&result.output_path) {
+                                eprintln!("[{}] Failed to remove oversized output {}: {}", task_id, result.output_path, remove_error);
+                            }
+                            break Err(e);
+                        }
+                    };
+                    println!("[{}] Output {} bytes exceeds target {} bytes, retrying at {}bps",
+                             task_id, result.compressed_size, plan.target_bytes, plan.video_bitrate_bps);
+                    attempt += 1;
                 };
-                command.arg("-c:a").arg("aac").arg("-b:a").arg(audio_bitrate_str_pass2);
-                command.arg("-y").arg(&output_path);
-
-                println!("Executing FFmpeg Pass 2: {:?}", command);
-                // The progress for the second pass will be from 50% to 100%.
-                // We need a way for execute_ffmpeg_command to know this offset.
-                // Modifying execute_ffmpeg_command to accept a progress_offset or base_progress.
-                // For now, let's assume execute_ffmpeg_command's progress is 0-100 for its run,
-                // and we'll adjust it when emitting.
-                // Or, we can create a specialized version or pass a closure for progress reporting.
-
-                // Re-using execute_ffmpeg_command. We need to adjust its 0-100% progress to 50-100%.
-                // This can be done by modifying how emit_progress is called within or after.
-                // Let's modify `execute_ffmpeg_command` to take a base_progress.
-                // For now, I will call it and then if successful, assume it reached 100% (of its own run).
-                // The progress updates from execute_ffmpeg_command will be 0-100.
-                // We need to adjust the ProgressPayload inside emit_progress when called from execute_ffmpeg_command
-                // if we are in a two-pass context.
-                // A simpler way: `execute_ffmpeg_command` reports 0-100. The `targetSize` block
-                // is responsible for emitting progress updates *around* calls to it.
-                // So, `emit_progress(&app_handle, &task_id_for_progress, 50.0 + (pass2_progress / 2.0));`
-
-                // To achieve the 50-100% range for pass 2 progress:
-                // We need to intercept the progress reporting from execute_ffmpeg_command for pass 2.
-                // This is getting complex. Let's simplify: the progress for pass 2 will also be 0-100%,
-                // but the UI should understand that this is the second part of a two-part process.
-                // Or, the `task_id` could be different, e.g., `task_id_pass1`, `task_id_pass2`.
-                // For now, `execute_ffmpeg_command` will emit progress from 0-100 for the second pass.
-                // The UI will see progress jump to 50%, then go from 0% to 100% again for the second pass, which is not ideal.
-
-                // A better approach for execute_ffmpeg_command: add `progress_offset: f32` and `progress_scale: f32`
-                // Then `current_progress = progress_offset + (current_progress_of_this_pass * progress_scale)`
-                // For pass 1 (if it had progress): offset 0, scale 0.5
-                // For pass 2: offset 50, scale 0.5
-                // This change is for execute_ffmpeg_command and emit_progress.
-
-                // For now, let's proceed without modifying execute_ffmpeg_command's internal progress reporting scaling.
-                // The UI will see 0-100% for pass 2. This can be refined later.
-                return execute_ffmpeg_command(command, app_handle, task_id_for_progress, original_size, output_path).await;
+                remove_two_pass_logs(&passlog_prefix);
+                if settings.verify_quality {
+                    return attach_quality_report(&app_handle, &task_id, &input_path, &trim, &source_filter, result?);
+                }
+                return result;
 
             } else { // Failed to parse target_size_mb_val
-                return Err(format!("Could not parse target size value: '{}'", size_str));
+                return Err(CompressError::invalid_settings("targetSizeMB", format!("could not parse '{}'", size_str)));
             }
         } else { // target_size_mb is None
-            return Err("Target size (MB) not provided for Target Size mode.".to_string());
+            return Err(CompressError::invalid_settings("targetSizeMB", "not provided for Target Size mode"));
         }
     } // End of targetSize specific logic
 
     // Fallback if somehow reached here without returning (should not happen with current logic)
-    Err("Compression logic error: No specific preset path taken.".to_string())
+    Err(CompressError::invalid_settings("presetType", format!("no compression path for '{}'", settings.preset_type)))
 }
 
 
+// Finds the CRF for the "perceptual" preset by encoding and scoring samples of the input.
+// Returns the CRF on the x264 scale, like the other presets use.
+fn search_quality_crf(
+    app_handle: &tauri::AppHandle,
+    task_id: &str,
+    input_path: &str,
+    trim: &trim::TrimPlan,
+    encoder: VideoEncoder,
+    filter_chain: &FilterChain,
+    settings: &CompressionSettings,
+) -> Result<u8, CompressError> {
+    let metric = settings.quality_metric;
+    let target = metric.validate_target(settings.quality_target
+        .ok_or_else(|| CompressError::invalid_settings("qualityTarget", "not provided for Perceptual mode"))?)?;
+    let ffmpeg = ffmpeg_locator::locate()?;
+    if metric == QualityMetric::Vmaf && !ffmpeg.has_library("libvmaf") {
+        return Err(CompressError::invalid_settings("qualityMetric", "this ffmpeg build has no libvmaf, choose SSIM instead"));
+    }
+    let duration_secs = trim.duration()
+        .ok_or_else(|| CompressError::ProbeFailed {
+            path: input_path.to_string(),
+            reason: "could not determine video duration for quality sampling".to_string(),
+        })?;
+    // Samples are spread over the trimmed output, then encoded straight from the source
+    let samples: Vec<quality_search::SampleRange> = quality_search::sample_ranges(duration_secs).into_iter()
+        .map(|sample| {
+            let (start_secs, duration_secs) = trim.source_span(sample.start_secs, sample.duration_secs);
+            quality_search::SampleRange { start_secs, duration_secs }
+        })
+        .collect();
+    // Two runs (encode, then score) per sample per search step
+    let runs = quality_search::MAX_SEARCH_STEPS * samples.len() * 2;
+
+    let choice = quality_search::search_crf(target, |crf, step| {
+        // The worst sample decides, so high-motion stretches aren't starved to spare static ones
+        let mut worst = f64::MAX;
+        for (i, sample) in samples.iter().enumerate() {
+            let run = (step * samples.len() + i) * 2;
+            let sample_path = task_temp_path(task_id, &format!("sample{}", i)).with_extension("mkv");
+
+            let mut encode = Command::new(&ffmpeg.ffmpeg);
+            encode.arg("-y")
+                .args(encoder.input_args())
+                .arg("-ss").arg(sample.start_secs.to_string())
+                .arg("-t").arg(sample.duration_secs.to_string())
+                .arg("-i").arg(input_path);
+            if let Some(vf) = encoder.video_filter(filter_chain).to_vf() { encode.arg("-vf").arg(vf); }
+            encode.arg("-c:v").arg(encoder.encoder_name())
+                .args(encoder.crf_args(crf))
+                .args(encoder.speed_args("medium"))
+                .arg("-an")
+                .arg("-f").arg("matroska") // Holds every codec we encode
+                .arg(&sample_path);
+            let encoded = run_ffmpeg_pass(encode, app_handle, task_id, ProgressSpan::quality_search(sample.duration_secs, run, runs));
+
+            let measured = encoded.and_then(|_| {
+                let mut compare = Command::new(&ffmpeg.ffmpeg);
+                compare.arg("-i").arg(&sample_path)
+                    .arg("-ss").arg(sample.start_secs.to_string())
+                    .arg("-t").arg(sample.duration_secs.to_string())
+                    .arg("-i").arg(input_path)
+                    .arg("-lavfi").arg(quality_search::compare_graph(metric, filter_chain))
+                    .arg("-f").arg("null").arg("-");
+                run_ffmpeg_pass(compare, app_handle, task_id, ProgressSpan::quality_search(sample.duration_secs, run + 1, runs))
+            });
+            let _ = fs::remove_file(&sample_path);
+
+            let stderr_tail = measured.map_err(|e| metric.measure_error(e))?;
+            let score = metric.parse_score(&stderr_tail)
+                .ok_or_else(|| CompressError::EncodeFailed {
+                    exit_code: Some(0),
+                    stderr_tail: format!("no {:?} score in ffmpeg output:\n{}", metric, Vec::from(stderr_tail).join("\n")),
+                })?;
+            worst = worst.min(score);
+        }
+        println!("[{}] Quality search: CRF {} scored {:?} {:.4} (target {})", task_id, crf, metric, worst, target);
+        Ok(worst)
+    })?;
+
+    if !choice.met_target {
+        println!("[{}] No CRF reached {:?} {}, using CRF {} ({:.4})", task_id, metric, target, choice.crf, choice.score);
+    }
+    println!("[{}] Quality search picked CRF {} ({:?} {:.4})", task_id, choice.crf, metric, choice.score);
+    Ok(choice.crf)
+}
+
+// Scores a finished video encode against its source and adds the report to the result.
+// Only a cancel fails the task; any other problem just leaves the report out. The encode's trim
+// and the frame rate conversion and crop are repeated on the source so frames line up.
+fn attach_quality_report(
+    app_handle: &tauri::AppHandle,
+    task_id: &str,
+    input_path: &str,
+    trim: &trim::TrimPlan,
+    source_filter: &FilterChain,
+    mut result: CompressionResult,
+) -> Result<CompressionResult, CompressError> {
+    let ffmpeg = ffmpeg_locator::locate()?;
+    let files = quality_report::StatsFiles {
+        psnr: task_temp_path(task_id, "psnr").with_extension("log"),
+        ssim: task_temp_path(task_id, "ssim").with_extension("log"),
+        vmaf: task_temp_path(task_id, "vmaf").with_extension("json"),
+    };
+    let output_size = result.width.zip(result.height);
+    let duration_secs = trim.duration();
+    let source_graph = trim.segment_graph("1:v:0", None, "src");
+    let compare = |with_vmaf: bool| {
+        let mut command = Command::new(&ffmpeg.ffmpeg);
+        command.arg("-i").arg(&result.output_path)
+            .args(trim.input_args())
+            .arg("-i").arg(input_path)
+            .arg("-lavfi").arg(quality_report::compare_graph(&files, source_graph.clone(), source_filter, output_size, with_vmaf))
+            .arg("-f").arg("null").arg("-");
+        println!("[{}] Verifying output quality: {:?}", task_id, command);
+        // Progress stays at 100% while the stats line shows how the comparison is getting on
+        run_ffmpeg_pass(command, app_handle, task_id, ProgressSpan { duration_secs, offset: 100.0, scale: 0.0, pass: None })
+    };
+
+    // libvmaf is an optional ffmpeg component, so fall back to PSNR and SSIM without it. Builds
+    // that don't list their libraries are only found out when the filter is missing.
+    let mut with_vmaf = ffmpeg.has_library("libvmaf");
+    if !with_vmaf {
+        println!("[{}] ffmpeg has no libvmaf, verifying with PSNR and SSIM only", task_id);
+    }
+    let mut outcome = compare(with_vmaf);
+    if let Err(CompressError::EncodeFailed { stderr_tail, .. }) = &outcome {
+        if with_vmaf && stderr_tail.contains("No such filter: 'libvmaf'") {
+            println!("[{}] ffmpeg has no libvmaf, verifying with PSNR and SSIM only", task_id);
+            with_vmaf = false;
+            outcome = compare(false);
+        }
+    }
+
+    match outcome {
+        Ok(_) => result.quality = Some(quality_report::read_report(&files, with_vmaf)),
+        Err(e @ CompressError::Cancelled { .. }) => {
+            files.remove();
+            return Err(e);
+        }
+        Err(e) => eprintln!("[{}] Quality verification failed: {}", task_id, e),
+    }
+    files.remove();
+    Ok(result)
+}
+
+// Adds an encode's video filters and stream maps: a plain -vf, or a -filter_complex that first
+// cuts and joins the keep-ranges. Both take the first audio stream rather than leaving the pick to
+// ffmpeg, which prefers the one with most channels, so the target size audio budget is planned for
+// the stream that is encoded. `with_audio` is false for commands that drop the audio with -an.
+fn add_video_filters(command: &mut Command, trim: &trim::TrimPlan, filter_chain: &FilterChain, with_audio: bool) {
+    let Some(mut graph) = trim.segment_graph("0:v:0", with_audio.then_some(("0:a:0", "aout")), "vcat") else {
+        if let Some(vf) = filter_chain.to_vf() {
+            command.arg("-vf").arg(vf);
+        }
+        // 0:V skips cover art, like probe's primary video
+        command.arg("-map").arg("0:V:0");
+        if with_audio {
+            command.arg("-map").arg("0:a:0");
+        }
+        return;
+    };
+    graph.chain(&["vcat"], filter_chain, &["vout"]);
+    command.arg("-filter_complex").arg(graph.to_string()).arg("-map").arg("[vout]");
+    if with_audio {
+        command.arg("-map").arg("[aout]");
+    }
+}
+
 // Helper function to execute ffmpeg command and handle progress
-async fn execute_ffmpeg_command(
-    mut command: Command,
+fn execute_ffmpeg_command(
+    command: Command,
     app_handle: tauri::AppHandle,
     task_id: String,
     original_size: u64,
     output_path_str: String, // Pass as String to avoid lifetime issues with &Path
-    // Add these for more flexible progress reporting in two-pass scenarios
-    progress_offset: f32,
-    progress_scale: f32,
-) -> Result<String, String> {
+    progress: ProgressSpan,
+) -> Result<CompressionResult, CompressError> {
+    let ffmpeg_args: Vec<String> = command.get_args().map(|arg| arg.to_string_lossy().into_owned()).collect();
+    let (job, stdout, stderr) = spawn_tracked_ffmpeg(command, &app_handle, &task_id)?;
+    let stderr_tail = read_ffmpeg_output(stdout, stderr, &app_handle, &task_id, progress);
+
+    // The -progress pipe hit EOF, so ffmpeg has exited (or was killed) and waiting won't block a cancel
+    let status = job.wait().map_err(|e| CompressError::io("Failed to wait for FFmpeg command", e))?;
+    if job.is_cancelled() {
+        return Err(handle_cancelled_job(&app_handle, &task_id, &job));
+    }
+
+    if status.success() {
+        progress.emit(&app_handle, &task_id, 100.0, EncodeStats::default());
+
+        let result = build_compression_result(&task_id, &job, output_path_str, original_size, ffmpeg_args)?;
+        println!("[{}] Compression successful! Output: {} ({} -> {} bytes, ratio {:.3}, {:.1}s)",
+                 task_id, result.output_path, result.original_size, result.compressed_size, result.ratio, result.elapsed_secs);
+        Ok(result)
+    } else {
+        let error = CompressError::EncodeFailed { exit_code: status.code(), stderr_tail: Vec::from(stderr_tail).join("\n") };
+        println!("[{}] Error: {}", task_id, error);
+        Err(error)
+    }
+}
+
+// Collects sizes and timing of a finished job, and probes the output for its stream details.
+// A failed probe only leaves those details empty; the encode itself succeeded.
+fn build_compression_result(
+    task_id: &str,
+    job: &JobHandle,
+    output_path: String,
+    original_size: u64,
+    ffmpeg_args: Vec<String>,
+) -> Result<CompressionResult, CompressError> {
+    let compressed_size = fs::metadata(&output_path)
+        .map_err(|e| CompressError::io("Could not get output file metadata", e))?
+        .len();
+    let elapsed_secs = job.elapsed().as_secs_f64();
+    let output_info = probe::probe(&output_path)
+        .map_err(|e| eprintln!("[{}] Could not probe output {}: {}", task_id, output_path, e))
+        .ok();
+    let duration_secs = output_info.as_ref().and_then(|info| info.duration_secs);
+    let video = output_info.as_ref().and_then(|info| info.primary_video());
+
+    Ok(CompressionResult {
+        original_size,
+        compressed_size,
+        ratio: if original_size > 0 { compressed_size as f64 / original_size as f64 } else { 1.0 },
+        elapsed_secs,
+        speed: duration_secs.filter(|_| elapsed_secs > 0.0).map(|d| d / elapsed_secs),
+        bitrate: output_info.as_ref().and_then(|info| info.bit_rate)
+            .or_else(|| duration_secs.map(|d| (compressed_size as f64 * 8.0 / d) as u64)),
+        width: video.map(|v| v.width),
+        height: video.map(|v| v.height),
+        codec: video.map(|v| v.codec.clone())
+            .or_else(|| output_info.as_ref().and_then(|info| info.audio_streams.first()).map(|a| a.codec.clone())),
+        ffmpeg_args,
+        output_path,
+        quality: None,
+    })
+}
 
-    let mut child = command.stdout(Stdio::piped())
+// Spawns ffmpeg and attaches the child to the task's registry entry so `cancel_task` can kill it.
+// Progress is requested as key=value blocks on stdout; stderr keeps the log for error messages.
+fn spawn_tracked_ffmpeg(
+    command: Command,
+    app_handle: &tauri::AppHandle,
+    task_id: &str,
+) -> Result<(Arc<JobHandle>, ChildStdout, ChildStderr), CompressError> {
+    let job = app_handle.state::<JobRegistry>().get(task_id)
+        .ok_or_else(|| CompressError::TaskNotFound { task_id: task_id.to_string() })?;
+    if job.is_cancelled() {
+        return Err(handle_cancelled_job(app_handle, task_id, &job));
+    }
+
+    // -progress is a global option, so it has to come before the inputs
+    let mut tracked = Command::new(command.get_program());
+    tracked.arg("-progress").arg("pipe:1").arg("-nostats").args(command.get_args());
+    let mut child = tracked.stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn()
-        .map_err(|e| format!("Failed to execute FFmpeg command: {}", e))?;
-
-    let stderr = child.stderr.take().ok_or_else(|| "Could not capture ffmpeg stderr.".to_string())?;
-    let reader = BufReader::new(stderr);
-
-    let re_duration = Regex::new(r"Duration: (\d{2}):(\d{2}):(\d{2})\.(\d{2})").unwrap();
-    let re_time = Regex::new(r"time=(\d{2}):(\d{2}):(\d{2})\.(\d{2})").unwrap();
-    let mut total_duration_secs: Option<f32> = None;
-
-    for line_result in reader.lines() {
-        match line_result {
-            Ok(line) => {
-                // Slightly reduce verbosity of FFMPEG_STDERR unless in debug build
-                #[cfg(debug_assertions)]
-                println!("FFMPEG_STDERR: {}", line);
-
-                if total_duration_secs.is_none() {
-                    if let Some(caps) = re_duration.captures(&line) {
-                        let h = caps.get(1).unwrap().as_str().parse::<f32>().unwrap_or(0.0);
-                        let m = caps.get(2).unwrap().as_str().parse::<f32>().unwrap_or(0.0);
-                        let s = caps.get(3).unwrap().as_str().parse::<f32>().unwrap_or(0.0);
-                        let ss = caps.get(4).unwrap().as_str().parse::<f32>().unwrap_or(0.0) / 100.0;
-                        total_duration_secs = Some(h * 3600.0 + m * 60.0 + s + ss);
-                        // println!("Total duration from current pass: {:?} seconds", total_duration_secs);
-                    }
-                }
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => CompressError::FfmpegNotFound { program: "ffmpeg".to_string() },
+            _ => CompressError::io("Failed to execute FFmpeg command", e),
+        })?;
+    let stdout = child.stdout.take().ok_or_else(|| CompressError::io("Could not capture ffmpeg stdout", "pipe missing"))?;
+    let stderr = child.stderr.take().ok_or_else(|| CompressError::io("Could not capture ffmpeg stderr", "pipe missing"))?;
+    job.attach(child);
+    Ok((job, stdout, stderr))
+}
 
-                if let Some(duration_secs) = total_duration_secs {
-                    if duration_secs > 0.0 {
-                        if let Some(caps) = re_time.captures(&line) {
-                            let h = caps.get(1).unwrap().as_str().parse::<f32>().unwrap_or(0.0);
-                            let m = caps.get(2).unwrap().as_str().parse::<f32>().unwrap_or(0.0);
-                            let s = caps.get(3).unwrap().as_str().parse::<f32>().unwrap_or(0.0);
-                            let ss = caps.get(4).unwrap().as_str().parse::<f32>().unwrap_or(0.0) / 100.0;
-                            let current_time_secs = h * 3600.0 + m * 60.0 + s + ss;
-
-                            let pass_progress = (current_time_secs / duration_secs) * 100.0;
-                            let overall_progress = progress_offset + (pass_progress * progress_scale);
-                            emit_progress(&app_handle, &task_id, overall_progress.min(100.0));
-                        }
-                    }
+// Runs an intermediate ffmpeg pass (e.g. pass 1 of a two-pass encode, or a quality measurement)
+// that produces no output file. Returns the last stderr lines for callers that read results from the log.
+fn run_ffmpeg_pass(command: Command, app_handle: &tauri::AppHandle, task_id: &str, progress: ProgressSpan) -> Result<VecDeque<String>, CompressError> {
+    let (job, stdout, stderr) = spawn_tracked_ffmpeg(command, app_handle, task_id)?;
+    let stderr_tail = read_ffmpeg_output(stdout, stderr, app_handle, task_id, progress);
+
+    let status = job.wait().map_err(|e| CompressError::io("Failed to wait for FFmpeg command", e))?;
+    if job.is_cancelled() {
+        return Err(handle_cancelled_job(app_handle, task_id, &job));
+    }
+    if !status.success() {
+        return Err(CompressError::EncodeFailed { exit_code: status.code(), stderr_tail: Vec::from(stderr_tail).join("\n") });
+    }
+    progress.emit(app_handle, task_id, 100.0, EncodeStats::default());
+    Ok(stderr_tail)
+}
+
+// Reads ffmpeg's -progress output until it closes, reporting progress within the given span.
+// stderr is drained on a separate thread so a chatty log can't fill its pipe and stall ffmpeg.
+// Returns the last stderr lines for error messages.
+fn read_ffmpeg_output(
+    stdout: ChildStdout,
+    stderr: ChildStderr,
+    app_handle: &tauri::AppHandle,
+    task_id: &str,
+    progress: ProgressSpan,
+) -> VecDeque<String> {
+    #[cfg(debug_assertions)]
+    let log_task_id = task_id.to_string();
+    let stderr_reader = std::thread::spawn(move || {
+        let mut stderr_tail: VecDeque<String> = VecDeque::with_capacity(STDERR_TAIL_LINES);
+        for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+            // Slightly reduce verbosity of FFMPEG_STDERR unless in debug build
+            #[cfg(debug_assertions)]
+            println!("[{}] FFMPEG_STDERR: {}", log_task_id, line);
+            push_stderr_tail(&mut stderr_tail, &line);
+        }
+        stderr_tail
+    });
+
+    // Each report is a block of key=value lines terminated by "progress=continue" or "progress=end"
+    let mut stats = EncodeStats::default();
+    let mut out_time_secs: Option<f64> = None;
+    for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+        let Some((key, value)) = line.split_once('=') else { continue };
+        let value = value.trim();
+        match key.trim() {
+            "fps" => stats.fps = value.parse().ok(),
+            "bitrate" => stats.bitrate_kbps = value.trim_end_matches("kbits/s").parse().ok(),
+            "total_size" => stats.bytes_written = value.parse().ok(),
+            // Despite its name, out_time_ms is also in microseconds; out_time_us is the reliable key
+            "out_time_us" => out_time_secs = value.parse::<i64>().ok().filter(|us| *us >= 0).map(|us| us as f64 / 1_000_000.0),
+            "speed" => stats.speed = value.trim_end_matches('x').parse().ok(),
+            "progress" => {
+                if let (Some(duration_secs), Some(current_secs)) = (progress.duration_secs, out_time_secs) {
+                    stats.eta_secs = stats.speed.and_then(|speed| progress.eta_secs(current_secs, speed));
+                    let pass_progress = ((current_secs / duration_secs) * 100.0) as f32;
+                    progress.emit(app_handle, task_id, pass_progress.min(100.0), stats.clone());
                 }
             }
-            Err(e) => eprintln!("Error reading ffmpeg stderr line: {}", e),
+            _ => {}
         }
     }
 
-    let output_status = child.wait_with_output().map_err(|e| format!("Failed to wait for FFmpeg command: {}", e))?;
+    stderr_reader.join().unwrap_or_default()
+}
 
-    if output_status.status.success() {
-        // Ensure final progress (e.g. 100% for single pass, or 50% for pass 1, 100% for pass 2's contribution)
-        emit_progress(&app_handle, &task_id, progress_offset + (100.0 * progress_scale));
+// Per-task scratch file location in the temp dir rather than the working directory
+fn task_temp_path(task_id: &str, name: &str) -> std::path::PathBuf {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    task_id.hash(&mut hasher);
+    std::env::temp_dir().join(format!("pressvid-{}-{:016x}", name, hasher.finish()))
+}
 
-        let output_path = Path::new(&output_path_str);
-        let output_metadata = fs::metadata(output_path)
-            .map_err(|e| format!("Could not get output file metadata: {}", e))?;
-        let compressed_size = output_metadata.len();
-        // println!("Compressed file size: {} bytes", compressed_size);
+// Pass log location for a task's two-pass encode
+fn two_pass_log_prefix(task_id: &str) -> std::path::PathBuf {
+    task_temp_path(task_id, "2pass")
+}
 
-        let change_percentage = if original_size > 0 {
-            ((compressed_size as f64 - original_size as f64) / original_size as f64) * 100.0
-        } else {
-            0.0
-        };
-        let size_change_str = if change_percentage > 0.0 {
-            format!("increased by {:.2}%", change_percentage)
-        } else if change_percentage < 0.0 {
-            format!("reduced by {:.2}%", -change_percentage)
-        } else {
-            "remained the same".to_string()
-        };
+fn remove_two_pass_logs(prefix: &Path) {
+    // libx264 writes <prefix>-0.log plus a .mbtree sidecar, libx265 a .cutree one
+    for suffix in ["-0.log", "-0.log.mbtree", "-0.log.cutree", "-0.log.temp", "-0.log.mbtree.temp", "-0.log.cutree.temp"] {
+        let mut path = prefix.as_os_str().to_owned();
+        path.push(suffix);
+        let _ = fs::remove_file(path);
+    }
+}
 
-        let success_msg = format!(
-            "Video compression successful! Output: {} (Size: {} bytes, {} compared to original)",
-            output_path.display(), compressed_size, size_change_str
-        );
-        println!("{}", success_msg);
-        Ok(success_msg)
-    } else {
-        let stderr_output = String::from_utf8_lossy(&output_status.stderr);
-        let error_msg = format!("FFmpeg command execution failed: {}", stderr_output);
-        println!("Error: {}", error_msg);
-        Err(error_msg)
+fn push_stderr_tail(tail: &mut VecDeque<String>, line: &str) {
+    if tail.len() == STDERR_TAIL_LINES {
+        tail.pop_front();
     }
+    tail.push_back(line.to_string());
 }
 
-// Helper to emit progress (no change needed here for scaling, it's handled by the caller)
-fn emit_progress(app_handle: &tauri::AppHandle, task_id: &str, progress: f32) {
+// Cleans up after a cancelled job: removes the partial output and tells the frontend.
+// Returns the error the task should end with.
+fn handle_cancelled_job(app_handle: &tauri::AppHandle, task_id: &str, job: &JobHandle) -> CompressError {
+    match fs::remove_file(job.output_path()) {
+        Ok(()) => println!("[{}] Removed partial output: {}", task_id, job.output_path()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => eprintln!("[{}] Failed to remove partial output {}: {}", task_id, job.output_path(), e),
+    }
+    let payload = CancelledPayload { task_id: task_id.to_string() };
+    app_handle.emit("TASK_CANCELLED_EVENT", payload).unwrap_or_else(|e| eprintln!("Failed to emit cancel event: {}", e));
+    CompressError::Cancelled { task_id: task_id.to_string() }
+}
+
+#[tauri::command]
+fn cancel_task(app_handle: tauri::AppHandle, task_id: String) -> Result<(), CompressError> {
+    println!("Cancelling task: {}", task_id);
+    if app_handle.state::<JobRegistry>().cancel(&task_id) {
+        return Ok(());
+    }
+    // Not started yet: it only has to leave the queue
+    if queue::cancel_waiting(&app_handle, &task_id) {
+        let payload = CancelledPayload { task_id };
+        app_handle.emit("TASK_CANCELLED_EVENT", payload).unwrap_or_else(|e| eprintln!("Failed to emit cancel event: {}", e));
+        return Ok(());
+    }
+    Err(CompressError::TaskNotFound { task_id })
+}
+
+// Helper to emit progress; callers map per-pass progress onto the task's scale via ProgressSpan
+fn emit_progress(app_handle: &tauri::AppHandle, task_id: &str, progress: f32, pass: Option<u8>, stats: EncodeStats) {
     let payload = ProgressPayload {
         task_id: task_id.to_string(),
-        progress: progress.min(100.0).max(0.0),
+        progress: progress.clamp(0.0, 100.0),
+        pass,
+        stats,
     };
     app_handle.emit("PROGRESS_EVENT", payload).unwrap_or_else(|e| eprintln!("Failed to emit progress: {}", e));
 }
 
 
-// Helper function to get video duration using ffmpeg -i
-async fn get_video_duration(
-    input_path: &str,
-    app_handle: &tauri::AppHandle,
-    task_id: &str
-) -> Result<f32, String> {
-    // println!("Attempting to get video duration for: {}", input_path);
-    // emit_progress(app_handle, task_id, 5.0); // Progress for starting duration check
-
-    let mut cmd = Command::new("ffmpeg");
-    cmd.arg("-i").arg(input_path)
-       .arg("-f").arg("null") // Tell ffmpeg to not expect an output file for real processing
-       .arg("-"); // Read from stdin - not actually reading, but helps ffmpeg exit after info dump with some versions
-
-    let child = cmd.stdout(Stdio::null())
-                   .stderr(Stdio::piped())
-                   .spawn()
-                   .map_err(|e| format!("Failed to spawn ffmpeg for duration check: {}", e))?;
-
-    let stderr_capture = child.stderr.ok_or_else(|| "Could not capture stderr for duration check".to_string())?;
-    let reader = BufReader::new(stderr_capture);
-    let re_duration = Regex::new(r"Duration: (\d{2}):(\d{2}):(\d{2})\.(\d{2})").unwrap();
-    let mut duration_found_secs: Option<f32> = None;
-
-    // Limit how many lines we read to avoid hanging if ffmpeg behaves unexpectedly
-    let mut lines_read = 0;
-    const MAX_LINES_FOR_DURATION_CHECK: usize = 200;
-
-    for line_result in reader.lines() {
-        lines_read += 1;
-        if lines_read > MAX_LINES_FOR_DURATION_CHECK {
-            println!("Warning: Exceeded max lines read while checking duration. FFmpeg might be stuck or output is too verbose.");
-            break;
-        }
+#[tauri::command]
+async fn compress_audio(
+    app_handle: tauri::AppHandle,
+    input_path: String,
+    output_path: Option<String>,
+    settings: AudioCompressionSettings,
+    output: Option<OutputOptions>,
+) -> Result<String, CompressError> {
+    let output = output.unwrap_or_default();
+    let output_path = plan_output_path(&input_path, output_path, output.template.as_deref(),
+        |_| Ok(audio_template_values(&settings)))?;
+    Ok(queue::enqueue(&app_handle, input_path, output_path, JobSettings::Audio(settings), output.collision))
+}
 
-        if let Ok(line) = line_result {
-            // println!("FFMPEG_DURATION_CHECK_STDERR: {}", line); // Debug
-            if let Some(caps) = re_duration.captures(&line) {
-                let h = caps.get(1).unwrap().as_str().parse::<f32>().unwrap_or(0.0);
-                let m = caps.get(2).unwrap().as_str().parse::<f32>().unwrap_or(0.0);
-                let s = caps.get(3).unwrap().as_str().parse::<f32>().unwrap_or(0.0);
-                let ss = caps.get(4).unwrap().as_str().parse::<f32>().unwrap_or(0.0) / 100.0;
-                duration_found_secs = Some(h * 3600.0 + m * 60.0 + s + ss);
-                // println!("Duration found: {:?} seconds", duration_found_secs);
-                break;
-            }
-            // Some versions of ffmpeg with `-f null -` might print "Output file #0 does not contain any stream"
-            // and then exit. That's fine.
-            if line.contains("Output file #0 does not contain any stream") || line.contains("Conversion failed!") {
-                 break;
-            }
-        } else {
-            break;
-        }
+fn run_audio_compression(
+    app_handle: tauri::AppHandle,
+    task_id: String,
+    input_path: String,
+    output_path: String,
+    settings: AudioCompressionSettings,
+) -> Result<CompressionResult, CompressError> {
+    println!("[{}] Beginning audio compression process...", task_id);
+    println!("[{}] Input: {}, Output: {}", task_id, input_path, output_path);
+    println!("[{}] Audio Settings: {:?}", task_id, settings);
+
+    if !Path::new(&input_path).exists() {
+        return Err(CompressError::InputMissing { path: input_path });
     }
 
-    // It's good practice to ensure the child process is waited on to release resources,
-    // especially if we break early from reading its output.
-    // let _ = child.wait(); // We might not care about the exit status here if duration was found.
+    let input_metadata = fs::metadata(&input_path)
+        .map_err(|e| CompressError::io("Cannot get input file metadata", e))?;
+    let original_size = input_metadata.len();
+    println!("[{}] Original file size: {} bytes", task_id, original_size);
+
+    let ffmpeg = ffmpeg_locator::locate()?;
+
+    let media = probe::probe(&input_path)?;
+    if media.audio_streams.is_empty() {
+        return Err(CompressError::ProbeFailed { path: input_path, reason: "no audio stream".to_string() });
+    }
+
+    let format = resolve_audio_format(settings.format.as_deref(), &output_path)?;
+    println!("[{}] Audio format: {}, quality: {}", task_id, format, settings.quality);
+    require_encoder_library(&ffmpeg, audio_format_library(format))?;
+
+    let mut command = Command::new(&ffmpeg.ffmpeg);
+    command.arg("-i").arg(&input_path)
+           .arg("-vn"); // Drop video streams such as embedded cover art, most audio muxers reject them
+    command.args(audio_codec_args(format, &settings.quality));
+    command.arg("-y").arg(&output_path);
+
+    println!("[{}] Executing audio FFmpeg command: {:?}", task_id, command);
+    execute_ffmpeg_command(command, app_handle, task_id, original_size, output_path, ProgressSpan::full(media.duration_secs))
+}
 
-    match duration_found_secs {
-        Some(d) if d > 0.0 => {
-            // emit_progress(app_handle, task_id, 10.0); // Progress after finding duration
-            Ok(d)
+// Picks the audio format from the explicit setting, or from the output file extension when none is given
+fn resolve_audio_format(format: Option<&str>, output_path: &str) -> Result<&'static str, CompressError> {
+    let extension = Path::new(output_path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+
+    let from_extension = match extension.as_str() {
+        "m4a" | "aac" | "mp4" => Some("aac"),
+        "opus" | "ogg" | "webm" => Some("opus"),
+        "mp3" => Some("mp3"),
+        "flac" => Some("flac"),
+        _ => None,
+    };
+
+    match format {
+        Some(requested) => {
+            let requested = match requested.to_ascii_lowercase().as_str() {
+                "aac" => "aac",
+                "opus" => "opus",
+                "mp3" => "mp3",
+                "flac" => "flac",
+                other => return Err(CompressError::invalid_settings("format", format!("unsupported audio format '{}', use aac, opus, mp3 or flac", other))),
+            };
+            match from_extension {
+                Some(ext_format) if ext_format != requested => Err(CompressError::invalid_settings("format", format!(
+                    "output extension '.{}' does not match the selected audio format '{}'",
+                    extension, requested
+                ))),
+                None => Err(CompressError::invalid_settings("format", format!(
+                    "output extension '.{}' cannot hold '{}' audio",
+                    extension, requested
+                ))),
+                _ => Ok(requested),
+            }
         }
-        _ => Err(format!("Could not parse video duration for '{}' from ffmpeg output. Ensure ffmpeg is working correctly.", input_path)),
+        None => from_extension.ok_or_else(|| CompressError::invalid_settings("format", format!(
+            "cannot infer an audio format from output extension '.{}', use .m4a, .opus, .mp3 or .flac",
+            extension
+        ))),
     }
 }
-        let output_metadata = fs::metadata(&output_path)
-            .map_err(|e| format!("无法获取输出文件元数据: {}", e))?;
-        let compressed_size = output_metadata.len();
-        println!("压缩后文件大小: {} 字节", compressed_size);
-
-        // 计算压缩比例
-        let change_percentage = if original_size > 0 {
-            ((compressed_size as f64 - original_size as f64) / original_size as f64) * 100.0
-        } else {
-            0.0
-        };
 
-        let size_change_str = if change_percentage > 0.0 {
-            format!("增加了 {:.2}%", change_percentage)
-        } else if change_percentage < 0.0 {
-            format!("减少了 {:.2}%", -change_percentage)
-        } else {
-            "保持不变".to_string()
-        };
-        
-        let success_msg = format!(
-            "视频压缩成功！输出文件: {} (大小: {} 字节, 相较原始文件{})",
-            output_path, compressed_size, size_change_str
-        );
-        println!("{}", success_msg);
-        Ok(success_msg)
-    } else {
-        let stderr_output = String::from_utf8_lossy(&output.stderr);
-        let error_msg = format!("FFmpeg命令执行失败: {}", stderr_output);
-        println!("错误: {}", error_msg);
-        Err(error_msg)
+// The --enable-* library behind an audio format's encoder; AAC and FLAC are built into ffmpeg
+fn audio_format_library(format: &str) -> Option<&'static str> {
+    match format {
+        "opus" => Some("libopus"),
+        "mp3" => Some("libmp3lame"),
+        _ => None,
     }
 }
 
-#[tauri::command]
-async fn compress_audio(
-    app_handle: tauri::AppHandle,
-    input_path: String,
-    output_path: String,
-    settings: AudioCompressionSettings,
-) -> Result<String, String> {
-    println!("Attempting to compress audio...");
-    println!("Input Path: {}", input_path);
-    println!("Output Path: {}", output_path);
-    println!("Audio Settings: {:?}", settings);
-
-    let task_id = input_path.clone(); // Use input_path as a simple task_id
-
-    // Simulate progress
-    for i in 0..=10 {
-        let progress = i as f32 * 10.0;
-        let payload = ProgressPayload {
-            task_id: task_id.clone(),
-            progress,
-        };
-        app_handle.emit("PROGRESS_EVENT", payload.clone()).unwrap_or_else(|e| eprintln!("Failed to emit progress for audio: {}", e));
-         println!("Simulated audio progress: {}% for task {}", progress, task_id);
-        // Simulate work being done
-        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await; // tokio::time::sleep is used directly
+// Rejects a format whose encoder library this ffmpeg build was compiled without, before it fails
+// halfway into the encode
+fn require_encoder_library(ffmpeg: &ffmpeg_locator::FfmpegInstall, library: Option<&str>) -> Result<(), CompressError> {
+    match library {
+        Some(library) if !ffmpeg.has_library(library) => Err(CompressError::invalid_settings("format", format!(
+            "this ffmpeg build has no {} (--enable-{}), choose another format", library, library
+        ))),
+        _ => Ok(()),
     }
+}
 
-    Ok(format!(
-        "Audio processing stub: Successfully processed '{}' to '{}' with quality '{}'",
-        input_path, output_path, settings.quality
-    ))
+// Encoder arguments for an audio format, with "low"/"medium"/"high" mapped onto each encoder's own scale
+fn audio_codec_args(format: &str, quality: &str) -> Vec<String> {
+    let args: Vec<&str> = match format {
+        "opus" => {
+            // Opus is transparent at much lower bitrates than AAC
+            let bitrate = match quality { "low" => "64k", "high" => "128k", _ => "96k" };
+            vec!["-c:a", "libopus", "-b:a", bitrate, "-vbr", "on"]
+        }
+        "mp3" => {
+            // LAME VBR: -q:a 0 is best, 9 is smallest (2 ≈ 190kbps, 4 ≈ 165kbps, 6 ≈ 115kbps)
+            let vbr_quality = match quality { "low" => "6", "high" => "2", _ => "4" };
+            vec!["-c:a", "libmp3lame", "-q:a", vbr_quality]
+        }
+        "flac" => {
+            // FLAC is lossless, so quality only trades encode time for size
+            let compression_level = match quality { "low" => "8", "high" => "5", _ => "6" };
+            vec!["-c:a", "flac", "-compression_level", compression_level]
+        }
+        _ => {
+            let bitrate = match quality { "low" => "96k", "high" => "192k", _ => "128k" };
+            vec!["-c:a", "aac", "-b:a", bitrate]
+        }
+    };
+    args.into_iter().map(String::from).collect()
 }
 
 #[tauri::command]
 async fn compress_image(
     app_handle: tauri::AppHandle,
     input_path: String,
+    output_path: Option<String>,
+    settings: ImageCompressionSettings,
+    output: Option<OutputOptions>,
+) -> Result<String, CompressError> {
+    let output = output.unwrap_or_default();
+    let output_path = plan_output_path(&input_path, output_path, output.template.as_deref(),
+        |with_dimensions| image_template_values(&input_path, &settings, with_dimensions))?;
+    Ok(queue::enqueue(&app_handle, input_path, output_path, JobSettings::Image(settings), output.collision))
+}
+
+fn run_image_compression(
+    app_handle: tauri::AppHandle,
+    task_id: String,
+    input_path: String,
     output_path: String,
     settings: ImageCompressionSettings,
-) -> Result<String, String> {
-    println!("Attempting to compress image...");
-    println!("Input Path: {}", input_path);
-    println!("Output Path: {}", output_path);
-    println!("Image Settings: {:?}", settings);
-
-    let task_id = input_path.clone(); // Use input_path as a simple task_id
-
-    // Simulate progress
-    for i in 0..=10 {
-        let progress = i as f32 * 10.0;
-        let payload = ProgressPayload {
-            task_id: task_id.clone(),
-            progress,
-        };
-        app_handle.emit("PROGRESS_EVENT", payload.clone()).unwrap_or_else(|e| eprintln!("Failed to emit progress for image: {}", e));
-         println!("Simulated image progress: {}% for task {}", progress, task_id);
-        // Simulate work being done
-        tokio::time::sleep(tokio::time::Duration::from_millis(50)).await; // tokio::time::sleep is used directly
+) -> Result<CompressionResult, CompressError> {
+    println!("[{}] Beginning image compression process...", task_id);
+    println!("[{}] Input: {}, Output: {}", task_id, input_path, output_path);
+    println!("[{}] Image Settings: {:?}", task_id, settings);
+
+    if !Path::new(&input_path).exists() {
+        return Err(CompressError::InputMissing { path: input_path });
+    }
+    if settings.quality > 100 {
+        return Err(CompressError::invalid_settings("quality", format!("{} must be between 0 and 100", settings.quality)));
+    }
+
+    let input_metadata = fs::metadata(&input_path)
+        .map_err(|e| CompressError::io("Cannot get input file metadata", e))?;
+    let original_size = input_metadata.len();
+    println!("[{}] Original file size: {} bytes", task_id, original_size);
+
+    let ffmpeg = ffmpeg_locator::locate()?;
+
+    // Images decode as a single-frame video stream
+    let media = probe::probe(&input_path)?;
+    if media.video_streams.is_empty() {
+        return Err(CompressError::ProbeFailed { path: input_path, reason: "not a readable image".to_string() });
+    }
+
+    let format = resolve_image_format(settings.format.as_deref(), &output_path)?;
+    println!("[{}] Image format: {}, quality: {}", task_id, format, settings.quality);
+    require_encoder_library(&ffmpeg, image_format_library(format))?;
+
+    let mut command = Command::new(&ffmpeg.ffmpeg);
+    command.arg("-i").arg(&input_path);
+
+    // Resize first so the PNG palette is generated from the final pixels
+    let scale = FilterChain::default().then(image_scale_filter(settings.max_width, settings.max_height));
+    let (codec_args, palette_colors) = image_codec_args(format, settings.quality);
+    if let Some(max_colors) = palette_colors {
+        // Lossy PNG: quantize to an 8-bit palette, which is what makes screenshots shrink.
+        // [in] and [out] are the pads -vf connects to the input and the encoder.
+        let palettegen = Filter::new("palettegen").opt("max_colors", max_colors).opt("reserve_transparent", 1);
+        let mut graph = FilterGraph::default();
+        graph.chain(&["in"], &scale.then(Filter::new("split")), &["a", "b"])
+            .chain(&["a"], &palettegen.into(), &["p"])
+            .chain(&["b", "p"], &Filter::new("paletteuse").into(), &["out"]);
+        command.arg("-vf").arg(graph.to_string());
+    } else if let Some(vf) = scale.to_vf() {
+        command.arg("-vf").arg(vf);
+    }
+
+    command.args(codec_args);
+    if settings.strip_metadata {
+        command.arg("-map_metadata").arg("-1");
+    }
+    // Single still image: stop after one frame and let the image2 muxer write a plain file name
+    command.arg("-frames:v").arg("1").arg("-update").arg("1");
+    command.arg("-y").arg(&output_path);
+
+    println!("[{}] Executing image FFmpeg command: {:?}", task_id, command);
+    // A still image has no meaningful duration, so only completion is reported
+    execute_ffmpeg_command(command, app_handle, task_id, original_size, output_path, ProgressSpan::full(None))
+}
+
+// Picks the image format from the explicit setting, or from the output file extension when none is given
+fn resolve_image_format(format: Option<&str>, output_path: &str) -> Result<&'static str, CompressError> {
+    let extension = Path::new(output_path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+
+    let from_extension = match extension.as_str() {
+        "jpg" | "jpeg" => Some("jpeg"),
+        "png" => Some("png"),
+        "webp" => Some("webp"),
+        "avif" => Some("avif"),
+        _ => None,
+    };
+
+    match format {
+        Some(requested) => {
+            let requested = match requested.to_ascii_lowercase().as_str() {
+                "jpeg" | "jpg" => "jpeg",
+                "png" => "png",
+                "webp" => "webp",
+                "avif" => "avif",
+                other => return Err(CompressError::invalid_settings("format", format!("unsupported image format '{}', use jpeg, png, webp or avif", other))),
+            };
+            if from_extension != Some(requested) {
+                return Err(CompressError::invalid_settings("format", format!(
+                    "output extension '.{}' does not match the selected image format '{}'",
+                    extension, requested
+                )));
+            }
+            Ok(requested)
+        }
+        None => from_extension.ok_or_else(|| CompressError::invalid_settings("format", format!(
+            "cannot infer an image format from output extension '.{}', use .jpg, .png, .webp or .avif",
+            extension
+        ))),
     }
+}
+
+// The --enable-* library behind an image format's encoder; PNG and JPEG are built into ffmpeg
+fn image_format_library(format: &str) -> Option<&'static str> {
+    match format {
+        "webp" => Some("libwebp"),
+        "avif" => Some("libaom"),
+        _ => None,
+    }
+}
 
-    Ok(format!(
-        "Image processing stub: Successfully processed '{}' to '{}' with quality {}",
-        input_path, output_path, settings.quality
-    ))
+// Encoder arguments for an image format with the 0-100 quality mapped onto the encoder's own scale.
+// The second value is the palette size to quantize to, only used for lossy PNG.
+fn image_codec_args(format: &str, quality: u8) -> (Vec<String>, Option<u32>) {
+    let quality = quality.min(100) as u32;
+    let (args, palette_colors) = match format {
+        "png" => {
+            // PNG is lossless; below 90 we trade colors for size like pngquant does
+            let palette_colors = if quality >= 90 { None } else { Some((16 + quality * 240 / 90).min(256)) };
+            (vec!["-c:v".to_string(), "png".to_string(),
+                  "-compression_level".to_string(), "9".to_string(),
+                  "-pred".to_string(), "mixed".to_string()], palette_colors)
+        }
+        "webp" => {
+            // libwebp already uses 0-100; 100 switches to lossless
+            let mut args = vec!["-c:v".to_string(), "libwebp".to_string(),
+                                "-compression_level".to_string(), "6".to_string()];
+            if quality == 100 {
+                args.extend(["-lossless".to_string(), "1".to_string()]);
+            } else {
+                args.extend(["-quality".to_string(), quality.to_string()]);
+            }
+            (args, None)
+        }
+        "avif" => {
+            // libaom CRF runs 0 (best) to 63 (worst)
+            let crf = 63 - quality * 63 / 100;
+            (vec!["-c:v".to_string(), "libaom-av1".to_string(),
+                  "-still-picture".to_string(), "1".to_string(),
+                  "-crf".to_string(), crf.to_string(),
+                  "-b:v".to_string(), "0".to_string(),
+                  "-cpu-used".to_string(), "6".to_string()], None)
+        }
+        _ => {
+            // MJPEG -q:v runs 2 (best) to 31 (worst)
+            let q = 31 - quality * 29 / 100;
+            (vec!["-c:v".to_string(), "mjpeg".to_string(),
+                  "-q:v".to_string(), q.to_string()], None)
+        }
+    };
+    (args, palette_colors)
+}
+
+// Fits the image inside the optional bounding box, keeping the aspect ratio and never upscaling
+fn image_scale_filter(max_width: Option<u32>, max_height: Option<u32>) -> Option<Filter> {
+    let scale = Filter::new("scale");
+    match (max_width.filter(|w| *w > 0), max_height.filter(|h| *h > 0)) {
+        (Some(w), Some(h)) => Some(scale
+            .arg(format!("min({},iw)", w))
+            .arg(format!("min({},ih)", h))
+            .opt("force_original_aspect_ratio", "decrease")),
+        (Some(w), None) => Some(scale.arg(format!("min({},iw)", w)).arg(-1)),
+        (None, Some(h)) => Some(scale.arg(-1).arg(format!("min({},ih)", h))),
+        (None, None) => None,
+    }
+}
+
+// Works out where a task plans to write: the explicit output path, or else the naming template.
+// The collision policy is applied when the task starts. `values` supplies the template values,
+// planning dimensions when asked to.
+fn plan_output_path(
+    input_path: &str,
+    output_path: Option<String>,
+    template: Option<&str>,
+    values: impl FnOnce(bool) -> Result<TemplateValues, CompressError>,
+) -> Result<String, CompressError> {
+    if let Some(path) = output_path {
+        return Ok(path);
+    }
+    let template = template.unwrap_or(output_naming::DEFAULT_TEMPLATE);
+    let values = values(output_naming::uses_dimensions(template))?;
+    Ok(output_naming::expand_template(template, input_path, &values)?.to_string_lossy().into_owned())
+}
+
+
+fn video_template_values(input_path: &str, settings: &CompressionSettings, with_dimensions: bool) -> Result<TemplateValues, CompressError> {
+    let (width, height) = if with_dimensions {
+        let media = probe::probe(input_path)?;
+        let source = media.primary_video()
+            .ok_or_else(|| CompressError::ProbeFailed { path: input_path.to_string(), reason: "no video stream".to_string() })?;
+        // Auto crop runs the detection here too, so names match the encoded size
+        let trim = trim::TrimPlan::new(&settings.keep_ranges, media.duration_secs)?;
+        let crop = crop::resolve(settings.crop, &ffmpeg_locator::ffmpeg_path()?, input_path, &trim, source)?;
+        let frame = crop.map_or(resolution::display_size(source), |rect| (rect.width, rect.height));
+        let planned = resolution::plan(resolution::ResolutionTarget::from_settings(settings)?, frame);
+        (Some(planned.width), Some(planned.height))
+    } else {
+        (None, None)
+    };
+    Ok(TemplateValues {
+        // Quality presets have names of their own ("small", "high"); the other modes go by their type
+        preset: Some(if settings.preset_type == "quality" { settings.preset.clone() } else { settings.preset_type.clone() }),
+        width,
+        height,
+        codec: Some(settings.codec.encoder_name().to_string()),
+        ext: settings.container.map_or("mp4", Container::extension).to_string(),
+    })
+}
+
+fn audio_template_values(settings: &AudioCompressionSettings) -> TemplateValues {
+    let format = settings.format.as_deref().unwrap_or("aac").to_ascii_lowercase();
+    TemplateValues {
+        preset: Some(settings.quality.clone()),
+        ext: match format.as_str() { "aac" => "m4a", other => other }.to_string(),
+        codec: Some(format),
+        ..TemplateValues::default()
+    }
+}
+
+fn image_template_values(input_path: &str, settings: &ImageCompressionSettings, with_dimensions: bool) -> Result<TemplateValues, CompressError> {
+    let format = settings.format.as_deref().map(|f| f.to_ascii_lowercase());
+    let ext = match format.as_deref() {
+        Some("jpeg") => "jpg".to_string(),
+        Some(other) => other.to_string(),
+        // Without a format the image keeps its own
+        None => Path::new(input_path).extension().map(|e| e.to_string_lossy().into_owned()).unwrap_or_else(|| "jpg".to_string()),
+    };
+    let (width, height) = if with_dimensions {
+        let media = probe::probe(input_path)?;
+        let image = media.video_streams.first()
+            .ok_or_else(|| CompressError::ProbeFailed { path: input_path.to_string(), reason: "not a readable image".to_string() })?;
+        // Shrinks to fit the bounding box like image_scale_filter, never enlarges
+        let scale = [
+            settings.max_width.filter(|w| *w > 0).map(|w| w as f64 / image.width.max(1) as f64),
+            settings.max_height.filter(|h| *h > 0).map(|h| h as f64 / image.height.max(1) as f64),
+        ].into_iter().flatten().fold(1.0_f64, f64::min);
+        (Some((image.width as f64 * scale).round() as u32), Some((image.height as f64 * scale).round() as u32))
+    } else {
+        (None, None)
+    };
+    Ok(TemplateValues { preset: Some(settings.quality.to_string()), width, height, codec: format, ext })
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -1143,15 +1527,35 @@ pub fn run() {
         .plugin(tauri_plugin_dialog::init())
         .invoke_handler(tauri::generate_handler![
             greet,
-            select_video_file,
+            select_files,
             handle_dropped_file,
             compress_video,
             compress_audio,
-            compress_image
+            compress_image,
+            cancel_task,
+            hw_encoder::detect_encoders,
+            ffmpeg_locator::get_ffmpeg_info,
+            ffmpeg_locator::set_ffmpeg_path,
+            batch::compress_folder,
+            batch::get_batch_summary,
+            probe::probe_media,
+            queue::enqueue_job,
+            queue::list_jobs,
+            queue::reorder_job,
+            queue::remove_job,
+            queue::pause_job,
+            queue::resume_job,
+            queue::pause_queue,
+            queue::resume_queue,
+            queue::set_max_parallel_jobs
         ])
-        .setup(|_app| {
-            // Ensure `regex` crate is available if not already part of the project dependencies.
-            // This is a placeholder comment; actual dependency management is in Cargo.toml.
+        .manage(JobRegistry::default())
+        .setup(|app| {
+            // Restore unfinished jobs from the last session and start working through them
+            let data_dir = app.path().app_data_dir().ok();
+            ffmpeg_locator::load_settings(data_dir.clone());
+            app.manage(JobQueue::load(data_dir));
+            queue::dispatch(app.handle());
             Ok(())
         })
         .run(tauri::generate_context!())
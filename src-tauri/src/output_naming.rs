@@ -0,0 +1,190 @@
+// Output file naming: expands templates like "{dir}/{stem}_{preset}_{height}p.{ext}" and decides
+// what happens when the resulting file already exists.
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::CompressError;
+
+pub const DEFAULT_TEMPLATE: &str = "{dir}/{stem}_compressed.{ext}";
+
+// Numbered names tried by the increment policy before giving up
+const MAX_INCREMENT: u32 = 9999;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CollisionPolicy {
+    Skip,      // Leave the existing file and don't compress
+    Overwrite,
+    #[default]
+    Increment, // Write to "name_1.ext", "name_2.ext", ... instead
+    Fail,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OutputOptions {
+    #[serde(default)]
+    pub template: Option<String>, // Used when no explicit output path is given; DEFAULT_TEMPLATE when absent
+    #[serde(default)]
+    pub collision: CollisionPolicy,
+}
+
+// Values a task can fill into a template besides the input's {dir} and {stem}
+#[derive(Debug, Default)]
+pub struct TemplateValues {
+    pub preset: Option<String>,
+    pub width: Option<u32>, // Planned output dimensions
+    pub height: Option<u32>,
+    pub codec: Option<String>,
+    pub ext: String, // Without the dot
+}
+
+// Whether expanding the template needs output dimensions, which cost an ffprobe run to plan
+pub fn uses_dimensions(template: &str) -> bool {
+    template.contains("{width}") || template.contains("{height}")
+}
+
+pub fn expand_template(template: &str, input_path: &str, values: &TemplateValues) -> Result<PathBuf, CompressError> {
+    let input = Path::new(input_path);
+    let dir = input.parent().map(|d| d.to_string_lossy().into_owned()).unwrap_or_default();
+    let stem = input.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+
+    let mut expanded = String::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        expanded.push_str(&rest[..open]);
+        let close = rest[open..].find('}')
+            .ok_or_else(|| CompressError::invalid_settings("template", format!("unclosed '{{' in '{}'", template)))?;
+        let name = &rest[open + 1..open + close];
+        let missing = || CompressError::invalid_settings("template", format!("{{{}}} is not available for this file", name));
+        let value = match name {
+            "dir" => dir.clone(),
+            "stem" => sanitize(&stem),
+            "ext" => sanitize(&values.ext),
+            "preset" => sanitize(values.preset.as_deref().ok_or_else(missing)?),
+            "codec" => sanitize(values.codec.as_deref().ok_or_else(missing)?),
+            "width" => values.width.ok_or_else(missing)?.to_string(),
+            "height" => values.height.ok_or_else(missing)?.to_string(),
+            other => return Err(CompressError::invalid_settings("template", format!(
+                "unknown placeholder {{{}}}, use dir, stem, ext, preset, codec, width or height", other
+            ))),
+        };
+        expanded.push_str(&value);
+        rest = &rest[open + close + 1..];
+    }
+    expanded.push_str(rest);
+
+    let path = PathBuf::from(expanded);
+    // A template without {dir} writes next to the input
+    Ok(if path.is_relative() { input.parent().unwrap_or(Path::new("")).join(path) } else { path })
+}
+
+// Values other than {dir} must not add folders or stray separators to the name
+fn sanitize(value: &str) -> String {
+    value.chars().map(|c| if matches!(c, '/' | '\\' | ':') { '_' } else { c }).collect()
+}
+
+// Applies the collision policy to a planned output path. `in_use` reports paths that running
+// tasks are writing to, which count as taken even before the file appears.
+pub fn resolve_collision(
+    path: PathBuf,
+    input_path: &str,
+    policy: CollisionPolicy,
+    in_use: impl Fn(&Path) -> bool,
+) -> Result<PathBuf, CompressError> {
+    if same_file(&path, Path::new(input_path)) {
+        return Err(CompressError::invalid_settings("outputPath", format!(
+            "'{}' is the input file, choose another output name", path.display()
+        )));
+    }
+    let taken = |p: &Path| p.exists() || in_use(p);
+    if !taken(&path) {
+        return Ok(path);
+    }
+
+    let display = path.display().to_string();
+    match policy {
+        CollisionPolicy::Overwrite if in_use(&path) => Err(CompressError::OutputExists { path: display }),
+        CollisionPolicy::Overwrite => Ok(path),
+        CollisionPolicy::Skip => Err(CompressError::OutputSkipped { path: display }),
+        CollisionPolicy::Fail => Err(CompressError::OutputExists { path: display }),
+        CollisionPolicy::Increment => {
+            let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+            let extension = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+            (1..=MAX_INCREMENT)
+                .map(|n| path.with_file_name(format!("{}_{}{}", stem, n, extension)))
+                .find(|candidate| !taken(candidate) && !same_file(candidate, Path::new(input_path)))
+                .ok_or(CompressError::OutputExists { path: display })
+        }
+    }
+}
+
+// Compares through the file system so "./a.mp4" and "/home/me/a.mp4" are recognized as the same
+// file. The output usually doesn't exist yet, so its folder is resolved instead.
+fn same_file(output: &Path, input: &Path) -> bool {
+    let Ok(input) = fs::canonicalize(input) else { return false };
+    let resolved = match (output.parent(), output.file_name()) {
+        (Some(dir), Some(name)) => fs::canonicalize(if dir.as_os_str().is_empty() { Path::new(".") } else { dir })
+            .map(|dir| dir.join(name))
+            .unwrap_or_else(|_| output.to_path_buf()),
+        _ => output.to_path_buf(),
+    };
+    if cfg!(any(windows, target_os = "macos")) {
+        // Case-insensitive file systems by default
+        resolved.to_string_lossy().to_lowercase() == input.to_string_lossy().to_lowercase()
+    } else {
+        resolved == input
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values() -> TemplateValues {
+        TemplateValues { preset: Some("web/hq".to_string()), width: Some(1280), height: Some(720), codec: None, ext: "mp4".to_string() }
+    }
+
+    #[test]
+    fn expands_placeholders() {
+        let path = expand_template("{dir}/{stem}_{preset}_{height}p.{ext}", "/videos/trip.mov", &values()).unwrap();
+        assert_eq!(path, PathBuf::from("/videos/trip_web_hq_720p.mp4"));
+        // Without {dir} the output goes next to the input
+        let path = expand_template("small/{stem}.{ext}", "/videos/trip.mov", &values()).unwrap();
+        assert_eq!(path, PathBuf::from("/videos/small/trip.mp4"));
+    }
+
+    #[test]
+    fn rejects_bad_templates() {
+        for template in ["{stem", "{stem}_{size}.{ext}", "{stem}_{codec}.{ext}"] {
+            let error = expand_template(template, "/videos/trip.mov", &values()).unwrap_err();
+            assert!(matches!(error, CompressError::InvalidSettings { ref field, .. } if field == "template"), "{}", template);
+        }
+    }
+
+    #[test]
+    fn applies_the_collision_policy() {
+        let dir = std::env::temp_dir().join(format!("pressvid-naming-{}", crate::jobs::new_task_id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["in.mov", "out.mp4", "out_1.mp4"] {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+        let input = dir.join("in.mov").to_string_lossy().into_owned();
+        let out = dir.join("out.mp4");
+        let resolve = |path: &Path, policy, in_use: &dyn Fn(&Path) -> bool| resolve_collision(path.to_path_buf(), &input, policy, in_use);
+        let nothing_running = |_: &Path| false;
+
+        assert_eq!(resolve(&dir.join("new.mp4"), CollisionPolicy::Fail, &nothing_running).unwrap(), dir.join("new.mp4"));
+        assert_eq!(resolve(&out, CollisionPolicy::Increment, &nothing_running).unwrap(), dir.join("out_2.mp4"));
+        let out_2_running = |p: &Path| p == dir.join("out_2.mp4");
+        assert_eq!(resolve(&out, CollisionPolicy::Increment, &out_2_running).unwrap(), dir.join("out_3.mp4"));
+        assert_eq!(resolve(&out, CollisionPolicy::Overwrite, &nothing_running).unwrap(), out);
+        let out_running = |p: &Path| p == out;
+        assert!(matches!(resolve(&out, CollisionPolicy::Overwrite, &out_running), Err(CompressError::OutputExists { .. })));
+        assert!(matches!(resolve(&out, CollisionPolicy::Skip, &nothing_running), Err(CompressError::OutputSkipped { .. })));
+        assert!(matches!(resolve(&out, CollisionPolicy::Fail, &nothing_running), Err(CompressError::OutputExists { .. })));
+        assert!(matches!(resolve(&dir.join("in.mov"), CollisionPolicy::Overwrite, &nothing_running), Err(CompressError::InvalidSettings { .. })));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
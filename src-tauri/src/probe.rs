@@ -0,0 +1,407 @@
+// Structured media probing through `ffprobe -print_format json`.
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Command;
+
+use crate::error::CompressError;
+use crate::ffmpeg_locator;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MediaInfo {
+    #[serde(rename = "formatName")]
+    pub format_name: String,
+    #[serde(rename = "durationSecs")]
+    pub duration_secs: Option<f64>,
+    #[serde(rename = "sizeBytes")]
+    pub size_bytes: Option<u64>,
+    #[serde(rename = "bitRate")]
+    pub bit_rate: Option<u64>, // Overall bitrate in bits per second
+    #[serde(rename = "videoStreams")]
+    pub video_streams: Vec<VideoStreamInfo>,
+    #[serde(rename = "audioStreams")]
+    pub audio_streams: Vec<AudioStreamInfo>,
+    #[serde(rename = "subtitleStreams")]
+    pub subtitle_streams: Vec<SubtitleStreamInfo>,
+    pub chapters: Vec<ChapterInfo>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VideoStreamInfo {
+    pub index: u32,
+    pub codec: String,
+    pub profile: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    #[serde(rename = "frameRate")]
+    pub frame_rate: Option<f64>, // Average frame rate; falls back to r_frame_rate
+    #[serde(rename = "variableFrameRate")]
+    pub variable_frame_rate: bool, // Average and base rate disagree, as in most phone and screen recordings
+    #[serde(rename = "frameCount")]
+    pub frame_count: Option<u64>, // From the container's index; not every demuxer knows it
+    #[serde(rename = "bitRate")]
+    pub bit_rate: Option<u64>,
+    #[serde(rename = "pixelFormat")]
+    pub pixel_format: Option<String>,
+    pub rotation: i32, // Degrees, normalized to 0/90/180/270
+    #[serde(rename = "attachedPic")]
+    pub attached_pic: bool, // Cover art rather than real video
+    pub hdr: Option<HdrInfo>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HdrInfo {
+    pub transfer: String, // "smpte2084" (PQ) or "arib-std-b67" (HLG)
+    pub primaries: Option<String>,
+    #[serde(rename = "colorSpace")]
+    pub color_space: Option<String>,
+    #[serde(rename = "hasMasteringDisplay")]
+    pub has_mastering_display: bool,
+    #[serde(rename = "hasContentLightLevel")]
+    pub has_content_light_level: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AudioStreamInfo {
+    pub index: u32,
+    pub codec: String,
+    pub channels: u32,
+    #[serde(rename = "channelLayout")]
+    pub channel_layout: Option<String>,
+    #[serde(rename = "sampleRate")]
+    pub sample_rate: Option<u32>,
+    #[serde(rename = "bitRate")]
+    pub bit_rate: Option<u64>,
+    pub language: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SubtitleStreamInfo {
+    pub index: u32,
+    pub codec: String,
+    pub language: Option<String>,
+    pub title: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChapterInfo {
+    #[serde(rename = "startSecs")]
+    pub start_secs: f64,
+    #[serde(rename = "endSecs")]
+    pub end_secs: f64,
+    pub title: Option<String>,
+}
+
+// Which compressor a file belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaKind {
+    Video,
+    Audio,
+    Image,
+}
+
+impl MediaInfo {
+    // The main video stream, skipping cover art
+    pub fn primary_video(&self) -> Option<&VideoStreamInfo> {
+        self.video_streams.iter().find(|s| !s.attached_pic)
+    }
+
+    // Classifies by content rather than extension: still images come through ffprobe's image
+    // demuxers ("image2", "png_pipe", ...), and music with cover art counts as audio. AVIF and
+    // HEIC are read by the mov/mp4 demuxer, so a silent single-frame video is a still too.
+    pub fn kind(&self) -> Option<MediaKind> {
+        let is_image_format = self.format_name == "image2" || self.format_name.ends_with("_pipe");
+        let is_still = |video: &VideoStreamInfo| video.frame_count == Some(1) && self.audio_streams.is_empty();
+        match self.primary_video() {
+            Some(video) if is_image_format || is_still(video) => Some(MediaKind::Image),
+            Some(_) => Some(MediaKind::Video),
+            None if !self.audio_streams.is_empty() => Some(MediaKind::Audio),
+            None => None,
+        }
+    }
+}
+
+// --- Raw ffprobe JSON. Most numbers come back as strings, so they are parsed afterwards. ---
+
+#[derive(Debug, Deserialize)]
+struct RawProbe {
+    #[serde(default)]
+    streams: Vec<RawStream>,
+    format: Option<RawFormat>,
+    #[serde(default)]
+    chapters: Vec<RawChapter>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawFormat {
+    format_name: Option<String>,
+    duration: Option<String>,
+    size: Option<String>,
+    bit_rate: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawStream {
+    index: u32,
+    codec_type: Option<String>,
+    codec_name: Option<String>,
+    profile: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+    avg_frame_rate: Option<String>,
+    r_frame_rate: Option<String>,
+    nb_frames: Option<String>,
+    bit_rate: Option<String>,
+    pix_fmt: Option<String>,
+    color_transfer: Option<String>,
+    color_primaries: Option<String>,
+    color_space: Option<String>,
+    channels: Option<u32>,
+    channel_layout: Option<String>,
+    sample_rate: Option<String>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+    #[serde(default)]
+    disposition: HashMap<String, i64>,
+    #[serde(default)]
+    side_data_list: Vec<RawSideData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawSideData {
+    side_data_type: Option<String>,
+    rotation: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawChapter {
+    start_time: Option<String>,
+    end_time: Option<String>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+// Runs ffprobe on the input and returns typed stream information
+pub fn probe(input_path: &str) -> Result<MediaInfo, CompressError> {
+    if !Path::new(input_path).exists() {
+        return Err(CompressError::InputMissing { path: input_path.to_string() });
+    }
+
+    let output = Command::new(ffmpeg_locator::ffprobe_path()?)
+        .arg("-v").arg("error")
+        .arg("-print_format").arg("json")
+        .arg("-show_format")
+        .arg("-show_streams")
+        .arg("-show_chapters")
+        .arg(input_path)
+        .output()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => CompressError::FfmpegNotFound { program: "ffprobe".to_string() },
+            _ => CompressError::io("Failed to run ffprobe", e),
+        })?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(CompressError::ProbeFailed { path: input_path.to_string(), reason: stderr.trim().to_string() });
+    }
+
+    parse_probe_output(&String::from_utf8_lossy(&output.stdout))
+        .map_err(|reason| CompressError::ProbeFailed { path: input_path.to_string(), reason })
+}
+
+fn parse_probe_output(json: &str) -> Result<MediaInfo, String> {
+    let raw: RawProbe = serde_json::from_str(json)
+        .map_err(|e| format!("Could not parse ffprobe output: {}", e))?;
+
+    let format = raw.format.as_ref();
+    let mut info = MediaInfo {
+        format_name: format.and_then(|f| f.format_name.clone()).unwrap_or_default(),
+        duration_secs: format.and_then(|f| parse_num::<f64>(&f.duration)).filter(|d| *d > 0.0),
+        size_bytes: format.and_then(|f| parse_num(&f.size)),
+        bit_rate: format.and_then(|f| parse_num(&f.bit_rate)),
+        video_streams: Vec::new(),
+        audio_streams: Vec::new(),
+        subtitle_streams: Vec::new(),
+        chapters: Vec::new(),
+    };
+
+    for stream in raw.streams {
+        let codec = stream.codec_name.clone().unwrap_or_else(|| "unknown".to_string());
+        match stream.codec_type.as_deref() {
+            Some("video") => info.video_streams.push(VideoStreamInfo {
+                index: stream.index,
+                profile: stream.profile.clone(),
+                width: stream.width.unwrap_or(0),
+                height: stream.height.unwrap_or(0),
+                frame_rate: parse_rational(&stream.avg_frame_rate).or_else(|| parse_rational(&stream.r_frame_rate)),
+                variable_frame_rate: is_variable_frame_rate(&stream),
+                frame_count: parse_num(&stream.nb_frames),
+                bit_rate: parse_num(&stream.bit_rate),
+                pixel_format: stream.pix_fmt.clone(),
+                rotation: stream_rotation(&stream),
+                attached_pic: stream.disposition.get("attached_pic").copied().unwrap_or(0) == 1,
+                hdr: stream_hdr(&stream),
+                codec,
+            }),
+            Some("audio") => info.audio_streams.push(AudioStreamInfo {
+                index: stream.index,
+                codec,
+                channels: stream.channels.unwrap_or(0),
+                channel_layout: stream.channel_layout.clone(),
+                sample_rate: parse_num(&stream.sample_rate),
+                bit_rate: parse_num(&stream.bit_rate),
+                language: stream.tags.get("language").cloned(),
+            }),
+            Some("subtitle") => info.subtitle_streams.push(SubtitleStreamInfo {
+                index: stream.index,
+                codec,
+                language: stream.tags.get("language").cloned(),
+                title: stream.tags.get("title").cloned(),
+            }),
+            _ => {} // data/attachment streams are not interesting for compression
+        }
+    }
+
+    for chapter in raw.chapters {
+        info.chapters.push(ChapterInfo {
+            start_secs: parse_num(&chapter.start_time).unwrap_or(0.0),
+            end_secs: parse_num(&chapter.end_time).unwrap_or(0.0),
+            title: chapter.tags.get("title").cloned(),
+        });
+    }
+
+    Ok(info)
+}
+
+fn parse_num<T: std::str::FromStr>(value: &Option<String>) -> Option<T> {
+    value.as_deref().and_then(|v| v.trim().parse::<T>().ok())
+}
+
+// ffprobe frame rates are rationals like "30000/1001"; "0/0" means unknown
+fn parse_rational(value: &Option<String>) -> Option<f64> {
+    let value = value.as_deref()?;
+    let (num, den) = value.split_once('/').unwrap_or((value, "1"));
+    let num: f64 = num.trim().parse().ok()?;
+    let den: f64 = den.trim().parse().ok()?;
+    if num > 0.0 && den > 0.0 { Some(num / den) } else { None }
+}
+
+// r_frame_rate is the lowest rate that represents every timestamp, so it only matches the
+// average for constant frame rate streams
+fn is_variable_frame_rate(stream: &RawStream) -> bool {
+    match (parse_rational(&stream.avg_frame_rate), parse_rational(&stream.r_frame_rate)) {
+        (Some(avg), Some(base)) => (avg - base).abs() > base * 0.01,
+        _ => false,
+    }
+}
+
+// Rotation lives in the display matrix side data on newer ffmpeg and in the "rotate" tag on older ones
+fn stream_rotation(stream: &RawStream) -> i32 {
+    let degrees = stream.side_data_list.iter()
+        .find_map(|sd| sd.rotation)
+        .or_else(|| stream.tags.get("rotate").and_then(|r| r.parse::<f64>().ok()))
+        .unwrap_or(0.0);
+    (degrees.round() as i32).rem_euclid(360)
+}
+
+fn stream_hdr(stream: &RawStream) -> Option<HdrInfo> {
+    let transfer = stream.color_transfer.as_deref()?;
+    if transfer != "smpte2084" && transfer != "arib-std-b67" {
+        return None;
+    }
+    let has_side_data = |name: &str| stream.side_data_list.iter()
+        .any(|sd| sd.side_data_type.as_deref() == Some(name));
+    Some(HdrInfo {
+        transfer: transfer.to_string(),
+        primaries: stream.color_primaries.clone(),
+        color_space: stream.color_space.clone(),
+        has_mastering_display: has_side_data("Mastering display metadata"),
+        has_content_light_level: has_side_data("Content light level metadata"),
+    })
+}
+
+// Lets the frontend inspect a file before choosing settings
+#[tauri::command]
+pub async fn probe_media(input_path: String) -> Result<MediaInfo, CompressError> {
+    probe(&input_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PHONE_VIDEO: &str = r#"{
+        "streams": [
+            {"index": 0, "codec_type": "video", "codec_name": "hevc", "profile": "Main 10", "width": 3840, "height": 2160,
+             "avg_frame_rate": "2997/100", "r_frame_rate": "60/1", "bit_rate": "35000000", "pix_fmt": "yuv420p10le",
+             "color_transfer": "arib-std-b67", "color_primaries": "bt2020", "color_space": "bt2020nc", "nb_frames": "1783",
+             "side_data_list": [{"side_data_type": "Display Matrix", "rotation": -90}]},
+            {"index": 1, "codec_type": "audio", "codec_name": "aac", "channels": 2, "channel_layout": "stereo",
+             "sample_rate": "48000", "bit_rate": "192000", "tags": {"language": "eng"}},
+            {"index": 2, "codec_type": "data", "codec_name": "none"}
+        ],
+        "format": {"format_name": "mov,mp4,m4a,3gp,3g2,mj2", "duration": "29.993", "size": "131000000", "bit_rate": "34940000"},
+        "chapters": [{"start_time": "0.000000", "end_time": "12.5", "tags": {"title": "Intro"}}]
+    }"#;
+
+    #[test]
+    fn parses_streams_format_and_chapters() {
+        let info = parse_probe_output(PHONE_VIDEO).unwrap();
+        assert_eq!(info.format_name, "mov,mp4,m4a,3gp,3g2,mj2");
+        assert_eq!(info.duration_secs, Some(29.993));
+        assert_eq!(info.size_bytes, Some(131_000_000));
+
+        let video = info.primary_video().unwrap();
+        assert_eq!((video.codec.as_str(), video.width, video.height), ("hevc", 3840, 2160));
+        assert!((video.frame_rate.unwrap() - 29.97).abs() < 0.001);
+        assert!(video.variable_frame_rate);
+        assert_eq!(video.rotation, 270);
+        assert_eq!(video.frame_count, Some(1783));
+        let hdr = video.hdr.as_ref().unwrap();
+        assert_eq!((hdr.transfer.as_str(), hdr.primaries.as_deref()), ("arib-std-b67", Some("bt2020")));
+
+        assert_eq!(info.audio_streams.len(), 1);
+        assert_eq!(info.audio_streams[0].sample_rate, Some(48000));
+        assert_eq!(info.audio_streams[0].language.as_deref(), Some("eng"));
+        assert_eq!(info.chapters[0].end_secs, 12.5);
+        assert_eq!(info.chapters[0].title.as_deref(), Some("Intro"));
+        assert_eq!(info.kind(), Some(MediaKind::Video));
+    }
+
+    #[test]
+    fn cover_art_is_not_video() {
+        let info = parse_probe_output(r#"{
+            "streams": [
+                {"index": 0, "codec_type": "audio", "codec_name": "mp3", "channels": 2},
+                {"index": 1, "codec_type": "video", "codec_name": "mjpeg", "width": 600, "height": 600, "disposition": {"attached_pic": 1}}
+            ],
+            "format": {"format_name": "mp3", "duration": "N/A"}
+        }"#).unwrap();
+        assert!(info.primary_video().is_none());
+        assert_eq!(info.duration_secs, None);
+        assert_eq!(info.kind(), Some(MediaKind::Audio));
+    }
+
+    #[test]
+    fn stills_are_images() {
+        let png = parse_probe_output(r#"{
+            "streams": [{"index": 0, "codec_type": "video", "codec_name": "png", "width": 800, "height": 600, "avg_frame_rate": "0/0"}],
+            "format": {"format_name": "png_pipe"}
+        }"#).unwrap();
+        assert_eq!(png.kind(), Some(MediaKind::Image));
+        assert_eq!(png.primary_video().unwrap().frame_rate, None);
+
+        let avif = parse_probe_output(r#"{
+            "streams": [{"index": 0, "codec_type": "video", "codec_name": "av1", "width": 4032, "height": 3024, "nb_frames": "1"}],
+            "format": {"format_name": "mov,mp4,m4a,3gp,3g2,mj2"}
+        }"#).unwrap();
+        assert_eq!(avif.kind(), Some(MediaKind::Image));
+    }
+
+    #[test]
+    fn rejects_malformed_output() {
+        assert!(parse_probe_output("not json").is_err());
+        assert_eq!(parse_probe_output("{}").unwrap().kind(), None);
+    }
+}
@@ -0,0 +1,209 @@
+// Post-encode verification: compares a finished output with its source through ffmpeg's psnr, ssim
+// and (when the build has it) libvmaf filters, and summarizes the per-frame scores they log.
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::filter_graph::{Filter, FilterChain, FilterGraph};
+
+// Frames listed per metric as the worst of the encode
+const WORST_FRAME_COUNT: usize = 5;
+
+// psnr reports identical frames as "inf"; they are capped so the report stays valid JSON
+const PSNR_CAP_DB: f64 = 100.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QualityReport {
+    pub psnr: Option<MetricSummary>, // dB, averaged over Y/U/V
+    pub ssim: Option<MetricSummary>, // 0-1, all planes
+    pub vmaf: Option<MetricSummary>, // 0-100; None when ffmpeg was built without libvmaf
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetricSummary {
+    pub average: f64,
+    pub min: f64,
+    #[serde(rename = "worstFrames")]
+    pub worst_frames: Vec<FrameScore>, // Lowest scoring frames first
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FrameScore {
+    pub frame: u64, // 0-based frame number in the output
+    pub score: f64,
+}
+
+// Per-frame log files written by one comparison run
+pub struct StatsFiles {
+    pub psnr: PathBuf,
+    pub ssim: PathBuf,
+    pub vmaf: PathBuf,
+}
+
+impl StatsFiles {
+    pub fn remove(&self) {
+        for path in [&self.psnr, &self.ssim, &self.vmaf] {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+// Filter graph comparing the output (input 0) with the source (input 1). The source goes through
+// `source_filter` (the encode's frame rate conversion and crop) and is scaled to the output's
+// dimensions so the two can be compared frame by frame.
+// `source_graph` joins the kept ranges of a trimmed source into [src]; None uses input 1 as is.
+pub fn compare_graph(
+    files: &StatsFiles,
+    source_graph: Option<FilterGraph>,
+    source_filter: &FilterChain,
+    output_size: Option<(u32, u32)>,
+    with_vmaf: bool,
+) -> String {
+    let copies = if with_vmaf { 3 } else { 2 };
+    let (mut graph, source) = match source_graph {
+        Some(graph) => (graph, "src"),
+        None => (FilterGraph::default(), "1:v"),
+    };
+    let normalized = |chain: FilterChain| chain
+        .then(Filter::new("format").arg("yuv420p"))
+        .then(Filter::new("setpts").arg("PTS-STARTPTS"))
+        .then(Filter::new("split").arg(copies));
+    let scale = output_size.map(|(w, h)| Filter::new("scale").arg(w).arg(h).opt("flags", "bicubic"));
+    graph.chain(&["0:v"], &normalized(FilterChain::default()), &["d1", "d2", "d3"][..copies])
+        .chain(&[source], &normalized(source_filter.clone().then(scale)), &["r1", "r2", "r3"][..copies])
+        .chain(&["d1", "r1"], &Filter::new("psnr").opt("stats_file", files.psnr.display()).into(), &[])
+        .chain(&["d2", "r2"], &Filter::new("ssim").opt("stats_file", files.ssim.display()).into(), &[]);
+    if with_vmaf {
+        let vmaf = Filter::new("libvmaf").opt("log_fmt", "json").opt("log_path", files.vmaf.display());
+        graph.chain(&["d3", "r3"], &vmaf.into(), &[]);
+    }
+    graph.to_string()
+}
+
+pub fn read_report(files: &StatsFiles, with_vmaf: bool) -> QualityReport {
+    QualityReport {
+        psnr: read_key_value_log(&files.psnr, "psnr_avg").map(|scores| summarize(&scores)),
+        ssim: read_key_value_log(&files.ssim, "All").map(|scores| summarize(&scores)),
+        vmaf: if with_vmaf { read_vmaf_log(&files.vmaf).map(|scores| summarize(&scores)) } else { None },
+    }
+}
+
+// psnr and ssim stats files hold one line of "key:value" pairs per frame, e.g.
+// "n:1 mse_avg:3.52 ... psnr_avg:42.67 ..." or "n:1 Y:0.991 U:0.995 V:0.994 All:0.992 (21.2)"
+fn read_key_value_log(path: &Path, key: &str) -> Option<Vec<f64>> {
+    let content = fs::read_to_string(path).ok()?;
+    let scores: Vec<f64> = content.lines()
+        .filter_map(|line| {
+            line.split_whitespace()
+                .filter_map(|pair| pair.split_once(':'))
+                .find(|(k, _)| *k == key)
+                .and_then(|(_, v)| v.parse::<f64>().ok())
+        })
+        .map(|score| if score.is_finite() { score } else { PSNR_CAP_DB })
+        .collect();
+    if scores.is_empty() { None } else { Some(scores) }
+}
+
+#[derive(Deserialize)]
+struct VmafLog {
+    frames: Vec<VmafFrame>,
+}
+
+#[derive(Deserialize)]
+struct VmafFrame {
+    metrics: VmafFrameMetrics,
+}
+
+#[derive(Deserialize)]
+struct VmafFrameMetrics {
+    vmaf: f64,
+}
+
+fn read_vmaf_log(path: &Path) -> Option<Vec<f64>> {
+    let log: VmafLog = serde_json::from_str(&fs::read_to_string(path).ok()?).ok()?;
+    let scores: Vec<f64> = log.frames.into_iter().map(|f| f.metrics.vmaf).collect();
+    if scores.is_empty() { None } else { Some(scores) }
+}
+
+fn summarize(scores: &[f64]) -> MetricSummary {
+    let mut by_score: Vec<FrameScore> = scores.iter().enumerate()
+        .map(|(frame, &score)| FrameScore { frame: frame as u64, score })
+        .collect();
+    by_score.sort_by(|a, b| a.score.total_cmp(&b.score));
+    by_score.truncate(WORST_FRAME_COUNT);
+    MetricSummary {
+        average: scores.iter().sum::<f64>() / scores.len() as f64,
+        min: by_score.first().map_or(0.0, |f| f.score),
+        worst_frames: by_score,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_temp(name: &str, content: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("pressvid-{}-{}", crate::jobs::new_task_id(), name));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn reads_key_value_logs_and_caps_infinite_psnr() {
+        let psnr = write_temp("psnr.log", "\
+n:1 mse_avg:0.00 mse_y:0.00 mse_u:0.00 mse_v:0.00 psnr_avg:inf psnr_y:inf psnr_u:inf psnr_v:inf
+n:2 mse_avg:3.52 mse_y:4.10 mse_u:1.98 mse_v:2.01 psnr_avg:42.67 psnr_y:41.99 psnr_u:45.16 psnr_v:45.09
+");
+        let ssim = write_temp("ssim.log", "n:1 Y:0.991 U:0.995 V:0.994 All:0.992 (21.2)\n");
+        assert_eq!(read_key_value_log(&psnr, "psnr_avg"), Some(vec![PSNR_CAP_DB, 42.67]));
+        assert_eq!(read_key_value_log(&ssim, "All"), Some(vec![0.992]));
+        assert_eq!(read_key_value_log(&ssim, "psnr_avg"), None);
+        let _ = fs::remove_file(psnr);
+        let _ = fs::remove_file(ssim);
+    }
+
+    #[test]
+    fn reads_vmaf_json_log() {
+        let vmaf = write_temp("vmaf.json", r#"{
+            "version": "2.3.1",
+            "frames": [
+                {"frameNum": 0, "metrics": {"integer_motion": 0.0, "vmaf": 97.43}},
+                {"frameNum": 1, "metrics": {"integer_motion": 1.2, "vmaf": 93.1}}
+            ],
+            "pooled_metrics": {"vmaf": {"min": 93.1, "max": 97.43, "mean": 95.265}}
+        }"#);
+        assert_eq!(read_vmaf_log(&vmaf), Some(vec![97.43, 93.1]));
+        let _ = fs::remove_file(&vmaf);
+        assert_eq!(read_vmaf_log(&vmaf), None);
+    }
+
+    #[test]
+    fn summary_lists_the_worst_frames_first() {
+        let scores = [40.0, 38.0, 45.0, 30.0, 41.0, 44.0, 39.0];
+        let summary = summarize(&scores);
+        assert!((summary.average - 277.0 / 7.0).abs() < 1e-9);
+        assert_eq!(summary.min, 30.0);
+        let worst: Vec<(u64, f64)> = summary.worst_frames.iter().map(|f| (f.frame, f.score)).collect();
+        assert_eq!(worst, vec![(3, 30.0), (1, 38.0), (6, 39.0), (0, 40.0), (4, 41.0)]);
+    }
+
+    #[test]
+    fn compare_graph_escapes_stats_paths() {
+        let files = StatsFiles {
+            psnr: PathBuf::from(r"C:\tmp\psnr.log"),
+            ssim: PathBuf::from("/tmp/a,b/ssim.log"),
+            vmaf: PathBuf::from("/tmp/vmaf.json"),
+        };
+        let graph = compare_graph(&files, None, &FilterChain::default(), Some((1280, 720)), false);
+        assert_eq!(graph, concat!(
+            "[0:v]format=yuv420p,setpts=PTS-STARTPTS,split=2[d1][d2];",
+            "[1:v]scale=1280:720:flags=bicubic,format=yuv420p,setpts=PTS-STARTPTS,split=2[r1][r2];",
+            r"[d1][r1]psnr=stats_file=C\\:\\\\tmp\\\\psnr.log;",
+            r"[d2][r2]ssim=stats_file=/tmp/a\,b/ssim.log",
+        ));
+
+        let with_vmaf = compare_graph(&files, None, &FilterChain::default(), None, true);
+        assert!(with_vmaf.contains("split=3[d1][d2][d3]"));
+        assert!(with_vmaf.ends_with("[d3][r3]libvmaf=log_fmt=json:log_path=/tmp/vmaf.json"));
+    }
+}
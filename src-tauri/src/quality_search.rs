@@ -0,0 +1,215 @@
+// CRF search for the "perceptual" preset: short samples of the input are encoded at a few CRF
+// values and scored against the source with ffmpeg's libvmaf or ssim filter, and the highest CRF
+// whose samples still reach the target score is used for the real encode.
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+use crate::error::CompressError;
+use crate::filter_graph::{Filter, FilterChain, FilterGraph};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QualityMetric {
+    #[default]
+    Vmaf, // 0-100, 95 is about visually lossless
+    Ssim, // 0-1
+}
+
+impl QualityMetric {
+    pub fn validate_target(self, target: f64) -> Result<f64, CompressError> {
+        let (min, max) = match self {
+            QualityMetric::Vmaf => (1.0, 100.0),
+            QualityMetric::Ssim => (0.01, 1.0),
+        };
+        if !(min..=max).contains(&target) {
+            return Err(CompressError::invalid_settings("qualityTarget", format!(
+                "{:?} target {} is outside {}-{}", self, target, min, max
+            )));
+        }
+        Ok(target)
+    }
+
+    fn filter_name(self) -> &'static str {
+        match self {
+            QualityMetric::Vmaf => "libvmaf",
+            QualityMetric::Ssim => "ssim",
+        }
+    }
+
+    // Pulls the pooled score out of ffmpeg's log: "VMAF score: 94.81" from libvmaf,
+    // "SSIM Y:0.98 (17.2) U:... All:0.979 (16.8)" from ssim
+    pub fn parse_score(self, stderr_lines: &VecDeque<String>) -> Option<f64> {
+        stderr_lines.iter().rev().find_map(|line| {
+            let value = match self {
+                QualityMetric::Vmaf => line.split_once("VMAF score:")?.1,
+                QualityMetric::Ssim => line.split_once("All:")?.1,
+            };
+            value.split_whitespace().next()?.parse().ok()
+        })
+    }
+
+    // Maps a failed measurement to a clearer error when the ffmpeg build lacks the filter
+    pub fn measure_error(self, error: CompressError) -> CompressError {
+        match &error {
+            CompressError::EncodeFailed { stderr_tail, .. }
+                if stderr_tail.contains(&format!("No such filter: '{}'", self.filter_name())) =>
+            {
+                CompressError::invalid_settings("qualityMetric", format!(
+                    "this ffmpeg build has no {} filter, choose another metric", self.filter_name()
+                ))
+            }
+            _ => error,
+        }
+    }
+}
+
+// Samples are short so the search costs a fraction of the real encode
+const SAMPLE_SECS: f64 = 4.0;
+const SAMPLE_COUNT: usize = 3;
+
+// x264-scale CRF range searched; VideoEncoder::crf_args translates it for other encoders
+const MIN_CRF: u8 = 16;
+const MAX_CRF: u8 = 40;
+
+// Longest possible search: a binary search over MIN_CRF..=MAX_CRF
+pub const MAX_SEARCH_STEPS: usize = 5;
+
+#[derive(Debug, Clone, Copy)]
+pub struct SampleRange {
+    pub start_secs: f64,
+    pub duration_secs: f64,
+}
+
+// Spreads the samples evenly over the input, away from intros and credits at the very ends.
+// Short inputs are measured whole.
+pub fn sample_ranges(duration_secs: f64) -> Vec<SampleRange> {
+    if duration_secs <= SAMPLE_SECS * SAMPLE_COUNT as f64 * 2.0 {
+        return vec![SampleRange { start_secs: 0.0, duration_secs }];
+    }
+    (1..=SAMPLE_COUNT)
+        .map(|i| SampleRange {
+            start_secs: duration_secs * i as f64 / (SAMPLE_COUNT + 1) as f64 - SAMPLE_SECS / 2.0,
+            duration_secs: SAMPLE_SECS,
+        })
+        .collect()
+}
+
+// Filter graph for scoring an encoded sample (input 0) against the source (input 1). The source
+// goes through the same filters as the encode, so frames and dimensions match.
+pub fn compare_graph(metric: QualityMetric, filter_chain: &FilterChain) -> String {
+    let normalized = |chain: FilterChain| chain
+        .then(Filter::new("format").arg("yuv420p"))
+        .then(Filter::new("setpts").arg("PTS-STARTPTS"));
+    let mut graph = FilterGraph::default();
+    graph.chain(&["0:v"], &normalized(FilterChain::default()), &["dist"])
+        .chain(&["1:v"], &normalized(filter_chain.clone()), &["ref"])
+        .chain(&["dist", "ref"], &Filter::new(metric.filter_name()).into(), &[]);
+    graph.to_string()
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CrfChoice {
+    pub crf: u8, // x264 scale
+    pub score: f64,
+    pub met_target: bool, // false when even MIN_CRF falls short and it is used anyway
+}
+
+// Binary search for the highest CRF scoring at least `target`, assuming the score drops as CRF
+// rises. `measure` returns the score of a CRF; `step` is the number of measurements so far.
+pub fn search_crf(
+    target: f64,
+    mut measure: impl FnMut(u8, usize) -> Result<f64, CompressError>,
+) -> Result<CrfChoice, CompressError> {
+    let mut scores: HashMap<u8, f64> = HashMap::new();
+    let (mut low, mut high) = (MIN_CRF, MAX_CRF);
+    let mut best: Option<CrfChoice> = None;
+    while low <= high {
+        let crf = low + (high - low) / 2;
+        let score = measure(crf, scores.len())?;
+        scores.insert(crf, score);
+        if score >= target {
+            best = Some(CrfChoice { crf, score, met_target: true });
+            low = crf + 1;
+        } else {
+            high = crf - 1; // crf >= MIN_CRF > 0
+        }
+    }
+    // Nothing reached the target, so the search narrowed down to MIN_CRF, the best it may use
+    Ok(best.unwrap_or_else(|| CrfChoice {
+        crf: MIN_CRF,
+        score: scores.get(&MIN_CRF).copied().unwrap_or(0.0),
+        met_target: false,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs the search against a score curve, returning the choice and the CRFs it measured
+    fn search(target: f64, score: impl Fn(u8) -> f64) -> (CrfChoice, Vec<u8>) {
+        let mut measured = Vec::new();
+        let choice = search_crf(target, |crf, step| {
+            assert_eq!(step, measured.len());
+            measured.push(crf);
+            Ok(score(crf))
+        }).unwrap();
+        (choice, measured)
+    }
+
+    #[test]
+    fn converges_on_the_highest_crf_meeting_the_target() {
+        let (choice, measured) = search(75.0, |crf| 100.0 - crf as f64);
+        assert_eq!((choice.crf, choice.score, choice.met_target), (25, 75.0, true));
+        assert!(measured.len() <= MAX_SEARCH_STEPS);
+
+        let (choice, measured) = search(10.0, |_| 99.0);
+        assert_eq!((choice.crf, choice.met_target), (MAX_CRF, true));
+        assert!(measured.len() <= MAX_SEARCH_STEPS);
+    }
+
+    #[test]
+    fn falls_back_to_min_crf_when_the_target_is_never_met() {
+        let (choice, measured) = search(95.0, |crf| 90.0 - crf as f64 / 10.0);
+        assert_eq!((choice.crf, choice.met_target), (MIN_CRF, false));
+        assert_eq!(choice.score, 88.4);
+        assert_eq!(measured.last(), Some(&MIN_CRF));
+        assert!(measured.len() <= MAX_SEARCH_STEPS);
+    }
+
+    #[test]
+    fn measurement_errors_end_the_search() {
+        let result = search_crf(90.0, |_, _| Err(CompressError::Cancelled { task_id: "t".to_string() }));
+        assert!(matches!(result, Err(CompressError::Cancelled { .. })));
+    }
+
+    #[test]
+    fn parses_the_last_score_line() {
+        let lines = |lines: &[&str]| lines.iter().map(|line| line.to_string()).collect::<VecDeque<String>>();
+        let vmaf = lines(&["[libvmaf @ 0x55] VMAF score: 91.2", "[Parsed_libvmaf_2 @ 0x55] VMAF score: 94.813602"]);
+        assert_eq!(QualityMetric::Vmaf.parse_score(&vmaf), Some(94.813602));
+        let ssim = lines(&["[Parsed_ssim_2 @ 0x55] SSIM Y:0.981 (17.2) U:0.990 (20.1) V:0.989 (19.8) All:0.984 (17.9)", "video:0kB audio:0kB"]);
+        assert_eq!(QualityMetric::Ssim.parse_score(&ssim), Some(0.984));
+        assert_eq!(QualityMetric::Vmaf.parse_score(&ssim), None);
+    }
+
+    #[test]
+    fn samples_spread_over_long_inputs() {
+        let whole = sample_ranges(20.0);
+        assert_eq!(whole.len(), 1);
+        assert_eq!((whole[0].start_secs, whole[0].duration_secs), (0.0, 20.0));
+
+        let spread: Vec<(f64, f64)> = sample_ranges(120.0).iter().map(|s| (s.start_secs, s.duration_secs)).collect();
+        assert_eq!(spread, vec![(28.0, 4.0), (58.0, 4.0), (88.0, 4.0)]);
+    }
+
+    #[test]
+    fn compare_graph_filters_the_source_like_the_encode() {
+        let chain = FilterChain::from(Filter::new("scale").arg(1280).arg(720));
+        assert_eq!(compare_graph(QualityMetric::Ssim, &chain), concat!(
+            "[0:v]format=yuv420p,setpts=PTS-STARTPTS[dist];",
+            "[1:v]scale=1280:720,format=yuv420p,setpts=PTS-STARTPTS[ref];",
+            "[dist][ref]ssim",
+        ));
+    }
+}
@@ -0,0 +1,537 @@
+// Backend job queue: runs compressions with a bounded number of parallel workers and persists
+// unfinished jobs to the app data directory so they survive a restart.
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{Emitter, Manager};
+
+use crate::error::CompressError;
+use crate::output_naming::CollisionPolicy;
+use crate::{AudioCompressionSettings, CompressionResult, CompressionSettings, ImageCompressionSettings};
+
+const QUEUE_FILE_NAME: &str = "queue.json";
+const DEFAULT_MAX_PARALLEL: usize = 2;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "settings", rename_all = "lowercase")]
+pub enum JobSettings {
+    Video(Box<CompressionSettings>), // Boxed, it is much larger than the other settings
+    Audio(AudioCompressionSettings),
+    Image(ImageCompressionSettings),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Paused,
+    Running,
+    Completed,
+    Failed,
+}
+
+impl JobStatus {
+    fn is_finished(self) -> bool {
+        matches!(self, JobStatus::Completed | JobStatus::Failed)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuedJob {
+    pub id: String, // Also the task id carried by this job's progress and completion events
+    #[serde(rename = "inputPath")]
+    pub input_path: String,
+    #[serde(rename = "outputPath")]
+    pub output_path: String,
+    pub settings: JobSettings,
+    pub status: JobStatus,
+    #[serde(default)]
+    pub message: Option<String>, // Error text once the job has failed
+    #[serde(default)]
+    pub error: Option<CompressError>, // Set when the job failed, for the frontend to branch on
+    #[serde(default)]
+    pub result: Option<CompressionResult>, // Set when the job completed
+    #[serde(default, rename = "batchId")]
+    pub batch_id: Option<String>, // Set on jobs queued together by compress_folder
+    #[serde(default)]
+    pub collision: CollisionPolicy, // Applied to output_path when the job starts
+    #[serde(default, rename = "outputClaimed")]
+    pub output_claimed: bool, // output_path has been resolved and is this job's own file
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueSnapshot {
+    pub jobs: Vec<QueuedJob>,
+    #[serde(rename = "maxParallel")]
+    pub max_parallel: usize,
+    pub paused: bool,
+}
+
+// Queue state changes, kept apart from locking, persistence and events
+impl QueueSnapshot {
+    // Jobs that were running when the app quit are queued again, with their partial output removed
+    // so the retry can write the same name; finished jobs are dropped
+    fn recover_interrupted(&mut self) {
+        self.jobs.retain(|job| !job.status.is_finished());
+        for job in self.jobs.iter_mut().filter(|job| job.status == JobStatus::Running) {
+            if job.output_claimed {
+                match fs::remove_file(&job.output_path) {
+                    Ok(()) => println!("Removed partial output of interrupted job {}: {}", job.id, job.output_path),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => eprintln!("Failed to remove partial output {}: {}", job.output_path, e),
+                }
+                job.output_claimed = false;
+            }
+            job.status = JobStatus::Queued;
+        }
+        self.max_parallel = self.max_parallel.max(1);
+    }
+
+    // Marks queued jobs as running up to the worker limit and returns them for starting
+    fn start_ready(&mut self) -> Vec<QueuedJob> {
+        if self.paused {
+            return Vec::new();
+        }
+        let running = self.jobs.iter().filter(|job| job.status == JobStatus::Running).count();
+        let free_slots = self.max_parallel.saturating_sub(running);
+        let mut started = Vec::new();
+        for job in self.jobs.iter_mut().filter(|job| job.status == JobStatus::Queued).take(free_slots) {
+            job.status = JobStatus::Running;
+            started.push(job.clone());
+        }
+        started
+    }
+
+    fn job_mut(&mut self, job_id: &str) -> Result<&mut QueuedJob, CompressError> {
+        self.jobs.iter_mut().find(|job| job.id == job_id)
+            .ok_or_else(|| CompressError::TaskNotFound { task_id: job_id.to_string() })
+    }
+
+    fn reorder(&mut self, job_id: &str, new_index: usize) -> Result<(), CompressError> {
+        let current = self.jobs.iter().position(|job| job.id == job_id)
+            .ok_or_else(|| CompressError::TaskNotFound { task_id: job_id.to_string() })?;
+        let job = self.jobs.remove(current);
+        let index = new_index.min(self.jobs.len());
+        self.jobs.insert(index, job);
+        Ok(())
+    }
+
+    fn remove(&mut self, job_id: &str) -> Result<QueuedJob, CompressError> {
+        let index = self.jobs.iter().position(|job| job.id == job_id)
+            .ok_or_else(|| CompressError::TaskNotFound { task_id: job_id.to_string() })?;
+        if self.jobs[index].status == JobStatus::Running {
+            return Err(CompressError::TaskConflict { task_id: job_id.to_string(), reason: "running, cancel it first".to_string() });
+        }
+        Ok(self.jobs.remove(index))
+    }
+
+    fn take_waiting(&mut self, job_id: &str) -> Option<QueuedJob> {
+        let waiting = |job: &QueuedJob| job.id == job_id && matches!(job.status, JobStatus::Queued | JobStatus::Paused);
+        self.jobs.iter().position(waiting).map(|index| self.jobs.remove(index))
+    }
+
+    fn pause(&mut self, job_id: &str) -> Result<(), CompressError> {
+        let job = self.job_mut(job_id)?;
+        if job.status != JobStatus::Queued {
+            return Err(CompressError::TaskConflict { task_id: job_id.to_string(), reason: "only queued jobs can be paused".to_string() });
+        }
+        job.status = JobStatus::Paused;
+        Ok(())
+    }
+
+    fn resume(&mut self, job_id: &str) -> Result<(), CompressError> {
+        let job = self.job_mut(job_id)?;
+        if job.status != JobStatus::Paused {
+            return Err(CompressError::TaskConflict { task_id: job_id.to_string(), reason: "not paused".to_string() });
+        }
+        job.status = JobStatus::Queued;
+        Ok(())
+    }
+
+    fn set_max_parallel(&mut self, max_parallel: usize) -> Result<(), CompressError> {
+        if max_parallel == 0 {
+            return Err(CompressError::invalid_settings("maxParallel", "at least one parallel job is required"));
+        }
+        self.max_parallel = max_parallel;
+        Ok(())
+    }
+}
+
+pub struct JobQueue {
+    state: Mutex<QueueSnapshot>,
+    persist_path: Option<PathBuf>,
+}
+
+impl JobQueue {
+    // Loads the persisted queue and recovers the jobs an earlier run left unfinished
+    pub fn load(data_dir: Option<PathBuf>) -> Self {
+        let persist_path = data_dir.map(|dir| dir.join(QUEUE_FILE_NAME));
+        let mut snapshot = persist_path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|json| match serde_json::from_str::<QueueSnapshot>(&json) {
+                Ok(snapshot) => Some(snapshot),
+                Err(e) => {
+                    eprintln!("Ignoring unreadable job queue file: {}", e);
+                    None
+                }
+            })
+            .unwrap_or(QueueSnapshot { jobs: Vec::new(), max_parallel: DEFAULT_MAX_PARALLEL, paused: false });
+
+        snapshot.recover_interrupted();
+        println!("Loaded job queue with {} unfinished job(s)", snapshot.jobs.len());
+
+        JobQueue { state: Mutex::new(snapshot), persist_path }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, QueueSnapshot> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn snapshot(&self) -> QueueSnapshot {
+        self.lock().clone()
+    }
+
+    fn save(&self, snapshot: &QueueSnapshot) {
+        let Some(path) = &self.persist_path else { return };
+        if let Some(dir) = path.parent() {
+            if let Err(e) = fs::create_dir_all(dir) {
+                eprintln!("Failed to create app data directory {}: {}", dir.display(), e);
+                return;
+            }
+        }
+        match serde_json::to_string_pretty(snapshot) {
+            Ok(json) => {
+                // Written aside and renamed over the old file, so a crash mid-write can't leave a
+                // truncated queue behind
+                let temp_path = path.with_extension("json.tmp");
+                if let Err(e) = fs::write(&temp_path, json).and_then(|_| fs::rename(&temp_path, path)) {
+                    eprintln!("Failed to persist job queue to {}: {}", path.display(), e);
+                }
+            }
+            Err(e) => eprintln!("Failed to serialize job queue: {}", e),
+        }
+    }
+
+    // Applies a change to the queue, persists it and notifies the frontend
+    fn update<T>(&self, app_handle: &tauri::AppHandle, change: impl FnOnce(&mut QueueSnapshot) -> T) -> T {
+        let (result, snapshot) = {
+            let mut state = self.lock();
+            let result = change(&mut state);
+            // Saved under the lock, so jobs finishing at the same time can't interleave their writes
+            self.save(&state);
+            (result, state.clone())
+        };
+        app_handle.emit("QUEUE_UPDATED_EVENT", snapshot).unwrap_or_else(|e| eprintln!("Failed to emit queue update: {}", e));
+        result
+    }
+}
+
+// Starts queued jobs until the worker limit is reached
+pub fn dispatch(app_handle: &tauri::AppHandle) {
+    let queue = app_handle.state::<JobQueue>();
+    let to_start = queue.update(app_handle, QueueSnapshot::start_ready);
+
+    for job in to_start {
+        println!("Queue: starting job {} ({})", job.id, job.input_path);
+        // A job blocks on ffmpeg for its whole run, so it gets a blocking thread rather than one
+        // of the async workers that serve commands
+        let app_handle = app_handle.clone();
+        tauri::async_runtime::spawn_blocking(move || run_job(app_handle, job));
+    }
+}
+
+fn run_job(app_handle: tauri::AppHandle, job: QueuedJob) {
+    let batch_id = job.batch_id.clone();
+    let queue = app_handle.state::<JobQueue>();
+    let result = match create_output_folder(&job.output_path).and_then(|()| crate::register_job(&app_handle, &job.id, &job.input_path, &job.output_path, job.collision)) {
+        Ok(guard) => {
+            let output = guard.job.output_path().to_string();
+            // Recorded before ffmpeg starts, so after a crash the file can be told apart from one
+            // that was there before
+            queue.update(&app_handle, |state| {
+                if let Some(entry) = state.jobs.iter_mut().find(|entry| entry.id == job.id) {
+                    entry.output_path = output.clone();
+                    entry.output_claimed = true;
+                }
+            });
+            let (app, id, input) = (app_handle.clone(), job.id.clone(), job.input_path);
+            match job.settings {
+                JobSettings::Video(settings) => crate::run_registered_task(guard, || crate::run_video_compression(app, id, input, output, *settings)),
+                JobSettings::Audio(settings) => crate::run_registered_task(guard, || crate::run_audio_compression(app, id, input, output, settings)),
+                JobSettings::Image(settings) => crate::run_registered_task(guard, || crate::run_image_compression(app, id, input, output, settings)),
+            }
+        }
+        // Failed before it started, e.g. on the collision policy; the frontend still waits for an event
+        Err(error) => {
+            crate::emit_task_failed(&app_handle, &job.id, &error);
+            Err(error)
+        }
+    };
+
+    queue.update(&app_handle, |state| {
+        if let Some(entry) = state.jobs.iter_mut().find(|entry| entry.id == job.id) {
+            match result {
+                Ok(result) => {
+                    entry.status = JobStatus::Completed;
+                    entry.result = Some(result);
+                }
+                Err(error) => {
+                    entry.status = JobStatus::Failed;
+                    entry.message = Some(error.to_string());
+                    entry.error = Some(error);
+                }
+            }
+        }
+    });
+    if let Some(batch_id) = batch_id {
+        crate::batch::report_if_finished(&app_handle, &batch_id);
+    }
+    dispatch(&app_handle);
+}
+
+// Batch outputs go into a mirrored folder tree, which is only created once a job gets to run
+fn create_output_folder(output_path: &str) -> Result<(), CompressError> {
+    match Path::new(output_path).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => fs::create_dir_all(parent)
+            .map_err(|e| CompressError::io(&format!("Could not create output folder {}", parent.display()), e)),
+        _ => Ok(()),
+    }
+}
+
+#[tauri::command]
+pub fn enqueue_job(
+    app_handle: tauri::AppHandle,
+    input_path: String,
+    output_path: String,
+    settings: JobSettings,
+    collision: Option<CollisionPolicy>,
+) -> Result<String, CompressError> {
+    Ok(enqueue(&app_handle, input_path, output_path, settings, collision.unwrap_or_default()))
+}
+
+// Queues a single job and returns its id, which is also the task id carried by its events
+pub fn enqueue(
+    app_handle: &tauri::AppHandle,
+    input_path: String,
+    output_path: String,
+    settings: JobSettings,
+    collision: CollisionPolicy,
+) -> String {
+    let job = QueuedJob {
+        id: crate::jobs::new_task_id(),
+        input_path,
+        output_path,
+        settings,
+        status: JobStatus::Queued,
+        message: None,
+        error: None,
+        result: None,
+        batch_id: None,
+        collision,
+        output_claimed: false,
+    };
+    let job_id = job.id.clone();
+    println!("Queue: enqueued job {} ({})", job_id, job.input_path);
+    enqueue_all(app_handle, vec![job]);
+    job_id
+}
+
+// Adds jobs to the end of the queue in one update and starts as many as the worker limit allows
+pub fn enqueue_all(app_handle: &tauri::AppHandle, jobs: Vec<QueuedJob>) {
+    app_handle.state::<JobQueue>().update(app_handle, |state| state.jobs.extend(jobs));
+    dispatch(app_handle);
+}
+
+#[tauri::command]
+pub fn list_jobs(app_handle: tauri::AppHandle) -> QueueSnapshot {
+    app_handle.state::<JobQueue>().snapshot()
+}
+
+// Moves a job to a new position in the queue. Only the order of jobs that have not started matters.
+#[tauri::command]
+pub fn reorder_job(app_handle: tauri::AppHandle, job_id: String, new_index: usize) -> Result<(), CompressError> {
+    app_handle.state::<JobQueue>().update(&app_handle, |state| state.reorder(&job_id, new_index))
+}
+
+// Removes a job that is not running. Use cancel_task to stop a running one.
+#[tauri::command]
+pub fn remove_job(app_handle: tauri::AppHandle, job_id: String) -> Result<(), CompressError> {
+    let removed = app_handle.state::<JobQueue>().update(&app_handle, |state| state.remove(&job_id))?;
+    // A finished job was already counted when it finished
+    if !removed.status.is_finished() {
+        report_removed_from_batch(&app_handle, &removed);
+    }
+    Ok(())
+}
+
+// Takes a job that has not started out of the queue. Returns false when no such job is waiting.
+pub fn cancel_waiting(app_handle: &tauri::AppHandle, job_id: &str) -> bool {
+    let removed = app_handle.state::<JobQueue>().update(app_handle, |state| state.take_waiting(job_id));
+    match removed {
+        Some(job) => {
+            report_removed_from_batch(app_handle, &job);
+            true
+        }
+        None => false,
+    }
+}
+
+// Taking the last unfinished job out of a batch finishes the batch too
+fn report_removed_from_batch(app_handle: &tauri::AppHandle, removed: &QueuedJob) {
+    if let Some(batch_id) = &removed.batch_id {
+        crate::batch::report_if_finished(app_handle, batch_id);
+    }
+}
+
+// Holds a queued job back until it is resumed
+#[tauri::command]
+pub fn pause_job(app_handle: tauri::AppHandle, job_id: String) -> Result<(), CompressError> {
+    app_handle.state::<JobQueue>().update(&app_handle, |state| state.pause(&job_id))
+}
+
+#[tauri::command]
+pub fn resume_job(app_handle: tauri::AppHandle, job_id: String) -> Result<(), CompressError> {
+    app_handle.state::<JobQueue>().update(&app_handle, |state| state.resume(&job_id))?;
+    dispatch(&app_handle);
+    Ok(())
+}
+
+// Stops starting new jobs. Jobs that are already running are left to finish.
+#[tauri::command]
+pub fn pause_queue(app_handle: tauri::AppHandle) {
+    app_handle.state::<JobQueue>().update(&app_handle, |state| state.paused = true);
+}
+
+#[tauri::command]
+pub fn resume_queue(app_handle: tauri::AppHandle) {
+    app_handle.state::<JobQueue>().update(&app_handle, |state| state.paused = false);
+    dispatch(&app_handle);
+}
+
+#[tauri::command]
+pub fn set_max_parallel_jobs(app_handle: tauri::AppHandle, max_parallel: usize) -> Result<(), CompressError> {
+    app_handle.state::<JobQueue>().update(&app_handle, |state| state.set_max_parallel(max_parallel))?;
+    dispatch(&app_handle);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(id: &str, status: JobStatus) -> QueuedJob {
+        QueuedJob {
+            id: id.to_string(),
+            input_path: format!("/in/{}.wav", id),
+            output_path: format!("/out/{}.m4a", id),
+            settings: JobSettings::Audio(AudioCompressionSettings { quality: "medium".to_string(), format: None }),
+            status,
+            message: None,
+            error: None,
+            result: None,
+            batch_id: None,
+            collision: CollisionPolicy::default(),
+            output_claimed: false,
+        }
+    }
+
+    fn queue(jobs: Vec<QueuedJob>, max_parallel: usize) -> QueueSnapshot {
+        QueueSnapshot { jobs, max_parallel, paused: false }
+    }
+
+    fn ids(snapshot: &QueueSnapshot) -> Vec<&str> {
+        snapshot.jobs.iter().map(|job| job.id.as_str()).collect()
+    }
+
+    fn statuses(snapshot: &QueueSnapshot) -> Vec<JobStatus> {
+        snapshot.jobs.iter().map(|job| job.status).collect()
+    }
+
+    #[test]
+    fn starts_queued_jobs_up_to_the_limit() {
+        use JobStatus::*;
+        let mut state = queue(vec![job("a", Running), job("b", Paused), job("c", Queued), job("d", Queued), job("e", Queued)], 3);
+        let started: Vec<String> = state.start_ready().into_iter().map(|job| job.id).collect();
+        assert_eq!(started, vec!["c", "d"]);
+        assert_eq!(statuses(&state), vec![Running, Paused, Running, Running, Queued]);
+        assert!(state.start_ready().is_empty());
+
+        state.set_max_parallel(4).unwrap();
+        assert_eq!(state.start_ready().len(), 1);
+        assert!(state.set_max_parallel(0).is_err());
+        assert_eq!(state.max_parallel, 4);
+    }
+
+    #[test]
+    fn paused_queue_starts_nothing() {
+        let mut state = queue(vec![job("a", JobStatus::Queued)], 2);
+        state.paused = true;
+        assert!(state.start_ready().is_empty());
+        assert_eq!(state.jobs[0].status, JobStatus::Queued);
+    }
+
+    #[test]
+    fn reorder_moves_a_job() {
+        let mut state = queue(vec![job("a", JobStatus::Queued), job("b", JobStatus::Queued), job("c", JobStatus::Queued)], 1);
+        state.reorder("c", 0).unwrap();
+        assert_eq!(ids(&state), vec!["c", "a", "b"]);
+        state.reorder("c", 99).unwrap();
+        assert_eq!(ids(&state), vec!["a", "b", "c"]);
+        assert!(matches!(state.reorder("x", 0), Err(CompressError::TaskNotFound { .. })));
+    }
+
+    #[test]
+    fn pause_and_resume_only_waiting_jobs() {
+        let mut state = queue(vec![job("a", JobStatus::Queued), job("b", JobStatus::Running)], 1);
+        state.pause("a").unwrap();
+        assert_eq!(state.jobs[0].status, JobStatus::Paused);
+        assert!(matches!(state.pause("a"), Err(CompressError::TaskConflict { .. })));
+        assert!(matches!(state.pause("b"), Err(CompressError::TaskConflict { .. })));
+        state.resume("a").unwrap();
+        assert_eq!(state.jobs[0].status, JobStatus::Queued);
+        assert!(matches!(state.resume("a"), Err(CompressError::TaskConflict { .. })));
+        assert!(matches!(state.resume("x"), Err(CompressError::TaskNotFound { .. })));
+    }
+
+    #[test]
+    fn running_jobs_are_not_removed() {
+        let mut state = queue(vec![job("a", JobStatus::Running), job("b", JobStatus::Paused), job("c", JobStatus::Failed)], 1);
+        assert!(matches!(state.remove("a"), Err(CompressError::TaskConflict { .. })));
+        assert!(state.take_waiting("a").is_none());
+        assert!(state.take_waiting("c").is_none());
+        assert_eq!(state.take_waiting("b").map(|job| job.id).as_deref(), Some("b"));
+        assert_eq!(state.remove("c").unwrap().id, "c");
+        assert_eq!(ids(&state), vec!["a"]);
+    }
+
+    #[test]
+    fn load_requeues_interrupted_jobs_and_removes_their_partial_output() {
+        let dir = std::env::temp_dir().join(format!("pressvid-queue-{}", crate::jobs::new_task_id()));
+        fs::create_dir_all(&dir).unwrap();
+        let output = |name: &str| dir.join(name).to_string_lossy().into_owned();
+
+        let mut claimed = job("claimed", JobStatus::Running);
+        claimed.output_path = output("claimed.m4a");
+        claimed.output_claimed = true;
+        // Never got to resolve its output, so the file there belongs to someone else
+        let mut unclaimed = job("unclaimed", JobStatus::Running);
+        unclaimed.output_path = output("unclaimed.m4a");
+        for path in [&claimed.output_path, &unclaimed.output_path] {
+            fs::write(path, b"partial").unwrap();
+        }
+        let persisted = queue(vec![job("done", JobStatus::Completed), claimed, unclaimed, job("paused", JobStatus::Paused)], 0);
+        fs::write(dir.join(QUEUE_FILE_NAME), serde_json::to_string(&persisted).unwrap()).unwrap();
+
+        let loaded = JobQueue::load(Some(dir.clone())).snapshot();
+        assert_eq!(ids(&loaded), vec!["claimed", "unclaimed", "paused"]);
+        assert_eq!(statuses(&loaded), vec![JobStatus::Queued, JobStatus::Queued, JobStatus::Paused]);
+        assert!(!loaded.jobs[0].output_claimed);
+        assert_eq!(loaded.max_parallel, 1);
+        assert!(!Path::new(&output("claimed.m4a")).exists());
+        assert!(Path::new(&output("unclaimed.m4a")).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,157 @@
+// Output resolution planning: turns the resolution and scale settings into exact output
+// dimensions from the probed source size, keeping its aspect ratio, never upscaling, and keeping
+// both sides even as 4:2:0 encoders require.
+use crate::error::CompressError;
+use crate::filter_graph::Filter;
+use crate::probe::VideoStreamInfo;
+use crate::CompressionSettings;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResolutionTarget {
+    Original,
+    ShortEdge(u32), // "720p": 1280x720 for landscape, 720x1280 for portrait phone videos
+    Height(u32),    // "h720"
+    Width(u32),     // "w1280"
+    LongEdge(u32),  // "l1920"
+    Fit { width: u32, height: u32 }, // "1280x720": the largest size inside the box
+    Percentage(f32), // The "scale" preset
+}
+
+impl ResolutionTarget {
+    // Reads the target from the settings the way the presets use them: the resolution applies to
+    // "quality" presets and custom settings, the percentage to the "scale" preset.
+    pub fn from_settings(settings: &CompressionSettings) -> Result<Self, CompressError> {
+        if settings.preset_type == "scale" {
+            let percentage = settings.scale_percentage.as_deref()
+                .and_then(|p| p.trim().trim_end_matches('%').parse::<f32>().ok())
+                .filter(|p| *p > 0.0 && *p <= 100.0)
+                .ok_or_else(|| CompressError::invalid_settings("scalePercentage", format!(
+                    "'{}' is not a percentage between 0 and 100", settings.scale_percentage.as_deref().unwrap_or("")
+                )))?;
+            return Ok(ResolutionTarget::Percentage(percentage));
+        }
+        if settings.custom_settings || settings.preset_type == "quality" {
+            return Self::parse(&settings.resolution);
+        }
+        Ok(ResolutionTarget::Original)
+    }
+
+    pub fn parse(resolution: &str) -> Result<Self, CompressError> {
+        let value = resolution.trim().to_ascii_lowercase();
+        let invalid = || CompressError::invalid_settings("resolution", format!(
+            "'{}' is not a resolution, use original, 720p, h720, w1280, l1920 or 1280x720", resolution
+        ));
+        let size = |digits: &str| digits.parse::<u32>().ok().filter(|n| *n >= 2).ok_or_else(invalid);
+        if value.is_empty() || value == "original" {
+            return Ok(ResolutionTarget::Original);
+        }
+        if let Some((width, height)) = value.split_once('x') {
+            return Ok(ResolutionTarget::Fit { width: size(width)?, height: size(height)? });
+        }
+        if let Some(digits) = value.strip_suffix('p') {
+            return Ok(ResolutionTarget::ShortEdge(size(digits)?));
+        }
+        if let Some(digits) = value.strip_prefix('h') {
+            return Ok(ResolutionTarget::Height(size(digits)?));
+        }
+        if let Some(digits) = value.strip_prefix('w') {
+            return Ok(ResolutionTarget::Width(size(digits)?));
+        }
+        if let Some(digits) = value.strip_prefix('l') {
+            return Ok(ResolutionTarget::LongEdge(size(digits)?));
+        }
+        Err(invalid())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResolutionPlan {
+    pub width: u32,
+    pub height: u32,
+    pub scaled: bool, // false when the source is encoded at its own size
+}
+
+impl ResolutionPlan {
+    pub fn scale_filter(&self) -> Option<Filter> {
+        self.scaled.then(|| Filter::new("scale").arg(self.width).arg(self.height).opt("flags", "lanczos"))
+    }
+}
+
+// Dimensions as displayed: ffmpeg applies the rotation tag before any filter runs
+pub fn display_size(source: &VideoStreamInfo) -> (u32, u32) {
+    if source.rotation % 180 == 90 { (source.height, source.width) } else { (source.width, source.height) }
+}
+
+// `frame` is the displayed size going into the scaler, after any crop
+pub fn plan(target: ResolutionTarget, frame: (u32, u32)) -> ResolutionPlan {
+    let (width, height) = frame;
+    let (w, h) = (width.max(1) as f64, height.max(1) as f64);
+    let factor = match target {
+        ResolutionTarget::Original => 1.0,
+        ResolutionTarget::ShortEdge(edge) => edge as f64 / w.min(h),
+        ResolutionTarget::Height(size) => size as f64 / h,
+        ResolutionTarget::Width(size) => size as f64 / w,
+        ResolutionTarget::LongEdge(edge) => edge as f64 / w.max(h),
+        ResolutionTarget::Fit { width, height } => (width as f64 / w).min(height as f64 / h),
+        ResolutionTarget::Percentage(p) => p as f64 / 100.0,
+    }
+    .min(1.0);
+
+    // Rounding never goes past the source, so "original" trims an odd size rather than adding a line
+    let planned = (even(w * factor).min(even_floor(width)), even(h * factor).min(even_floor(height)));
+    ResolutionPlan { width: planned.0, height: planned.1, scaled: planned != (width, height) }
+}
+
+// Nearest even size, at least 2
+fn even(size: f64) -> u32 {
+    ((size / 2.0).round() as u32 * 2).max(2)
+}
+
+fn even_floor(size: u32) -> u32 {
+    (size / 2 * 2).max(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn size(plan: ResolutionPlan) -> (u32, u32, bool) {
+        (plan.width, plan.height, plan.scaled)
+    }
+
+    #[test]
+    fn short_edge_follows_orientation() {
+        assert_eq!(size(plan(ResolutionTarget::ShortEdge(720), (1920, 1080))), (1280, 720, true));
+        assert_eq!(size(plan(ResolutionTarget::ShortEdge(720), (1080, 1920))), (720, 1280, true));
+    }
+
+    #[test]
+    fn never_upscales() {
+        assert_eq!(size(plan(ResolutionTarget::ShortEdge(1080), (640, 360))), (640, 360, false));
+        assert_eq!(size(plan(ResolutionTarget::Width(3840), (1920, 1080))), (1920, 1080, false));
+    }
+
+    #[test]
+    fn sizes_are_even_and_inside_the_source() {
+        assert_eq!(size(plan(ResolutionTarget::Original, (1281, 721))), (1280, 720, true));
+        assert_eq!(size(plan(ResolutionTarget::Height(480), (1920, 1080))), (854, 480, true));
+        assert_eq!(size(plan(ResolutionTarget::Percentage(50.0), (1920, 1080))), (960, 540, true));
+    }
+
+    #[test]
+    fn fit_uses_the_tighter_side() {
+        assert_eq!(size(plan(ResolutionTarget::Fit { width: 1280, height: 720 }, (1920, 1440))), (960, 720, true));
+        assert_eq!(size(plan(ResolutionTarget::LongEdge(1280), (1080, 1920))), (720, 1280, true));
+    }
+
+    #[test]
+    fn parses_resolution_names() {
+        assert_eq!(ResolutionTarget::parse(" 720P ").unwrap(), ResolutionTarget::ShortEdge(720));
+        assert_eq!(ResolutionTarget::parse("1280x720").unwrap(), ResolutionTarget::Fit { width: 1280, height: 720 });
+        assert_eq!(ResolutionTarget::parse("l1920").unwrap(), ResolutionTarget::LongEdge(1920));
+        assert_eq!(ResolutionTarget::parse("").unwrap(), ResolutionTarget::Original);
+        assert!(ResolutionTarget::parse("h1").is_err());
+        assert!(ResolutionTarget::parse("1280x").is_err());
+        assert!(ResolutionTarget::parse("big").is_err());
+    }
+}
@@ -0,0 +1,182 @@
+// Bitrate planning for the "targetSize" preset: splits a size budget between video, audio and
+// container overhead, and corrects the video bitrate when an encode still comes out too big.
+use crate::error::CompressError;
+use crate::probe::MediaInfo;
+use crate::video_codec::Container;
+
+// Sizes are entered in MB as file managers show them (1 MB = 1024 * 1024 bytes), while encoder
+// bitrates are decimal (1 kbps = 1000 bit/s). Everything below works in bits and bit/s.
+const BYTES_PER_MB: f64 = 1024.0 * 1024.0;
+
+// Give up on encodes that would need less video bitrate than this
+const MIN_VIDEO_BITRATE_BPS: f64 = 10_000.0;
+
+// Headroom for rate control, which lands a few percent either side of the requested bitrate
+const RATE_CONTROL_MARGIN: f64 = 0.03;
+
+#[derive(Debug, Clone, Copy)]
+pub struct TargetSizePlan {
+    pub target_bytes: u64,
+    pub video_bitrate_bps: u64,
+    pub audio_bitrate_kbps: Option<u32>, // None when the source has no audio and the output gets none
+    pub duration_secs: f64, // Output duration the budget is spread over
+}
+
+impl TargetSizePlan {
+    pub fn video_bitrate_arg(&self) -> String {
+        format!("{}", self.video_bitrate_bps)
+    }
+
+    // Lowers the video bitrate after an encode overshot the target. Audio and overhead come out as
+    // planned, so the whole excess is taken from the video budget.
+    pub fn corrected(&self, actual_bytes: u64) -> Result<TargetSizePlan, CompressError> {
+        let excess_bps = actual_bytes.saturating_sub(self.target_bytes) as f64 * 8.0 / self.duration_secs;
+        // Less the margin again, so the retry lands under the target
+        let video_bitrate = (self.video_bitrate_bps as f64 - excess_bps) * (1.0 - RATE_CONTROL_MARGIN);
+        if video_bitrate < MIN_VIDEO_BITRATE_BPS || actual_bytes <= self.target_bytes {
+            return Err(CompressError::invalid_settings("targetSizeMB", format!(
+                "output is {} bytes, over the {} byte target, and cannot be corrected further",
+                actual_bytes, self.target_bytes
+            )));
+        }
+        Ok(TargetSizePlan { video_bitrate_bps: video_bitrate as u64, ..*self })
+    }
+}
+
+// Audio bitrate for the "low"/"medium"/"high" setting, sized for the source's channel count and
+// never above what the source already has.
+fn plan_audio_bitrate(media: &MediaInfo, audio_quality: &str) -> Option<u32> {
+    let source = media.audio_streams.first()?; // The encodes map the first audio stream explicitly
+    let stereo_kbps: u32 = match audio_quality { "low" => 96, "high" => 192, _ => 128 };
+    let kbps = match source.channels {
+        0 | 2 => stereo_kbps,
+        1 => (stereo_kbps / 2).max(48),
+        channels => stereo_kbps * channels / 2,
+    };
+    match source.bit_rate {
+        Some(source_bps) if source_bps > 0 => Some(kbps.min(((source_bps / 1000) as u32).max(32))),
+        _ => Some(kbps),
+    }
+}
+
+// Mux overhead of the container: per-packet headers grow with duration, every video frame adds an
+// index entry or block header, plus a fixed header/index
+fn container_overhead_bits(container: Container, duration_secs: f64, frame_rate: f64, total_bits: f64) -> f64 {
+    let (fraction, bytes_per_frame) = match container {
+        Container::Mp4 | Container::Mov => (0.008, 12.0), // stsz, stts and ctts entries
+        Container::Mkv | Container::Webm => (0.006, 8.0), // SimpleBlock header
+    };
+    let fixed_bits = 64.0 * 1024.0 * 8.0;
+    let frame_bits = duration_secs * frame_rate * bytes_per_frame * 8.0;
+    total_bits * fraction + fixed_bits + frame_bits + duration_secs * 256.0 // ~32 bytes/s of timestamps and cues
+}
+
+// `frame_rate` is the output's, after any frame rate conversion
+pub fn plan(
+    target_size_mb: f64,
+    duration_secs: f64,
+    frame_rate: f64,
+    media: &MediaInfo,
+    audio_quality: &str,
+    container: Container,
+) -> Result<TargetSizePlan, CompressError> {
+    if target_size_mb <= 0.0 {
+        return Err(CompressError::invalid_settings("targetSizeMB", format!("{}MB must be greater than 0", target_size_mb)));
+    }
+    if duration_secs <= 0.0 {
+        return Err(CompressError::invalid_settings("targetSizeMB", "source duration is unknown"));
+    }
+
+    let target_bytes = (target_size_mb * BYTES_PER_MB) as u64;
+    let total_bits = target_bytes as f64 * 8.0;
+    let audio_bitrate_kbps = plan_audio_bitrate(media, audio_quality);
+    let audio_bits = audio_bitrate_kbps.map_or(0.0, |kbps| kbps as f64 * 1000.0 * duration_secs);
+    let overhead_bits = container_overhead_bits(container, duration_secs, frame_rate, total_bits);
+    let video_bits = (total_bits * (1.0 - RATE_CONTROL_MARGIN)) - audio_bits - overhead_bits;
+
+    let video_bitrate = video_bits / duration_secs;
+    if video_bitrate < MIN_VIDEO_BITRATE_BPS {
+        return Err(CompressError::invalid_settings("targetSizeMB", format!(
+            "{}MB leaves only {:.1}kbps for video after {}kbps audio over {:.1}s. Try a larger target size or lower audio quality.",
+            target_size_mb, (video_bitrate / 1000.0).max(0.0), audio_bitrate_kbps.unwrap_or(0), duration_secs
+        )));
+    }
+
+    Ok(TargetSizePlan { target_bytes, video_bitrate_bps: video_bitrate as u64, audio_bitrate_kbps, duration_secs })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::AudioStreamInfo;
+
+    fn media(audio: &[(u32, Option<u64>)]) -> MediaInfo {
+        MediaInfo {
+            format_name: "mov,mp4,m4a,3gp,3g2,mj2".to_string(),
+            duration_secs: Some(60.0),
+            size_bytes: None,
+            bit_rate: None,
+            video_streams: Vec::new(),
+            audio_streams: audio.iter().enumerate().map(|(index, &(channels, bit_rate))| AudioStreamInfo {
+                index: index as u32 + 1, codec: "aac".to_string(), channels, channel_layout: None,
+                sample_rate: Some(48000), bit_rate, language: None,
+            }).collect(),
+            subtitle_streams: Vec::new(),
+            chapters: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn audio_bitrate_follows_channels() {
+        assert_eq!(plan_audio_bitrate(&media(&[(2, None)]), "medium"), Some(128));
+        assert_eq!(plan_audio_bitrate(&media(&[(1, None)]), "medium"), Some(64));
+        assert_eq!(plan_audio_bitrate(&media(&[(1, None)]), "low"), Some(48));
+        assert_eq!(plan_audio_bitrate(&media(&[(6, None)]), "medium"), Some(384));
+        // Only the first stream is encoded
+        assert_eq!(plan_audio_bitrate(&media(&[(1, None), (6, None)]), "high"), Some(96));
+        assert_eq!(plan_audio_bitrate(&media(&[]), "high"), None);
+    }
+
+    #[test]
+    fn audio_bitrate_is_capped_at_the_source() {
+        assert_eq!(plan_audio_bitrate(&media(&[(2, Some(96_000))]), "high"), Some(96));
+        assert_eq!(plan_audio_bitrate(&media(&[(2, Some(20_000))]), "medium"), Some(32));
+        assert_eq!(plan_audio_bitrate(&media(&[(2, Some(320_000))]), "medium"), Some(128));
+    }
+
+    #[test]
+    fn budget_leaves_room_for_audio_and_overhead() {
+        let silent = plan(10.0, 60.0, 30.0, &media(&[]), "medium", Container::Mkv).unwrap();
+        assert_eq!(silent.target_bytes, 10 * 1024 * 1024);
+        assert_eq!(silent.audio_bitrate_kbps, None);
+        // 97% of 10 MB less 0.6% mux overhead, 64 KiB of headers, 8 bytes per frame and 32 bytes/s
+        assert!((silent.video_bitrate_bps as f64 - 1_336_855.0).abs() <= 1.0, "{}", silent.video_bitrate_bps);
+
+        let with_audio = plan(10.0, 60.0, 30.0, &media(&[(2, None)]), "medium", Container::Mkv).unwrap();
+        assert_eq!(with_audio.audio_bitrate_kbps, Some(128));
+        assert_eq!(silent.video_bitrate_bps - with_audio.video_bitrate_bps, 128_000);
+
+        // MP4 spends more per frame and per byte on its index
+        let mp4 = plan(10.0, 60.0, 30.0, &media(&[]), "medium", Container::Mp4).unwrap();
+        assert!(mp4.video_bitrate_bps < silent.video_bitrate_bps);
+    }
+
+    #[test]
+    fn rejects_targets_too_small_to_plan() {
+        for (target_mb, duration) in [(0.1, 600.0), (0.0, 60.0), (10.0, 0.0)] {
+            let error = plan(target_mb, duration, 30.0, &media(&[(2, None)]), "medium", Container::Mp4).unwrap_err();
+            assert!(matches!(error, CompressError::InvalidSettings { ref field, .. } if field == "targetSizeMB"));
+        }
+    }
+
+    #[test]
+    fn correction_takes_the_overshoot_from_the_video() {
+        let plan = TargetSizePlan { target_bytes: 10_000_000, video_bitrate_bps: 1_000_000, audio_bitrate_kbps: Some(128), duration_secs: 100.0 };
+        // 500 kB over across 100 s is 40 kbps, less the margin again
+        let corrected = plan.corrected(10_500_000).unwrap();
+        assert_eq!(corrected.video_bitrate_bps, 931_200);
+        assert_eq!(corrected.audio_bitrate_kbps, Some(128));
+        assert!(plan.corrected(10_000_000).is_err());
+        assert!(plan.corrected(30_000_000).is_err());
+    }
+}
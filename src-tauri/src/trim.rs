@@ -0,0 +1,220 @@
+// Trimming: the parts of the source a video job keeps. A single range is cut with input seeking
+// (-ss/-t before -i, frame-accurate when transcoding); several ranges are cut with trim/atrim and
+// joined with concat.
+use serde::{Deserialize, Serialize};
+
+use crate::error::CompressError;
+use crate::filter_graph::{Filter, FilterChain, FilterGraph};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct KeepRange {
+    pub start: f64, // Seconds into the source
+    #[serde(default)]
+    pub end: Option<f64>, // None keeps everything after `start`
+}
+
+#[derive(Debug, Clone)]
+pub struct TrimPlan {
+    ranges: Vec<(f64, Option<f64>)>, // Sorted and non-overlapping; empty keeps the whole source
+    source_duration: Option<f64>,
+}
+
+impl TrimPlan {
+    pub fn new(ranges: &[KeepRange], source_duration: Option<f64>) -> Result<Self, CompressError> {
+        let mut sorted: Vec<KeepRange> = ranges.to_vec();
+        sorted.sort_by(|a, b| a.start.total_cmp(&b.start));
+
+        let mut planned: Vec<(f64, Option<f64>)> = Vec::with_capacity(sorted.len());
+        for range in sorted {
+            let invalid = |reason: &str| CompressError::invalid_settings("keepRanges", format!(
+                "{}-{}: {}", range.start, range.end.map_or("end".to_string(), |e| e.to_string()), reason
+            ));
+            if !range.start.is_finite() || range.start < 0.0 {
+                return Err(invalid("start must be 0 or later"));
+            }
+            if range.end.is_some_and(|end| !end.is_finite() || end <= range.start) {
+                return Err(invalid("end must come after start"));
+            }
+            if source_duration.is_some_and(|duration| range.start >= duration) {
+                return Err(invalid("starts after the end of the video"));
+            }
+            if let Some(&(_, previous_end)) = planned.last() {
+                if previous_end.is_none_or(|previous_end| range.start < previous_end) {
+                    return Err(invalid("overlaps another range"));
+                }
+            }
+            // Past the end of the source just means "to the end"
+            let end = range.end.filter(|end| source_duration.is_none_or(|duration| *end < duration));
+            planned.push((range.start, end));
+        }
+        // A single range covering everything is no trim at all
+        if let [(start, None)] = planned.as_slice() {
+            if *start == 0.0 {
+                planned.clear();
+            }
+        }
+        Ok(TrimPlan { ranges: planned, source_duration })
+    }
+
+    pub fn is_trimmed(&self) -> bool {
+        !self.ranges.is_empty()
+    }
+
+    // Length of the output, which progress and bitrate budgets are based on
+    pub fn duration(&self) -> Option<f64> {
+        if self.ranges.is_empty() {
+            return self.source_duration;
+        }
+        self.ranges.iter()
+            .map(|&(start, end)| end.or(self.source_duration).map(|end| end - start))
+            .sum()
+    }
+
+    // Input options for a single range; they go right before the "-i" of the source
+    pub fn input_args(&self) -> Vec<String> {
+        match self.ranges.as_slice() {
+            [(start, end)] => {
+                let mut args = vec!["-ss".to_string(), start.to_string()];
+                if let Some(end) = end {
+                    args.extend(["-t".to_string(), (end - start).to_string()]);
+                }
+                args
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    // Graph cutting several ranges out of `video_in` (and `audio_in`) and joining them into
+    // `[video_out]` (and `[audio_out]`). None when there aren't several ranges to join.
+    pub fn segment_graph(&self, video_in: &str, audio_in: Option<(&str, &str)>, video_out: &str) -> Option<FilterGraph> {
+        if self.ranges.len() < 2 {
+            return None;
+        }
+        let count = self.ranges.len();
+        let mut graph = FilterGraph::default();
+        // Splits one stream into a copy per range and cuts each copy down to its range
+        let mut cut = |input: &str, prefix: &str, (split, trim, setpts): (&'static str, &'static str, &'static str)| {
+            let copies: Vec<String> = (0..count).map(|i| format!("{}{}in", prefix, i)).collect();
+            graph.chain(&[input], &Filter::new(split).arg(count).into(), &copies.iter().map(String::as_str).collect::<Vec<_>>());
+            for (i, &(start, end)) in self.ranges.iter().enumerate() {
+                let mut bounds = Filter::new(trim).opt("start", start);
+                if let Some(end) = end {
+                    bounds = bounds.opt("end", end);
+                }
+                let chain = FilterChain::from(bounds).then(Filter::new(setpts).arg("PTS-STARTPTS"));
+                graph.chain(&[&copies[i]], &chain, &[&format!("{}{}", prefix, i)]);
+            }
+        };
+        cut(video_in, "tv", ("split", "trim", "setpts"));
+        if let Some((audio_in, _)) = audio_in {
+            cut(audio_in, "ta", ("asplit", "atrim", "asetpts"));
+        }
+        // concat takes each segment's video followed by its audio
+        let segments: Vec<String> = (0..count)
+            .flat_map(|i| std::iter::once(format!("tv{i}")).chain(audio_in.map(|_| format!("ta{i}"))))
+            .collect();
+        let outputs: Vec<&str> = std::iter::once(video_out).chain(audio_in.map(|(_, audio_out)| audio_out)).collect();
+        let concat = Filter::new("concat").opt("n", count).opt("v", 1).opt("a", audio_in.is_some() as u8);
+        graph.chain(&segments.iter().map(String::as_str).collect::<Vec<_>>(), &concat.into(), &outputs);
+        Some(graph)
+    }
+
+    // Maps a span of the output timeline back to the source, for encoding quality samples straight
+    // from the source. The span is shortened if it would run past the end of its range.
+    pub fn source_span(&self, output_start: f64, duration: f64) -> (f64, f64) {
+        let mut offset = 0.0;
+        for &(start, end) in &self.ranges {
+            let length = end.or(self.source_duration).map(|end| end - start);
+            match length {
+                Some(length) if output_start >= offset + length => offset += length,
+                _ => {
+                    let source_start = start + (output_start - offset);
+                    let available = length.map_or(duration, |length| length - (output_start - offset));
+                    return (source_start, duration.min(available));
+                }
+            }
+        }
+        (output_start, duration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: f64, end: Option<f64>) -> KeepRange {
+        KeepRange { start, end }
+    }
+
+    #[test]
+    fn segment_graph_cuts_and_joins_each_range() {
+        let plan = TrimPlan::new(&[range(5.0, None), range(0.0, Some(2.5))], Some(10.0)).unwrap();
+        let graph = plan.segment_graph("0:v:0", Some(("0:a:0", "aout")), "vcat").unwrap();
+        assert_eq!(graph.to_string(), concat!(
+            "[0:v:0]split=2[tv0in][tv1in];",
+            "[tv0in]trim=start=0:end=2.5,setpts=PTS-STARTPTS[tv0];",
+            "[tv1in]trim=start=5,setpts=PTS-STARTPTS[tv1];",
+            "[0:a:0]asplit=2[ta0in][ta1in];",
+            "[ta0in]atrim=start=0:end=2.5,asetpts=PTS-STARTPTS[ta0];",
+            "[ta1in]atrim=start=5,asetpts=PTS-STARTPTS[ta1];",
+            "[tv0][ta0][tv1][ta1]concat=n=2:v=1:a=1[vcat][aout]",
+        ));
+    }
+
+    #[test]
+    fn segment_graph_without_audio_joins_video_only() {
+        let plan = TrimPlan::new(&[range(0.0, Some(1.0)), range(3.0, Some(4.0))], None).unwrap();
+        let graph = plan.segment_graph("0:v:0", None, "vcat").unwrap().to_string();
+        assert!(!graph.contains("asplit"));
+        assert!(graph.ends_with("[tv0][tv1]concat=n=2:v=1:a=0[vcat]"));
+    }
+
+    #[test]
+    fn ranges_are_sorted_and_checked() {
+        let plan = TrimPlan::new(&[range(6.0, Some(8.0)), range(1.0, Some(3.0))], Some(10.0)).unwrap();
+        assert_eq!(plan.ranges, vec![(1.0, Some(3.0)), (6.0, Some(8.0))]);
+        assert_eq!(plan.duration(), Some(4.0));
+
+        assert!(TrimPlan::new(&[range(-1.0, None)], None).is_err());
+        assert!(TrimPlan::new(&[range(4.0, Some(4.0))], None).is_err());
+        assert!(TrimPlan::new(&[range(12.0, None)], Some(10.0)).is_err());
+        assert!(TrimPlan::new(&[range(0.0, Some(5.0)), range(4.0, Some(6.0))], None).is_err());
+        assert!(TrimPlan::new(&[range(0.0, None), range(4.0, Some(6.0))], None).is_err());
+    }
+
+    #[test]
+    fn whole_source_is_no_trim() {
+        let plan = TrimPlan::new(&[range(0.0, Some(30.0))], Some(10.0)).unwrap();
+        assert!(!plan.is_trimmed());
+        assert_eq!(plan.duration(), Some(10.0));
+        assert!(plan.input_args().is_empty());
+    }
+
+    #[test]
+    fn single_range_seeks_the_input() {
+        let plan = TrimPlan::new(&[range(1.5, Some(4.0))], Some(10.0)).unwrap();
+        assert_eq!(plan.input_args(), vec!["-ss", "1.5", "-t", "2.5"]);
+        let open_ended = TrimPlan::new(&[range(3.0, None)], None).unwrap();
+        assert_eq!(open_ended.input_args(), vec!["-ss", "3"]);
+        assert_eq!(open_ended.duration(), None);
+    }
+
+    #[test]
+    fn source_span_maps_output_time_into_the_ranges() {
+        let plan = TrimPlan::new(&[range(10.0, Some(20.0)), range(50.0, None)], Some(60.0)).unwrap();
+        assert_eq!(plan.source_span(2.0, 3.0), (12.0, 3.0));
+        // Shortened at the end of the first range
+        assert_eq!(plan.source_span(8.0, 5.0), (18.0, 2.0));
+        assert_eq!(plan.source_span(12.0, 3.0), (52.0, 3.0));
+        assert_eq!(plan.source_span(18.0, 5.0), (58.0, 2.0));
+
+        let untrimmed = TrimPlan::new(&[], Some(60.0)).unwrap();
+        assert_eq!(untrimmed.source_span(5.0, 2.0), (5.0, 2.0));
+    }
+
+    #[test]
+    fn single_range_needs_no_graph() {
+        let plan = TrimPlan::new(&[range(1.0, Some(4.0))], Some(10.0)).unwrap();
+        assert!(plan.segment_graph("0:v:0", None, "vcat").is_none());
+    }
+}
@@ -0,0 +1,301 @@
+// Video encoders and output containers the compressor can use, and how each one maps our
+// settings onto ffmpeg arguments.
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::error::CompressError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum VideoCodec {
+    #[default]
+    #[serde(rename = "libx264")]
+    H264,
+    #[serde(rename = "libx265")]
+    H265,
+    #[serde(rename = "libvpx-vp9")]
+    Vp9,
+    #[serde(rename = "libaom-av1")]
+    Av1Aom,
+    #[serde(rename = "libsvtav1")]
+    Av1Svt,
+}
+
+impl VideoCodec {
+    pub fn encoder_name(self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "libx264",
+            VideoCodec::H265 => "libx265",
+            VideoCodec::Vp9 => "libvpx-vp9",
+            VideoCodec::Av1Aom => "libaom-av1",
+            VideoCodec::Av1Svt => "libsvtav1",
+        }
+    }
+
+    // The --enable-* configure flag an ffmpeg build needs for this encoder
+    pub fn library(self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "libx264",
+            VideoCodec::H265 => "libx265",
+            VideoCodec::Vp9 => "libvpx",
+            VideoCodec::Av1Aom => "libaom",
+            VideoCodec::Av1Svt => "libsvtav1",
+        }
+    }
+
+    // The UI's CRF values are on the x264 scale (0-51, 23 = default). Each encoder has its own
+    // scale, so the value is translated to roughly the same visual quality.
+    pub fn crf_from_x264(self, crf: u8) -> u8 {
+        let crf = crf.min(51) as u32;
+        let mapped = match self {
+            VideoCodec::H264 => crf,
+            // x265 CRF 28 looks about like x264 CRF 23
+            VideoCodec::H265 => crf + 5,
+            // libvpx and libaom use 0-63; 23 lands around 31
+            VideoCodec::Vp9 | VideoCodec::Av1Aom => crf * 63 / 51 + 3,
+            // SVT-AV1's default of 35 is the x264 default equivalent
+            VideoCodec::Av1Svt => crf * 63 / 51 + 7,
+        };
+        mapped.min(self.max_crf() as u32) as u8
+    }
+
+    fn max_crf(self) -> u8 {
+        match self {
+            VideoCodec::H264 | VideoCodec::H265 => 51,
+            VideoCodec::Vp9 | VideoCodec::Av1Aom | VideoCodec::Av1Svt => 63,
+        }
+    }
+
+    // Constant-quality rate control for an x264-scale CRF
+    pub fn crf_args(self, x264_crf: u8) -> Vec<String> {
+        let crf = self.crf_from_x264(x264_crf).to_string();
+        match self {
+            // libvpx and libaom only run in constant-quality mode with a zero target bitrate
+            VideoCodec::Vp9 | VideoCodec::Av1Aom => vec!["-crf".into(), crf, "-b:v".into(), "0".into()],
+            _ => vec!["-crf".into(), crf],
+        }
+    }
+
+    // Encoder speed for one of the x264 preset names we use ("fast", "medium", "slow")
+    pub fn speed_args(self, speed: &str) -> Vec<String> {
+        let pick = |fast: &str, medium: &str, slow: &str| match speed {
+            "fast" => fast.to_string(),
+            "slow" => slow.to_string(),
+            _ => medium.to_string(),
+        };
+        match self {
+            VideoCodec::H264 | VideoCodec::H265 => vec!["-preset".into(), pick("fast", "medium", "slow")],
+            VideoCodec::Vp9 => vec![
+                "-deadline".into(), "good".into(),
+                "-cpu-used".into(), pick("4", "2", "1"),
+                "-row-mt".into(), "1".into(),
+            ],
+            VideoCodec::Av1Aom => vec![
+                "-cpu-used".into(), pick("6", "4", "3"),
+                "-row-mt".into(), "1".into(),
+            ],
+            VideoCodec::Av1Svt => vec!["-preset".into(), pick("10", "8", "5")],
+        }
+    }
+
+    // ffmpeg's libsvtav1 wrapper has no -pass support
+    pub fn supports_two_pass(self) -> bool {
+        self != VideoCodec::Av1Svt
+    }
+
+    // Arguments for one pass of a two-pass encode. libx265 ignores -pass/-passlogfile and takes
+    // its stats file through -x265-params instead.
+    pub fn two_pass_args(self, pass: u8, log_prefix: &Path) -> Vec<String> {
+        match self {
+            VideoCodec::H265 => {
+                let stats = format!("{}-0.log", log_prefix.display());
+                vec!["-x265-params".into(), format!("pass={}:stats={}", pass, escape_x265_param(&stats))]
+            }
+            _ => vec![
+                "-pass".into(), pass.to_string(),
+                "-passlogfile".into(), log_prefix.display().to_string(),
+            ],
+        }
+    }
+
+    // Extra arguments needed for the output to play back widely
+    pub fn compatibility_args(self, container: Container) -> Vec<String> {
+        match (self, container) {
+            // QuickTime and Apple devices only play HEVC in MP4/MOV when tagged hvc1
+            (VideoCodec::H265, Container::Mp4 | Container::Mov) => vec!["-tag:v".into(), "hvc1".into()],
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Container {
+    Mp4,
+    Mkv,
+    Webm,
+    Mov,
+}
+
+impl Container {
+    fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "mp4" | "m4v" => Some(Container::Mp4),
+            "mkv" => Some(Container::Mkv),
+            "webm" => Some(Container::Webm),
+            "mov" => Some(Container::Mov),
+            _ => None,
+        }
+    }
+
+    fn extensions(self) -> &'static [&'static str] {
+        match self {
+            Container::Mp4 => &["mp4", "m4v"],
+            Container::Mkv => &["mkv"],
+            Container::Webm => &["webm"],
+            Container::Mov => &["mov"],
+        }
+    }
+
+    // Extension used for new output files
+    pub fn extension(self) -> &'static str {
+        self.extensions()[0]
+    }
+
+    pub fn supports(self, codec: VideoCodec) -> bool {
+        match self {
+            Container::Mkv | Container::Mp4 => true,
+            Container::Webm => matches!(codec, VideoCodec::Vp9 | VideoCodec::Av1Aom | VideoCodec::Av1Svt),
+            Container::Mov => matches!(codec, VideoCodec::H264 | VideoCodec::H265),
+        }
+    }
+
+    // Forces the muxer so it follows the setting rather than ffmpeg's guess from the file name
+    pub fn muxer_args(self) -> Vec<String> {
+        let mut args = vec!["-f".to_string(), match self {
+            Container::Mp4 => "mp4",
+            Container::Mkv => "matroska",
+            Container::Webm => "webm",
+            Container::Mov => "mov",
+        }.to_string()];
+        if matches!(self, Container::Mp4 | Container::Mov) {
+            // Move the moov atom to the front so playback can start before the download finishes
+            args.push("-movflags".into());
+            args.push("+faststart".into());
+        }
+        args
+    }
+
+    // Audio encoder for the soundtrack. WebM only takes Opus or Vorbis.
+    pub fn audio_encoder(self) -> &'static str {
+        if self == Container::Webm { "libopus" } else { "aac" }
+    }
+}
+
+// Works out the output container (explicit setting, else the output extension) and checks that
+// it can hold the chosen codec and matches the output file name, before any ffmpeg is spawned
+pub fn resolve_container(container: Option<Container>, codec: VideoCodec, output_path: &str) -> Result<Container, CompressError> {
+    let extension = Path::new(output_path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+
+    let container = match container {
+        Some(container) => {
+            if !container.extensions().contains(&extension.as_str()) {
+                return Err(CompressError::invalid_settings("container", format!(
+                    "output file '{}' does not match the {:?} container, expected extension: {}",
+                    output_path, container, container.extensions().join(" or ")
+                )));
+            }
+            container
+        }
+        None => Container::from_extension(&extension)
+            .ok_or_else(|| CompressError::invalid_settings("container", format!(
+                "unsupported output container '.{}', use mp4, mkv, webm or mov", extension
+            )))?,
+    };
+
+    if !container.supports(codec) {
+        return Err(CompressError::invalid_settings("codec", format!(
+            "{} video cannot be stored in a {:?} container", codec.encoder_name(), container
+        )));
+    }
+    Ok(container)
+}
+
+// -x265-params is a ':'-separated list, so colons and backslashes in paths (e.g. C:\...) need escaping
+fn escape_x265_param(value: &str) -> String {
+    value.replace('\\', "\\\\").replace(':', "\\:")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn crf_is_mapped_to_each_encoders_scale() {
+        assert_eq!(VideoCodec::H264.crf_from_x264(23), 23);
+        assert_eq!(VideoCodec::H264.crf_from_x264(60), 51);
+        assert_eq!(VideoCodec::H265.crf_from_x264(23), 28);
+        assert_eq!(VideoCodec::H265.crf_from_x264(51), 51);
+        assert_eq!(VideoCodec::Vp9.crf_from_x264(23), 31);
+        assert_eq!(VideoCodec::Av1Aom.crf_from_x264(0), 3);
+        assert_eq!(VideoCodec::Av1Svt.crf_from_x264(23), 35);
+        assert_eq!(VideoCodec::Av1Svt.crf_from_x264(51), 63);
+
+        assert_eq!(VideoCodec::H265.crf_args(23), args(&["-crf", "28"]));
+        assert_eq!(VideoCodec::Vp9.crf_args(23), args(&["-crf", "31", "-b:v", "0"]));
+    }
+
+    #[test]
+    fn speed_maps_to_each_encoders_preset() {
+        assert_eq!(VideoCodec::H264.speed_args("slow"), args(&["-preset", "slow"]));
+        assert_eq!(VideoCodec::H265.speed_args("unknown"), args(&["-preset", "medium"]));
+        assert_eq!(VideoCodec::Vp9.speed_args("fast"), args(&["-deadline", "good", "-cpu-used", "4", "-row-mt", "1"]));
+        assert_eq!(VideoCodec::Av1Aom.speed_args("slow"), args(&["-cpu-used", "3", "-row-mt", "1"]));
+        assert_eq!(VideoCodec::Av1Svt.speed_args("medium"), args(&["-preset", "8"]));
+    }
+
+    #[test]
+    fn two_pass_args_per_encoder() {
+        let prefix = Path::new("/tmp/pressvid-task-1");
+        assert_eq!(VideoCodec::H264.two_pass_args(1, prefix), args(&["-pass", "1", "-passlogfile", "/tmp/pressvid-task-1"]));
+        assert_eq!(VideoCodec::H265.two_pass_args(2, prefix), args(&["-x265-params", "pass=2:stats=/tmp/pressvid-task-1-0.log"]));
+        assert!(!VideoCodec::Av1Svt.supports_two_pass());
+    }
+
+    #[test]
+    fn x265_stats_path_is_escaped() {
+        let args = VideoCodec::H265.two_pass_args(1, Path::new(r"C:\Temp\pass"));
+        assert_eq!(args[1], r"pass=1:stats=C\:\\Temp\\pass-0.log");
+    }
+
+    #[test]
+    fn container_follows_setting_or_extension() {
+        assert_eq!(resolve_container(None, VideoCodec::H264, "/out/clip.M4V").unwrap(), Container::Mp4);
+        assert_eq!(resolve_container(Some(Container::Mkv), VideoCodec::Vp9, "/out/clip.mkv").unwrap(), Container::Mkv);
+        let invalid = |result: Result<Container, CompressError>| match result {
+            Err(CompressError::InvalidSettings { field, .. }) => field,
+            other => panic!("expected invalid settings, got {:?}", other),
+        };
+        // Setting and file name disagree
+        assert_eq!(invalid(resolve_container(Some(Container::Webm), VideoCodec::Vp9, "/out/clip.mp4")), "container");
+        assert_eq!(invalid(resolve_container(None, VideoCodec::H264, "/out/clip.avi")), "container");
+        // Container can't hold the codec
+        assert_eq!(invalid(resolve_container(None, VideoCodec::H264, "/out/clip.webm")), "codec");
+        assert_eq!(invalid(resolve_container(Some(Container::Mov), VideoCodec::Vp9, "/out/clip.mov")), "codec");
+    }
+
+    #[test]
+    fn faststart_only_for_mp4_and_mov() {
+        assert_eq!(Container::Mp4.muxer_args(), args(&["-f", "mp4", "-movflags", "+faststart"]));
+        assert_eq!(Container::Mov.muxer_args(), args(&["-f", "mov", "-movflags", "+faststart"]));
+        assert_eq!(Container::Mkv.muxer_args(), args(&["-f", "matroska"]));
+        assert_eq!(Container::Webm.muxer_args(), args(&["-f", "webm"]));
+    }
+}
@@ -27,19 +27,27 @@ vi.mock('@tauri-apps/api/event', () => ({
 
 // Mock child components that are not the focus of this integration test
 vi.mock('./components/ThemeToggle', () => ({ ThemeToggle: () => <div data-testid="theme-toggle-mock">ThemeToggle</div> }));
-vi.mock('./components/DropZone', () => ({ DropZone: ({onFileSelect}: {onFileSelect: (path: string) => void}) => <button data-testid="dropzone-mock" onClick={() => onFileSelect("/test/dummy-video.mp4")}>Select File</button> }));
+vi.mock('./components/DropZone', () => ({ DropZone: ({onFileSelect, onFilesSelect}: {onFileSelect: (path: string) => void; onFilesSelect?: (files: { path: string; kind: string }[]) => void}) => (
+  <>
+    <button data-testid="dropzone-mock" onClick={() => onFileSelect("/test/dummy-video.mp4")}>Select File</button>
+    <button data-testid="dropzone-twice-mock" onClick={() => onFilesSelect?.([
+      { path: "/test/dummy-video.mp4", kind: 'video' },
+      { path: "/test/dummy-video.mp4", kind: 'video' },
+    ])}>Drop Same File Twice</button>
+  </>
+) }));
 vi.mock('./components/CompressionSettings', () => ({ CompressionSettings: () => <div data-testid="video-settings-mock">VideoSettings</div> }));
 vi.mock('./components/AudioCompressionSettings', () => ({ AudioCompressionSettingsComponent: () => <div data-testid="audio-settings-mock">AudioSettings</div> }));
 vi.mock('./components/ImageCompressionSettings', () => ({ ImageCompressionSettingsComponent: () => <div data-testid="image-settings-mock">ImageSettings</div> }));
 // ProgressBar is part of TaskList, which we will observe indirectly through App's state affecting TaskList's props.
 
-// Helper to simulate progress event emission
-const emitProgressEvent = (taskId: string, progress: number) => {
-  if (eventListeners['PROGRESS_EVENT']) {
-    eventListeners['PROGRESS_EVENT'].forEach(callback => {
+// Helper to simulate a backend event
+const emitEvent = (eventKey: string, payload: any) => {
+  if (eventListeners[eventKey]) {
+    eventListeners[eventKey].forEach(callback => {
       callback({
-        event: 'PROGRESS_EVENT',
-        payload: { task_id: taskId, progress },
+        event: eventKey,
+        payload,
         windowLabel: 'main', // or appropriate window label
         id: Math.random(), // event ID
       });
@@ -47,6 +55,11 @@ const emitProgressEvent = (taskId: string, progress: number) => {
   }
 };
 
+// Helper to simulate progress event emission
+const emitProgressEvent = (taskId: string, progress: number) => {
+  emitEvent('PROGRESS_EVENT', { task_id: taskId, progress });
+};
+
 
 describe('App Component - Integration Tests (Task Management & Progress Reporting)', () => {
   beforeEach(() => {
@@ -82,8 +95,8 @@ describe('App Component - Integration Tests (Task Management & Progress Reportin
     const startButton = screen.getByRole('button', { name: /开始压缩/i }); // General "Start Compression"
     expect(startButton).not.toBeDisabled();
 
-    // 3. Mock successful invocation for compress_video
-    mockInvoke.mockResolvedValueOnce("Video compression successful!");
+    // 3. Mock successful invocation for compress_video; the backend answers with the task id right away
+    mockInvoke.mockResolvedValueOnce("task-1");
 
     // 4. Click "Start Compression"
     await act(async () => {
@@ -108,7 +121,7 @@ describe('App Component - Integration Tests (Task Management & Progress Reportin
 
     // 5. Simulate progress events
     await act(async () => {
-      emitProgressEvent("/test/dummy-video.mp4", 30);
+      emitProgressEvent("task-1", 30);
     });
     // TaskList uses ProgressBar, which gets infoText from progress.
     // The mocked ProgressBar in TaskList.test.tsx shows "Info: 30%"
@@ -119,25 +132,95 @@ describe('App Component - Integration Tests (Task Management & Progress Reportin
 
 
     await act(async () => {
-      emitProgressEvent("/test/dummy-video.mp4", 70);
+      emitProgressEvent("task-1", 70);
     });
     expect(screen.getByText('70%')).toBeInTheDocument();
 
-    // 6. Simulate completion (invoke promise resolves)
-    // The mockInvoke for compress_video is already set to resolve.
-    // We need to wait for the state updates after the promise resolution.
+    // 6. Simulate completion (the backend emits TASK_COMPLETED_EVENT with the same task id)
+    await act(async () => {
+      emitEvent('TASK_COMPLETED_EVENT', {
+        task_id: "task-1",
+        result: {
+          outputPath: "/test/dummy-video_compressed.mp4",
+          originalSize: 1000,
+          compressedSize: 400,
+          ratio: 0.4,
+          elapsedSecs: 12.5,
+          speed: 2.4,
+          bitrate: 800000,
+          width: 1280,
+          height: 720,
+          codec: "h264",
+          ffmpegArgs: ["-i", "/test/dummy-video.mp4"],
+        },
+      });
+    });
     await waitFor(() => {
       // Status should be 'completed'
       expect(screen.getByText('completed')).toBeInTheDocument();
       // Progress should be 100%
       expect(screen.getByText('100%')).toBeInTheDocument();
-      // Success message from invoke might be displayed as currentStatusMessage
-      expect(screen.getByText("Video compression successful!")).toBeInTheDocument();
+      // Summary built from the completion result is displayed as currentStatusMessage
+      expect(screen.getByText("dummy-video_compressed.mp4 压缩完成，用时 12.5 秒")).toBeInTheDocument();
+      // Size change is derived from the result's ratio
+      expect(screen.getByText("大小变化: 减少了 60.00%")).toBeInTheDocument();
       // TaskList should show output file
       expect(screen.getByText('输出: dummy-video_compressed.mp4')).toBeInTheDocument();
     });
   });
 
+  test('should keep two tasks for the same input apart by task id', async () => {
+    render(<App />);
+
+    // Both runs of the file are queued at once and get their own task ids
+    mockInvoke.mockResolvedValueOnce("task-720p").mockResolvedValueOnce("task-480p");
+    await act(async () => {
+      fireEvent.click(screen.getByTestId('dropzone-twice-mock'));
+    });
+    expect(mockInvoke.mock.calls.filter(([cmd]) => cmd === "compress_video")).toHaveLength(2);
+    expect(screen.getAllByText('dummy-video.mp4 (video)')).toHaveLength(2);
+
+    await act(async () => {
+      emitProgressEvent("task-720p", 40);
+      emitProgressEvent("task-480p", 90);
+    });
+    expect(screen.getByText('40%')).toBeInTheDocument();
+    expect(screen.getByText('90%')).toBeInTheDocument();
+
+    // Finishing one run leaves the other where it was
+    await act(async () => {
+      emitEvent('TASK_CANCELLED_EVENT', { task_id: "task-720p" });
+    });
+    expect(screen.getByText('cancelled')).toBeInTheDocument();
+    expect(screen.getByText('90%')).toBeInTheDocument();
+    expect(screen.queryByText('40%')).not.toBeInTheDocument();
+  });
+
+  test('should drop early updates for task ids that never arrive', async () => {
+    const start = Date.now();
+    const now = vi.spyOn(Date, 'now').mockReturnValue(start);
+    render(<App />);
+
+    // An event for a task this window never started, e.g. one restored from an earlier session
+    await act(async () => {
+      emitProgressEvent("task-stale", 55);
+    });
+    now.mockReturnValue(start + 61_000);
+    await act(async () => {
+      emitProgressEvent("task-other", 10);
+    });
+
+    await act(async () => {
+      fireEvent.click(screen.getByTestId('dropzone-mock'));
+    });
+    mockInvoke.mockResolvedValueOnce("task-stale");
+    await act(async () => {
+      fireEvent.click(screen.getByRole('button', { name: /开始压缩/i }));
+    });
+    expect(screen.queryByText('55%')).not.toBeInTheDocument();
+    now.mockRestore();
+  });
+
   test('should handle a failed compression task', async () => {
     render(<App />);
 
@@ -1,22 +1,52 @@
-import { useState, useEffect } from "react";
+import { useState, useEffect, useRef } from "react";
 import { invoke } from "@tauri-apps/api/core";
 import { listen } from "@tauri-apps/api/event";
 import { ThemeToggle } from "./components/ThemeToggle";
-import { DropZone } from "./components/DropZone";
+import { DropZone, SelectedFile } from "./components/DropZone";
 import { CompressionSettings, CompressionSettings as VideoCompressionSettingsType } from "./components/CompressionSettings";
-import { AudioCompressionSettingsComponent, AudioCompressionSettings as AudioCompressionSettingsType }
+import { AudioCompressionSettingsComponent, AudioCompressionSettings as AudioCompressionSettingsType, audioFormatExtensions }
   from "./components/AudioCompressionSettings";
-import { ImageCompressionSettingsComponent, ImageCompressionSettings as ImageCompressionSettingsType }
+import { ImageCompressionSettingsComponent, ImageCompressionSettings as ImageCompressionSettingsType, imageFormatExtensions }
   from "./components/ImageCompressionSettings";
 import { ProgressBar } from "./components/ProgressBar";
-import { TaskList, Task, ActiveView } from './components/TaskList'; // Import TaskList and types
+import { TaskList, Task, ActiveView, CompressionResult } from './components/TaskList'; // Import TaskList and types
+import { CompressError, describeError } from "./errors";
 
-// ProgressPayload interface is still needed here for the listen function
+// Every backend event carries the task id returned by the compress_* command
 interface ProgressPayload {
   task_id: string;
   progress: number;
+  pass: number | null; // 两遍编码时为当前遍数
+  fps: number | null;
+  speed: number | null; // 实时倍速
+  eta_secs: number | null;
+  bytes_written: number | null;
+  bitrate_kbps: number | null;
 }
 
+interface CancelledPayload {
+  task_id: string;
+}
+
+interface CompletedPayload {
+  task_id: string;
+  result: CompressionResult;
+}
+
+interface FailedPayload {
+  task_id: string;
+  error: CompressError;
+}
+
+// invoke returns right after queueing, so an early event whose task hasn't shown up by then never will
+const EARLY_UPDATE_TTL_MS = 60_000;
+
+const taskTypeLabels: Record<ActiveView, string> = {
+  video: '视频',
+  audio: '音频',
+  image: '图片',
+};
+
 function App() {
   const [activeView, setActiveView] = useState<ActiveView>('video');
   const [inputFile, setInputFile] = useState<string | null>(null); // For selecting current file
@@ -35,44 +65,126 @@ function App() {
     audioQuality: 'medium',
     customSettings: false,
     crfValue: 23,
+    codec: 'libx264',
+    hardwareEncoder: null,
+    frameRate: { mode: 'keep' },
+    crop: { mode: 'none' },
+    keepRanges: [],
+    container: 'mp4',
   });
   const [audioSettings, setAudioSettings] = useState<AudioCompressionSettingsType>({
     quality: 'medium',
+    format: 'aac',
   });
   const [imageSettings, setImageSettings] = useState<ImageCompressionSettingsType>({
     quality: 75, // Default image quality
+    stripMetadata: true,
   });
 
+  // Events that arrive before invoke has returned the task id are held here until the task is added.
+  // Ids that are never added, such as jobs restored from an earlier session, expire after a while.
+  const earlyTaskUpdates = useRef<Record<string, { update: Partial<Task>; receivedAt: number }>>({});
+
+  const updateTask = (taskId: string, update: Partial<Task>) => {
+    setTasks(prevTasks => {
+      if (!prevTasks.some(task => task.id === taskId)) {
+        const now = Date.now();
+        for (const [id, early] of Object.entries(earlyTaskUpdates.current)) {
+          if (now - early.receivedAt > EARLY_UPDATE_TTL_MS) {
+            delete earlyTaskUpdates.current[id];
+          }
+        }
+        const previous = earlyTaskUpdates.current[taskId];
+        earlyTaskUpdates.current[taskId] = { update: { ...previous?.update, ...update }, receivedAt: previous?.receivedAt ?? now };
+        return prevTasks;
+      }
+      return prevTasks.map(task => task.id === taskId ? { ...task, ...update } : task);
+    });
+  };
+
   useEffect(() => {
     const unlisten = listen<ProgressPayload>("PROGRESS_EVENT", (event) => {
-      setTasks(prevTasks =>
-        prevTasks.map(task =>
-          task.id === event.payload.task_id
-            ? { ...task, progress: event.payload.progress, status: event.payload.progress < 100 ? 'compressing' : 'finishing' }
-            : task
-        )
-      );
+      const { task_id, progress, pass, fps, speed, eta_secs, bytes_written, bitrate_kbps } = event.payload;
+      updateTask(task_id, {
+        progress,
+        pass: pass ?? undefined,
+        stats: { fps: fps ?? undefined, speed: speed ?? undefined, etaSecs: eta_secs ?? undefined, bytesWritten: bytes_written ?? undefined, bitrateKbps: bitrate_kbps ?? undefined },
+        status: progress < 100 ? 'compressing' : 'finishing',
+      });
+    });
+
+    const unlistenCompleted = listen<CompletedPayload>("TASK_COMPLETED_EVENT", (event) => {
+      const { task_id, result } = event.payload;
+      const changePercent = Math.abs(1 - result.ratio) * 100;
+      const sizeChangeText = result.ratio < 1 ? `减少了 ${changePercent.toFixed(2)}%`
+        : result.ratio > 1 ? `增加了 ${changePercent.toFixed(2)}%`
+        : '没有变化';
+      // 同名文件已存在时后端会改用带序号的文件名，以结果中的路径为准
+      updateTask(task_id, { status: 'completed', progress: 100, outputFile: result.outputPath, compressedSize: result.compressedSize, sizeChangeText, result });
+      setCurrentStatusMessage(`${getFileName(result.outputPath)} 压缩完成，用时 ${result.elapsedSecs.toFixed(1)} 秒`);
+    });
+
+    const unlistenFailed = listen<FailedPayload>("TASK_FAILED_EVENT", (event) => {
+      const { task_id, error } = event.payload;
+      console.error("压缩处理失败:", error);
+      const errorText = describeError(error);
+      updateTask(task_id, { status: 'failed', error: errorText, errorKind: error.kind });
+      setCurrentStatusMessage(`处理失败: ${errorText.substring(0,100)}`);
+    });
+
+    const unlistenCancelled = listen<CancelledPayload>("TASK_CANCELLED_EVENT", (event) => {
+      updateTask(event.payload.task_id, { status: 'cancelled' });
     });
 
     return () => {
       unlisten.then(f => f()).catch(console.error);
+      unlistenCompleted.then(f => f()).catch(console.error);
+      unlistenFailed.then(f => f()).catch(console.error);
+      unlistenCancelled.then(f => f()).catch(console.error);
     };
   }, []);
 
+  const cancelTask = async (taskId: string) => {
+    const task = tasks.find(t => t.id === taskId);
+    try {
+      await invoke("cancel_task", { taskId });
+      setCurrentStatusMessage(`任务 ${task ? getFileName(task.inputFile) : taskId} 已取消。`);
+    } catch (error) {
+      console.error("取消任务失败:", error);
+      setCurrentStatusMessage(`取消任务失败: ${describeError(error).substring(0,100)}`);
+    }
+  };
+
+  // 自动生成输出文件路径（在相同目录下添加 _compressed 后缀）
+  const defaultOutputPath = (filePath: string) => {
+    const lastDotIndex = filePath.lastIndexOf('.');
+    if (lastDotIndex !== -1) {
+      return filePath.substring(0, lastDotIndex) + 
+             '_compressed' + 
+             filePath.substring(lastDotIndex);
+    }
+    return filePath + '_compressed';
+  };
+
   const handleFileSelect = (filePath: string) => {
     setCurrentStatusMessage("已选择文件，准备开始。");
     // setProgress(0); // Task specific now
     setInputFile(filePath);
-    // 自动生成输出文件路径（在相同目录下添加 _compressed 后缀）
-    const lastDotIndex = filePath.lastIndexOf('.');
-    if (lastDotIndex !== -1) {
-      const newOutputPath = filePath.substring(0, lastDotIndex) + 
-                           '_compressed' + 
-                           filePath.substring(lastDotIndex);
-      setOutputFile(newOutputPath);
-    } else {
-      setOutputFile(filePath + '_compressed');
+    setOutputFile(defaultOutputPath(filePath));
+  };
+
+  // 单个文件切换到对应类型的页面；多个文件按各自类型直接用当前设置加入压缩
+  const handleFilesSelect = (files: SelectedFile[]) => {
+    if (files.length === 1) {
+      setActiveView(files[0].kind);
+      handleFileSelect(files[0].path);
+      return;
+    }
+    // 每个文件都进入后端队列，由队列按并行上限依次启动
+    for (const file of files) {
+      startCompressionOf(file.kind, file.path, defaultOutputPath(file.path));
     }
+    setCurrentStatusMessage(`已将 ${files.length} 个文件加入压缩队列。`);
   };
 
   const handleVideoSettingsChange = (newSettings: VideoCompressionSettingsType) => {
@@ -90,169 +202,80 @@ function App() {
   // Helper to get filename from path
   const getFileName = (path: string) => path.split(/[\\/]/).pop() || path;
 
-  const startVideoCompression = async () => {
-    if (!inputFile || !outputFile) {
-      setCurrentStatusMessage("请先选择输入和输出文件。");
-      return;
-    }
-    if (tasks.find(task => task.id === inputFile && (task.status === 'compressing' || task.status === 'pending'))) {
-      setCurrentStatusMessage(`任务 ${getFileName(inputFile)} 已在队列中或正在处理。`);
-      return;
-    }
+  // Queues a backend task. The command returns the task id at once; the task stays pending until
+  // the queue starts it and its first progress event arrives.
+  const startCompression = async (type: ActiveView, command: string, inputFile: string, taskOutputFile: string, settings: unknown) => {
 
-    const taskId = inputFile; // Using inputFile as task ID, as per backend
+    // Placeholder id until the backend hands out the real task id
+    const localId = `local-${Date.now()}-${Math.random().toString(36).slice(2)}`;
     const newTask: Task = {
-      id: taskId,
+      id: localId,
       inputFile,
-      outputFile,
+      outputFile: taskOutputFile,
       status: 'pending',
       progress: 0,
-      type: 'video',
+      type,
     };
     setTasks(prevTasks => [newTask, ...prevTasks]); // Add to the beginning of the list
-    setCurrentStatusMessage(`视频 ${getFileName(inputFile)} 已加入队列...`);
+    setCurrentStatusMessage(`${taskTypeLabels[type]} ${getFileName(inputFile)} 已加入队列...`);
 
     try {
-      // Update status to compressing right before invoke
-      setTasks(prevTasks => prevTasks.map(t => t.id === taskId ? {...t, status: 'compressing'} : t));
-      const successMessage = await invoke<string>("compress_video", {
-        inputPath: inputFile, // This is used as task_id in Rust
-        outputPath: outputFile,
-        settings: videoSettings,
+      const taskId = await invoke<string>(command, {
+        inputPath: inputFile,
+        outputPath: taskOutputFile,
+        settings,
       });
-
-      // 解析 successMessage 提取压缩后大小和比例
-      let compressedSize: number | undefined;
-      let sizeChangeText: string | undefined;
-
-      const regex = /大小: (\d+) 字节, 相较原始文件(.*)\)/;
-      const match = successMessage.match(regex);
-
-      if (match && match[1] && match[2]) {
-        compressedSize = parseInt(match[1], 10);
-        sizeChangeText = match[2].trim();
-      }
-
+      const earlyUpdate = earlyTaskUpdates.current[taskId]?.update;
+      delete earlyTaskUpdates.current[taskId];
       setTasks(prevTasks =>
-        prevTasks.map(task =>
-          task.id === taskId ? {
-            ...task,
-            status: 'completed',
-            progress: 100,
-            compressedSize, // Add compressed size
-            sizeChangeText, // Add size change text
-          } : task
-        )
+        prevTasks.map(task => task.id === localId ? { ...task, id: taskId, ...earlyUpdate } : task)
       );
-      setCurrentStatusMessage(successMessage);
     } catch (error) {
-      console.error("视频压缩处理失败:", error);
+      console.error(`${taskTypeLabels[type]}压缩处理失败:`, error);
+      const errorText = describeError(error);
       setTasks(prevTasks =>
-        prevTasks.map(task =>
-          task.id === taskId ? { ...task, status: 'failed', error: String(error) } : task
-        )
+        prevTasks.map(task => task.id === localId ? { ...task, status: 'failed', error: errorText } : task)
       );
-      setCurrentStatusMessage(`视频 ${getFileName(inputFile)} 处理失败: ${String(error).substring(0,100)}`);
+      setCurrentStatusMessage(`${taskTypeLabels[type]} ${getFileName(inputFile)} 处理失败: ${errorText.substring(0,100)}`);
     }
   };
 
-  const startAudioCompression = async () => {
-    if (!inputFile || !outputFile) {
-      setCurrentStatusMessage("请先选择输入和输出文件。");
-      return;
-    }
-    if (tasks.find(task => task.id === inputFile && (task.status === 'compressing' || task.status === 'pending'))) {
-      setCurrentStatusMessage(`任务 ${getFileName(inputFile)} 已在队列中或正在处理。`);
-      return;
-    }
-
-    const taskId = inputFile;
-    const newTask: Task = {
-      id: taskId,
-      inputFile,
-      outputFile,
-      status: 'pending',
-      progress: 0,
-      type: 'audio',
-    };
-    setTasks(prevTasks => [newTask, ...prevTasks]);
-    setCurrentStatusMessage(`音频 ${getFileName(inputFile)} 已加入队列...`);
+  const replaceExtension = (path: string, extension: string) => {
+    const dotIndex = path.lastIndexOf('.');
+    return (dotIndex !== -1 ? path.substring(0, dotIndex) : path) + '.' + extension;
+  };
 
-    try {
-      setTasks(prevTasks => prevTasks.map(t => t.id === taskId ? {...t, status: 'compressing'} : t));
-      const successMessage = await invoke<string>("compress_audio", {
-        inputPath: inputFile,
-        outputPath: outputFile,
-        settings: audioSettings,
-      });
-      setTasks(prevTasks =>
-        prevTasks.map(task =>
-          task.id === taskId ? { ...task, status: 'completed', progress: 100 } : task
-        )
-      );
-      setCurrentStatusMessage(successMessage);
-    } catch (error) {
-      console.error("音频压缩处理失败:", error);
-      setTasks(prevTasks =>
-        prevTasks.map(task =>
-          task.id === taskId ? { ...task, status: 'failed', error: String(error) } : task
-        )
-      );
-      setCurrentStatusMessage(`音频 ${getFileName(inputFile)} 处理失败: ${String(error).substring(0,100)}`);
+  // 按文件类型选择压缩命令和设置，并让输出扩展名与所选格式一致
+  const startCompressionOf = async (type: ActiveView, input: string, output: string) => {
+    if (type === 'video') {
+      await startCompression('video', "compress_video", input, replaceExtension(output, videoSettings.container ?? 'mp4'), videoSettings);
+    } else if (type === 'audio') {
+      const outputExtension = audioFormatExtensions[audioSettings.format] ?? 'm4a';
+      await startCompression('audio', "compress_audio", input, replaceExtension(output, outputExtension), audioSettings);
+    } else {
+      // 选择了输出格式时替换扩展名，否则保持原格式
+      const imageExtension = imageSettings.format && imageFormatExtensions[imageSettings.format];
+      await startCompression('image', "compress_image", input, imageExtension ? replaceExtension(output, imageExtension) : output, imageSettings);
     }
   };
 
-  const startImageCompression = async () => {
+  const startSelectedCompression = async (type: ActiveView) => {
     if (!inputFile || !outputFile) {
       setCurrentStatusMessage("请先选择输入和输出文件。");
       return;
     }
-    if (tasks.find(task => task.id === inputFile && (task.status === 'compressing' || task.status === 'pending'))) {
-      setCurrentStatusMessage(`任务 ${getFileName(inputFile)} 已在队列中或正在处理。`);
-      return;
-    }
-
-    const taskId = inputFile;
-    const newTask: Task = {
-      id: taskId,
-      inputFile,
-      outputFile,
-      status: 'pending',
-      progress: 0,
-      type: 'image',
-    };
-    setTasks(prevTasks => [newTask, ...prevTasks]);
-    setCurrentStatusMessage(`图片 ${getFileName(inputFile)} 已加入队列...`);
-
-    try {
-      setTasks(prevTasks => prevTasks.map(t => t.id === taskId ? {...t, status: 'compressing'} : t));
-      const successMessage = await invoke<string>("compress_image", {
-        inputPath: inputFile, // Corrected, was: inputPath: inputFile,
-        outputPath: outputFile,
-        settings: imageSettings,
-      });
-      setTasks(prevTasks =>
-        prevTasks.map(task =>
-          task.id === taskId ? { ...task, status: 'completed', progress: 100 } : task
-        )
-      );
-      setCurrentStatusMessage(successMessage);
-    } catch (error) {
-      console.error("图片压缩处理失败:", error);
-      setTasks(prevTasks =>
-        prevTasks.map(task =>
-          task.id === taskId ? { ...task, status: 'failed', error: String(error) } : task
-        )
-      );
-      setCurrentStatusMessage(`图片 ${getFileName(inputFile)} 处理失败: ${String(error).substring(0,100)}`);
-    }
+    await startCompressionOf(type, inputFile, outputFile);
   };
 
+  const startVideoCompression = () => startSelectedCompression('video');
+  const startAudioCompression = () => startSelectedCompression('audio');
+  const startImageCompression = () => startSelectedCompression('image');
+
   const baseLinkClass = "flex items-center px-4 py-3 text-gray-600 dark:text-gray-300 hover:bg-gray-50 dark:hover:bg-gray-700/30";
   const activeLinkClass = "flex items-center px-4 py-3 bg-blue-50 dark:bg-blue-900/20 border-l-4 border-blue-500 text-blue-500 font-medium";
 
-  // Find the task that corresponds to the currently selected inputFile for display in the main UI
-  const currentFileTask = tasks.find(task => task.id === inputFile);
+  // Latest task for the currently selected inputFile (tasks are newest first), for display in the main UI
+  const currentFileTask = tasks.find(task => task.inputFile === inputFile);
   // Disable start button if current file is already compressing or pending
   const isCurrentFileProcessing = !!currentFileTask && (currentFileTask.status === 'compressing' || currentFileTask.status === 'pending');
 
@@ -336,7 +359,7 @@ function App() {
                 </div>
                 <div className="p-5">
                   {!inputFile ? (
-                    <DropZone onFileSelect={handleFileSelect} prompt="拖放视频文件到此处，或点击选择文件" />
+                    <DropZone onFileSelect={handleFileSelect} onFilesSelect={handleFilesSelect} prompt="拖放视频文件到此处，或点击选择文件" />
                   ) : (
                     <div className="space-y-4">
                       <div className="p-3 bg-gray-50 dark:bg-gray-700/30 rounded-md">
@@ -390,7 +413,7 @@ function App() {
                 </div>
                 <div className="p-5">
                   {!inputFile ? (
-                    <DropZone onFileSelect={handleFileSelect} prompt="拖放音频文件到此处，或点击选择文件" />
+                    <DropZone onFileSelect={handleFileSelect} onFilesSelect={handleFilesSelect} prompt="拖放音频文件到此处，或点击选择文件" />
                   ) : (
                      <div className="space-y-4">
                       <div className="p-3 bg-gray-50 dark:bg-gray-700/30 rounded-md">
@@ -444,7 +467,7 @@ function App() {
                 </div>
                 <div className="p-5">
                   {!inputFile ? (
-                    <DropZone onFileSelect={handleFileSelect} prompt="拖放图片文件到此处，或点击选择文件" />
+                    <DropZone onFileSelect={handleFileSelect} onFilesSelect={handleFilesSelect} prompt="拖放图片文件到此处，或点击选择文件" />
                   ) : (
                     <div className="space-y-4">
                       <div className="p-3 bg-gray-50 dark:bg-gray-700/30 rounded-md">
@@ -480,7 +503,7 @@ function App() {
         {/* Task List Display Area */}
         <div className="mt-8">
           <h2 className="text-xl font-semibold text-gray-700 dark:text-gray-200 mb-4">任务队列 ({tasks.length})</h2>
-          <TaskList tasks={tasks} getFileName={getFileName} />
+          <TaskList tasks={tasks} getFileName={getFileName} onCancel={cancelTask} />
         </div>
     </main>
     </div>
@@ -2,29 +2,61 @@ import { useState, useEffect } from 'react';
 
 export interface AudioCompressionSettings {
   quality: string;
+  format: string; // "aac" | "opus" | "mp3" | "flac"
 }
 
+// File extension written for each audio format
+export const audioFormatExtensions: Record<string, string> = {
+  aac: 'm4a',
+  opus: 'opus',
+  mp3: 'mp3',
+  flac: 'flac',
+};
+
 interface AudioCompressionSettingsProps {
   onSettingsChange: (settings: AudioCompressionSettings) => void;
   initialQuality?: string;
+  initialFormat?: string;
 }
 
 export function AudioCompressionSettingsComponent({
   onSettingsChange,
   initialQuality = 'medium',
+  initialFormat = 'aac',
 }: AudioCompressionSettingsProps) {
   const [quality, setQuality] = useState<string>(initialQuality);
+  const [format, setFormat] = useState<string>(initialFormat);
 
   useEffect(() => {
-    onSettingsChange({ quality });
-  }, [quality, onSettingsChange]);
+    onSettingsChange({ quality, format });
+  }, [quality, format, onSettingsChange]);
 
   const handleQualityChange = (event: React.ChangeEvent<HTMLSelectElement>) => {
     setQuality(event.target.value);
   };
 
+  const handleFormatChange = (event: React.ChangeEvent<HTMLSelectElement>) => {
+    setFormat(event.target.value);
+  };
+
   return (
     <div className="space-y-3">
+      <div>
+        <label htmlFor="audioFormat" className="block text-sm font-medium mb-1 text-gray-700 dark:text-gray-300">
+          输出格式
+        </label>
+        <select
+          id="audioFormat"
+          value={format}
+          onChange={handleFormatChange}
+          className="w-full rounded border border-gray-300 bg-white dark:bg-gray-800 dark:border-gray-600 px-3 py-1.5 text-sm"
+        >
+          <option value="aac">AAC (.m4a)</option>
+          <option value="opus">Opus (.opus)</option>
+          <option value="mp3">MP3 (.mp3)</option>
+          <option value="flac">FLAC (.flac, 无损)</option>
+        </select>
+      </div>
       <div>
         <label htmlFor="audioQuality" className="block text-sm font-medium mb-1 text-gray-700 dark:text-gray-300">
           音频质量
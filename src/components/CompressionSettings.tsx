@@ -4,12 +4,44 @@ interface CompressionSettingsProps {
   onSettingsChange: (settings: CompressionSettings) => void;
 }
 
-export type PresetType = 'quality' | 'vbr' | 'cbr' | 'scale' | 'targetSize';
+export type PresetType = 'quality' | 'vbr' | 'cbr' | 'scale' | 'targetSize' | 'perceptual';
+
+export type QualityMetric = 'vmaf' | 'ssim';
+
+export type VideoCodec = 'libx264' | 'libx265' | 'libvpx-vp9' | 'libaom-av1' | 'libsvtav1';
+
+// 硬件编码后端，'auto' 为自动选择可用的显卡
+export type HardwareEncoder = 'auto' | 'nvenc' | 'qsv' | 'vaapi' | 'videotoolbox' | 'amf';
+
+// 帧率：保持原样、限制上限，或转换为固定帧率
+export type FrameRate = { mode: 'keep' } | { mode: 'cap'; fps: number } | { mode: 'exact'; fps: number };
+
+// 裁剪：不裁剪、自动去黑边，或手动指定区域（按旋转后的显示画面计算）
+export type Crop =
+  | { mode: 'none' }
+  | { mode: 'auto' }
+  | { mode: 'manual'; x: number; y: number; width: number; height: number };
+
+// 保留的片段，单位秒；end 为空表示到结尾
+export interface KeepRange {
+  start: number;
+  end?: number;
+}
+
+export type VideoContainer = 'mp4' | 'mkv' | 'webm' | 'mov';
+
+// 每种封装格式可容纳的视频编码
+export const containerCodecs: Record<VideoContainer, VideoCodec[]> = {
+  mp4: ['libx264', 'libx265', 'libvpx-vp9', 'libaom-av1', 'libsvtav1'],
+  mkv: ['libx264', 'libx265', 'libvpx-vp9', 'libaom-av1', 'libsvtav1'],
+  webm: ['libvpx-vp9', 'libaom-av1', 'libsvtav1'],
+  mov: ['libx264', 'libx265'],
+};
 
 export interface CompressionSettings {
   preset: string; // Existing preset names like 'small', 'balanced', 'high' or new ones
   presetType: PresetType; // To distinguish new preset categories
-  resolution: string; // e.g., '480p', '720p', '1080p', 'original', or custom like '1280x720'
+  resolution: string; // e.g., '720p' (short edge), 'h720', 'w1280', 'l1920' (long edge), 'original', or a box like '1280x720'
   bitrate: string; // For CBR or as a general quality indicator if not using CRF
   audioQuality: string;
   customSettings: boolean;
@@ -17,6 +49,15 @@ export interface CompressionSettings {
   targetBitrate: string; // For CBR preset
   scalePercentage: string; // For scale preset (e.g., "50%")
   targetSizeMB: string; // For target file size preset
+  codec: VideoCodec; // Video encoder
+  hardwareEncoder: HardwareEncoder | null; // null 为软件编码
+  frameRate: FrameRate;
+  crop: Crop;
+  keepRanges: KeepRange[]; // 为空时保留整个视频
+  container: VideoContainer; // Output container, also decides the output extension
+  qualityMetric: QualityMetric; // For perceptual quality preset
+  qualityTarget: number; // VMAF 0-100 or SSIM 0-1
+  verifyQuality: boolean; // 压缩后与原视频对比 PSNR/SSIM/VMAF
 }
 
 export function CompressionSettings({ onSettingsChange }: CompressionSettingsProps) {
@@ -31,8 +72,20 @@ export function CompressionSettings({ onSettingsChange }: CompressionSettingsPro
     targetBitrate: '1000', // Default for CBR if selected
     scalePercentage: '50', // Default for scaling if selected
     targetSizeMB: '100', // Default for target size if selected
+    codec: 'libx264',
+    hardwareEncoder: null,
+    frameRate: { mode: 'keep' },
+    crop: { mode: 'none' },
+    keepRanges: [],
+    container: 'mp4',
+    qualityMetric: 'vmaf',
+    qualityTarget: 93,
+    verifyQuality: false,
   });
 
+  // 自定义尺寸形如 "1280x720"
+  const isCustomSize = settings.resolution.includes('x');
+
   const handlePresetChange = (preset: string, presetType: PresetType = 'quality') => {
     let newSettings: CompressionSettings = {
       ...settings,
@@ -87,18 +140,54 @@ export function CompressionSettings({ onSettingsChange }: CompressionSettingsPro
     } else if (presetType === 'targetSize') {
         newSettings.resolution = 'original';
         // targetSizeMB will be set by user input
+    } else if (presetType === 'perceptual') {
+        newSettings.resolution = 'original';
+        // qualityTarget will be set by user input
     }
     
     setSettings(newSettings);
     onSettingsChange(newSettings);
   };
 
-  const handleSettingChange = (key: keyof CompressionSettings, value: string | boolean | number) => {
+  // 截取片段输入框的内容，支持 "90"、"1:30"、"1:02:03"
+  const [trimStart, setTrimStart] = useState('');
+  const [trimEnd, setTrimEnd] = useState('');
+
+  const handleTrimChange = (start: string, end: string) => {
+    setTrimStart(start);
+    setTrimEnd(end);
+    const startSecs = parseTime(start);
+    const endSecs = parseTime(end);
+    const keepRanges: KeepRange[] = start.trim() === '' && end.trim() === ''
+      ? []
+      : [{ start: startSecs ?? 0, ...(endSecs !== undefined ? { end: endSecs } : {}) }];
+    const newSettings = { ...settings, keepRanges };
+    setSettings(newSettings);
+    onSettingsChange(newSettings);
+  };
+
+  const handleCropChange = (crop: Crop) => {
+    const newSettings = { ...settings, crop };
+    setSettings(newSettings);
+    onSettingsChange(newSettings);
+  };
+
+  const handleFrameRateChange = (frameRate: FrameRate) => {
+    const newSettings = { ...settings, frameRate };
+    setSettings(newSettings);
+    onSettingsChange(newSettings);
+  };
+
+  const handleSettingChange = (key: keyof CompressionSettings, value: string | boolean | number | null) => {
     const newSettings = { ...settings, [key]: value };
     // Ensure numeric fields are numbers
-    if ((key === 'crfValue' || key === 'targetBitrate' || key === 'targetSizeMB' || key === 'scalePercentage') && typeof value === 'string') {
-      // For scalePercentage, allow decimal, otherwise parseInt
-      newSettings[key] = key === 'scalePercentage' ? parseFloat(value) : parseInt(value, 10);
+    if ((key === 'crfValue' || key === 'targetBitrate' || key === 'targetSizeMB' || key === 'scalePercentage' || key === 'qualityTarget') && typeof value === 'string') {
+      // For scalePercentage and qualityTarget, allow decimal, otherwise parseInt
+      newSettings[key] = key === 'scalePercentage' || key === 'qualityTarget' ? parseFloat(value) : parseInt(value, 10);
+    }
+    // 切换指标时换成该指标的常用目标值
+    if (key === 'qualityMetric' && value !== settings.qualityMetric) {
+      newSettings.qualityTarget = value === 'ssim' ? 0.98 : 93;
     }
     setSettings(newSettings);
     onSettingsChange(newSettings);
@@ -187,9 +276,175 @@ export function CompressionSettings({ onSettingsChange }: CompressionSettingsPro
           >
             目标文件大小
           </button>
+          <button
+            type="button"
+            className={`px-3 py-1.5 rounded text-center text-sm ${
+              settings.presetType === 'perceptual'
+                ? 'bg-blue-500 text-white'
+                : 'bg-gray-100 text-gray-800 dark:bg-gray-700 dark:text-gray-200'
+            }`}
+            onClick={() => handlePresetChange('perceptual_default', 'perceptual')}
+          >
+            目标画质
+          </button>
         </div>
       </div>
 
+      <div>
+        <label htmlFor="videoCodec" className="block text-sm font-medium mb-1 text-gray-700 dark:text-gray-300">
+          视频编码
+        </label>
+        <select
+          id="videoCodec"
+          value={settings.codec}
+          onChange={(e) => handleSettingChange('codec', e.target.value)}
+          className="w-full rounded border border-gray-300 bg-white dark:bg-gray-800 dark:border-gray-600 px-3 py-1.5 text-sm"
+        >
+          <option value="libx264">H.264 (兼容性最好)</option>
+          <option value="libx265">H.265 / HEVC</option>
+          <option value="libvpx-vp9">VP9 (WebM)</option>
+          <option value="libaom-av1">AV1 (libaom)</option>
+          <option value="libsvtav1">AV1 (SVT-AV1, 更快)</option>
+        </select>
+        {!containerCodecs[settings.container].includes(settings.codec) && (
+          <p className="text-xs text-red-500 dark:text-red-400 mt-1">当前封装格式不支持该编码，请更换封装格式。</p>
+        )}
+        {settings.codec === 'libsvtav1' && settings.presetType === 'targetSize' && (
+          <p className="text-xs text-red-500 dark:text-red-400 mt-1">SVT-AV1 不支持目标文件大小模式。</p>
+        )}
+      </div>
+
+      <div>
+        <label htmlFor="hardwareEncoder" className="block text-sm font-medium mb-1 text-gray-700 dark:text-gray-300">
+          硬件加速
+        </label>
+        <select
+          id="hardwareEncoder"
+          value={settings.hardwareEncoder ?? ''}
+          onChange={(e) => handleSettingChange('hardwareEncoder', e.target.value || null)}
+          className="w-full rounded border border-gray-300 bg-white dark:bg-gray-800 dark:border-gray-600 px-3 py-1.5 text-sm"
+        >
+          <option value="">关闭 (软件编码)</option>
+          <option value="auto">自动</option>
+          <option value="nvenc">NVIDIA NVENC</option>
+          <option value="qsv">Intel Quick Sync</option>
+          <option value="vaapi">VAAPI (Linux)</option>
+          <option value="videotoolbox">VideoToolbox (macOS)</option>
+          <option value="amf">AMD AMF</option>
+        </select>
+        {settings.hardwareEncoder && (
+          <p className="text-xs text-gray-500 dark:text-gray-400 mt-1">
+            所选硬件不可用时自动改用软件编码；目标文件大小模式始终使用软件编码。
+          </p>
+        )}
+      </div>
+
+      <div>
+        <label className="block text-sm font-medium mb-1 text-gray-700 dark:text-gray-300">截取片段</label>
+        <div className="flex items-center gap-2">
+          <input
+            type="text"
+            value={trimStart}
+            onChange={(e) => handleTrimChange(e.target.value, trimEnd)}
+            placeholder="开始，如 0:30"
+            className="flex-1 rounded border border-gray-300 bg-white dark:bg-gray-800 dark:border-gray-600 px-3 py-1.5 text-sm"
+          />
+          <span className="text-gray-500">-</span>
+          <input
+            type="text"
+            value={trimEnd}
+            onChange={(e) => handleTrimChange(trimStart, e.target.value)}
+            placeholder="结束，留空到结尾"
+            className="flex-1 rounded border border-gray-300 bg-white dark:bg-gray-800 dark:border-gray-600 px-3 py-1.5 text-sm"
+          />
+        </div>
+      </div>
+
+      <div>
+        <label htmlFor="cropMode" className="block text-sm font-medium mb-1 text-gray-700 dark:text-gray-300">
+          裁剪画面
+        </label>
+        <select
+          id="cropMode"
+          value={settings.crop.mode}
+          onChange={(e) => {
+            const mode = e.target.value as Crop['mode'];
+            handleCropChange(mode === 'manual' ? { mode, x: 0, y: 0, width: 1280, height: 720 } : { mode });
+          }}
+          className="w-full rounded border border-gray-300 bg-white dark:bg-gray-800 dark:border-gray-600 px-3 py-1.5 text-sm"
+        >
+          <option value="none">不裁剪</option>
+          <option value="auto">自动去除黑边</option>
+          <option value="manual">手动指定区域</option>
+        </select>
+        {settings.crop.mode === 'manual' && (
+          <div className="mt-2 grid grid-cols-4 gap-2">
+            {(['x', 'y', 'width', 'height'] as const).map((field) => (
+              <input
+                key={field}
+                type="number"
+                min={0}
+                value={settings.crop.mode === 'manual' ? settings.crop[field] : 0}
+                onChange={(e) => settings.crop.mode === 'manual'
+                  && handleCropChange({ ...settings.crop, [field]: Math.max(0, parseInt(e.target.value, 10) || 0) })}
+                title={{ x: '左边距', y: '上边距', width: '宽度', height: '高度' }[field]}
+                className="rounded border border-gray-300 bg-white dark:bg-gray-800 dark:border-gray-600 px-2 py-1.5 text-sm"
+              />
+            ))}
+          </div>
+        )}
+      </div>
+
+      <div>
+        <label htmlFor="frameRateMode" className="block text-sm font-medium mb-1 text-gray-700 dark:text-gray-300">
+          帧率
+        </label>
+        <div className="flex gap-2">
+          <select
+            id="frameRateMode"
+            value={settings.frameRate.mode}
+            onChange={(e) => {
+              const mode = e.target.value as FrameRate['mode'];
+              const fps = settings.frameRate.mode === 'keep' ? 30 : settings.frameRate.fps;
+              handleFrameRateChange(mode === 'keep' ? { mode } : { mode, fps });
+            }}
+            className="flex-1 rounded border border-gray-300 bg-white dark:bg-gray-800 dark:border-gray-600 px-3 py-1.5 text-sm"
+          >
+            <option value="keep">保持原帧率</option>
+            <option value="cap">最高不超过</option>
+            <option value="exact">固定为</option>
+          </select>
+          {settings.frameRate.mode !== 'keep' && (
+            <select
+              value={settings.frameRate.fps}
+              onChange={(e) => handleFrameRateChange({ mode: settings.frameRate.mode as 'cap' | 'exact', fps: parseFloat(e.target.value) })}
+              className="w-28 rounded border border-gray-300 bg-white dark:bg-gray-800 dark:border-gray-600 px-3 py-1.5 text-sm"
+            >
+              {[15, 24, 25, 30, 50, 60].map((fps) => (
+                <option key={fps} value={fps}>{fps} fps</option>
+              ))}
+            </select>
+          )}
+        </div>
+      </div>
+
+      <div>
+        <label htmlFor="videoContainer" className="block text-sm font-medium mb-1 text-gray-700 dark:text-gray-300">
+          封装格式
+        </label>
+        <select
+          id="videoContainer"
+          value={settings.container}
+          onChange={(e) => handleSettingChange('container', e.target.value)}
+          className="w-full rounded border border-gray-300 bg-white dark:bg-gray-800 dark:border-gray-600 px-3 py-1.5 text-sm"
+        >
+          <option value="mp4">MP4 (网页边下边播)</option>
+          <option value="mkv">MKV</option>
+          <option value="webm">WebM</option>
+          <option value="mov">MOV</option>
+        </select>
+      </div>
+
       {/* Conditional Inputs based on PresetType */}
       {settings.presetType === 'vbr' && (
         <div>
@@ -274,6 +529,43 @@ export function CompressionSettings({ onSettingsChange }: CompressionSettingsPro
         </div>
       )}
 
+      {settings.presetType === 'perceptual' && (
+        <div className="space-y-2">
+          <div>
+            <label htmlFor="qualityMetric" className="block text-sm font-medium mb-1 text-gray-700 dark:text-gray-300">
+              画质指标
+            </label>
+            <select
+              id="qualityMetric"
+              value={settings.qualityMetric}
+              onChange={(e) => handleSettingChange('qualityMetric', e.target.value)}
+              className="w-full rounded border border-gray-300 bg-white dark:bg-gray-800 dark:border-gray-600 px-3 py-1.5 text-sm"
+            >
+              <option value="vmaf">VMAF (0-100)</option>
+              <option value="ssim">SSIM (0-1)</option>
+            </select>
+          </div>
+          <div>
+            <label htmlFor="qualityTarget" className="block text-sm font-medium mb-1 text-gray-700 dark:text-gray-300">
+              目标分数
+            </label>
+            <input
+              type="number"
+              id="qualityTarget"
+              value={settings.qualityTarget}
+              onChange={(e) => handleSettingChange('qualityTarget', e.target.value)}
+              className="w-full rounded border border-gray-300 bg-white dark:bg-gray-800 dark:border-gray-600 px-3 py-1.5 text-sm"
+              min={settings.qualityMetric === 'ssim' ? '0.5' : '50'}
+              max={settings.qualityMetric === 'ssim' ? '1' : '100'}
+              step={settings.qualityMetric === 'ssim' ? '0.005' : '1'}
+            />
+            <p className="text-xs text-gray-500 dark:text-gray-400 mt-1">
+              先用几段样本试编码并打分，选出达到目标分数的最高 CRF。VMAF 93 左右肉眼难以分辨，需要 FFmpeg 带 libvmaf。
+            </p>
+          </div>
+        </div>
+      )}
+
       {/* Common settings like Audio Quality, shown if not a specific preset that overrides them, or if custom settings are on */}
       {settings.presetType === 'quality' || settings.presetType === 'vbr' || settings.presetType === 'cbr' || settings.customSettings ? (
       <div>
@@ -296,6 +588,19 @@ export function CompressionSettings({ onSettingsChange }: CompressionSettingsPro
 
 
       <div className="flex items-center space-x-2 pt-2">
+        <input
+          type="checkbox"
+          id="verifyQuality"
+          checked={settings.verifyQuality}
+          onChange={(e) => handleSettingChange('verifyQuality', e.target.checked)}
+          className="rounded border-gray-300 text-blue-500 focus:ring-blue-500"
+        />
+        <label htmlFor="verifyQuality" className="text-sm font-medium text-gray-700 dark:text-gray-300">
+          压缩后校验画质 (PSNR/SSIM/VMAF，耗时较长)
+        </label>
+      </div>
+
+      <div className="flex items-center space-x-2">
         <input
           type="checkbox"
           id="customSettings"
@@ -317,16 +622,28 @@ export function CompressionSettings({ onSettingsChange }: CompressionSettingsPro
             </label>
             <select
               id="customResolution"
-              value={settings.resolution} // Still uses the main resolution state
-              onChange={(e) => handleSettingChange('resolution', e.target.value)}
+              value={isCustomSize ? 'custom' : settings.resolution} // Still uses the main resolution state
+              onChange={(e) => handleSettingChange('resolution', e.target.value === 'custom' ? '1280x720' : e.target.value)}
               className="w-full rounded border border-gray-300 bg-white dark:bg-gray-800 dark:border-gray-600 px-3 py-1.5 text-sm"
             >
               <option value="original">保持原始分辨率</option>
-              <option value="480p">480p (854x480)</option>
-              <option value="720p">720p (1280x720)</option>
-              <option value="1080p">1080p (1920x1080)</option>
-              {/* Consider adding a "custom" option to input width/height manually if needed */}
+              <option value="480p">480p (短边 480)</option>
+              <option value="720p">720p (短边 720)</option>
+              <option value="1080p">1080p (短边 1080)</option>
+              <option value="l1280">长边 1280</option>
+              <option value="l1920">长边 1920</option>
+              <option value="custom">自定义尺寸…</option>
             </select>
+            {isCustomSize && (
+              <input
+                type="text"
+                value={settings.resolution}
+                onChange={(e) => handleSettingChange('resolution', e.target.value.trim())}
+                placeholder="1280x720"
+                className="w-full mt-2 rounded border border-gray-300 bg-white dark:bg-gray-800 dark:border-gray-600 px-3 py-1.5 text-sm"
+              />
+            )}
+            <p className="text-xs text-gray-500 dark:text-gray-400 mt-1">保持原始宽高比，不会放大；自定义尺寸为最大宽 x 高。</p>
           </div>
 
           {/* Show CRF slider if custom settings are on, AND current preset is NOT CBR */}
@@ -372,4 +689,17 @@ export function CompressionSettings({ onSettingsChange }: CompressionSettingsPro
       )}
     </div>
   );
-}
\ No newline at end of file
+}
+
+// 把 "90"、"1:30"、"1:02:03.5" 解析为秒，无法解析时返回 undefined
+function parseTime(value: string): number | undefined {
+  const parts = value.trim().split(':');
+  if (value.trim() === '' || parts.length > 3) return undefined;
+  let seconds = 0;
+  for (const part of parts) {
+    const n = Number(part);
+    if (part === '' || Number.isNaN(n) || n < 0) return undefined;
+    seconds = seconds * 60 + n;
+  }
+  return seconds;
+}
@@ -1,18 +1,27 @@
 import { useState } from 'react';
 import { invoke } from '@tauri-apps/api/core';
 import { useEffect } from 'react';
+import { isCompressError } from '../errors';
+import type { ActiveView } from './TaskList';
 
 // 扩展File接口，添加Tauri特有的path属性
 interface TauriFile extends File {
   path?: string;
 }
 
+// 后端按探测到的内容判断文件类型，而不是按扩展名
+export interface SelectedFile {
+  path: string;
+  kind: ActiveView;
+}
+
 interface DropZoneProps {
   onFileSelect: (filePath: string) => void;
+  onFilesSelect?: (files: SelectedFile[]) => void; // 提供时收到全部文件，否则只取第一个
   prompt: string;
 }
 
-export function DropZone({ onFileSelect, prompt }: DropZoneProps) {
+export function DropZone({ onFileSelect, onFilesSelect, prompt }: DropZoneProps) {
   const [isDragging, setIsDragging] = useState(false);
   const [isSelecting, setIsSelecting] = useState(false);
 
@@ -22,9 +31,11 @@ export function DropZone({ onFileSelect, prompt }: DropZoneProps) {
     const handleFileDrop = (e: DragEvent) => {
       e.preventDefault();
       if (e.dataTransfer && e.dataTransfer.files.length > 0) {
-        const file = e.dataTransfer.files[0] as TauriFile;
-        if (file.path) {
-          handleDroppedFile(file.path);
+        const paths = Array.from(e.dataTransfer.files)
+          .map(file => (file as TauriFile).path)
+          .filter((path): path is string => !!path);
+        if (paths.length > 0) {
+          handleDroppedFiles(paths);
         }
       }
     };
@@ -38,7 +49,7 @@ export function DropZone({ onFileSelect, prompt }: DropZoneProps) {
       document.removeEventListener('drop', handleFileDrop);
       document.removeEventListener('dragover', (e) => e.preventDefault());
     };
-  }, [onFileSelect]);
+  }, [onFileSelect, onFilesSelect]);
 
   const handleDragOver = (e: React.DragEvent<HTMLDivElement>) => {
     e.preventDefault();
@@ -55,10 +66,12 @@ export function DropZone({ onFileSelect, prompt }: DropZoneProps) {
 
     // 在Tauri应用中处理拖放的文件
     if (e.dataTransfer.files.length > 0) {
-      const file = e.dataTransfer.files[0] as TauriFile;
       // 在Tauri中，我们可以通过file.path获取完整路径
-      if (file.path) {
-        handleDroppedFile(file.path);
+      const paths = Array.from(e.dataTransfer.files)
+        .map(file => (file as TauriFile).path)
+        .filter((path): path is string => !!path);
+      if (paths.length > 0) {
+        handleDroppedFiles(paths);
       } else {
         console.error('无法获取文件路径，可能是浏览器安全限制');
         // 如果无法直接获取路径，回退到文件选择对话框
@@ -67,16 +80,26 @@ export function DropZone({ onFileSelect, prompt }: DropZoneProps) {
     }
   };
 
-  const handleDroppedFile = async (filePath: string) => {
-    try {
-      // 调用Rust后端处理拖放的文件
-      const result = await invoke<string>("handle_dropped_file", { filePath });
-      if (result) {
-        onFileSelect(result);
+  const deliverFiles = (files: SelectedFile[]) => {
+    if (files.length === 0) return;
+    if (onFilesSelect) {
+      onFilesSelect(files);
+    } else {
+      onFileSelect(files[0].path);
+    }
+  };
+
+  const handleDroppedFiles = async (filePaths: string[]) => {
+    // 拖入的文件夹由后端展开成其中的媒体文件
+    const files: SelectedFile[] = [];
+    for (const filePath of filePaths) {
+      try {
+        files.push(...await invoke<SelectedFile[]>("handle_dropped_file", { filePath }));
+      } catch (error) {
+        console.error('处理拖放文件出错:', filePath, error);
       }
-    } catch (error) {
-      console.error('处理拖放文件出错:', error);
     }
+    deliverFiles(files);
   };
 
   const handleFileSelect = async () => {
@@ -85,12 +108,11 @@ export function DropZone({ onFileSelect, prompt }: DropZoneProps) {
     try {
       setIsSelecting(true);
       // 使用Tauri的命令调用Rust端的文件选择功能
-      const filePath = await invoke<string>("select_video_file");
-      
-      if (filePath) {
-        onFileSelect(filePath);
-      }
+      const files = await invoke<SelectedFile[]>("select_files");
+      deliverFiles(files);
     } catch (error) {
+      // 用户关闭对话框不算出错
+      if (isCompressError(error) && error.kind === 'NoFileSelected') return;
       console.error('选择文件出错:', error);
     } finally {
       setIsSelecting(false);
@@ -2,8 +2,20 @@ import { useState, useEffect } from 'react';
 
 export interface ImageCompressionSettings {
   quality: number; // Typically 0-100 for images
+  format?: string; // "jpeg" | "png" | "webp" | "avif"; empty keeps the source format
+  maxWidth?: number; // Optional bounding box, images are only scaled down
+  maxHeight?: number;
+  stripMetadata: boolean;
 }
 
+// File extension written for each image format
+export const imageFormatExtensions: Record<string, string> = {
+  jpeg: 'jpg',
+  png: 'png',
+  webp: 'webp',
+  avif: 'avif',
+};
+
 interface ImageCompressionSettingsProps {
   onSettingsChange: (settings: ImageCompressionSettings) => void;
   initialQuality?: number;
@@ -14,10 +26,22 @@ export function ImageCompressionSettingsComponent({
   initialQuality = 75, // A common default quality
 }: ImageCompressionSettingsProps) {
   const [quality, setQuality] = useState<number>(initialQuality);
+  const [format, setFormat] = useState<string>('');
+  const [maxWidth, setMaxWidth] = useState<string>('');
+  const [maxHeight, setMaxHeight] = useState<string>('');
+  const [stripMetadata, setStripMetadata] = useState<boolean>(true);
 
   useEffect(() => {
-    onSettingsChange({ quality });
-  }, [quality, onSettingsChange]);
+    const parsedWidth = parseInt(maxWidth, 10);
+    const parsedHeight = parseInt(maxHeight, 10);
+    onSettingsChange({
+      quality,
+      format: format || undefined,
+      maxWidth: parsedWidth > 0 ? parsedWidth : undefined,
+      maxHeight: parsedHeight > 0 ? parsedHeight : undefined,
+      stripMetadata,
+    });
+  }, [quality, format, maxWidth, maxHeight, stripMetadata, onSettingsChange]);
 
   const handleQualityChange = (event: React.ChangeEvent<HTMLInputElement>) => {
     const newQuality = parseInt(event.target.value, 10);
@@ -28,6 +52,23 @@ export function ImageCompressionSettingsComponent({
 
   return (
     <div className="space-y-3">
+      <div>
+        <label htmlFor="imageFormat" className="block text-sm font-medium mb-1 text-gray-700 dark:text-gray-300">
+          输出格式
+        </label>
+        <select
+          id="imageFormat"
+          value={format}
+          onChange={(e) => setFormat(e.target.value)}
+          className="w-full rounded border border-gray-300 bg-white dark:bg-gray-800 dark:border-gray-600 px-3 py-1.5 text-sm"
+        >
+          <option value="">保持原格式</option>
+          <option value="jpeg">JPEG</option>
+          <option value="png">PNG</option>
+          <option value="webp">WebP</option>
+          <option value="avif">AVIF</option>
+        </select>
+      </div>
       <div>
         <label htmlFor="imageQuality" className="block text-sm font-medium mb-1 text-gray-700 dark:text-gray-300">
           图片质量 ({quality})
@@ -45,6 +86,45 @@ export function ImageCompressionSettingsComponent({
           选择期望的图片质量。较低的值会显著减小文件大小但可能降低图片清晰度。
         </p>
       </div>
+      <div className="grid grid-cols-2 gap-3">
+        <div>
+          <label htmlFor="imageMaxWidth" className="block text-sm font-medium mb-1 text-gray-700 dark:text-gray-300">
+            最大宽度 (px)
+          </label>
+          <input
+            type="number"
+            id="imageMaxWidth"
+            min="1"
+            placeholder="不限制"
+            value={maxWidth}
+            onChange={(e) => setMaxWidth(e.target.value)}
+            className="w-full rounded border border-gray-300 bg-white dark:bg-gray-800 dark:border-gray-600 px-3 py-1.5 text-sm"
+          />
+        </div>
+        <div>
+          <label htmlFor="imageMaxHeight" className="block text-sm font-medium mb-1 text-gray-700 dark:text-gray-300">
+            最大高度 (px)
+          </label>
+          <input
+            type="number"
+            id="imageMaxHeight"
+            min="1"
+            placeholder="不限制"
+            value={maxHeight}
+            onChange={(e) => setMaxHeight(e.target.value)}
+            className="w-full rounded border border-gray-300 bg-white dark:bg-gray-800 dark:border-gray-600 px-3 py-1.5 text-sm"
+          />
+        </div>
+      </div>
+      <label className="flex items-center text-sm text-gray-700 dark:text-gray-300">
+        <input
+          type="checkbox"
+          checked={stripMetadata}
+          onChange={(e) => setStripMetadata(e.target.checked)}
+          className="mr-2"
+        />
+        移除元数据 (EXIF、GPS 等)
+      </label>
     </div>
   );
 }
@@ -4,21 +4,64 @@ import { ProgressBar } from './ProgressBar'; // Assuming ProgressBar is in the s
 // Define Task and ActiveView types (can be moved to a shared types.ts later)
 export type ActiveView = 'video' | 'audio' | 'image';
 
+// 后端 CompressionResult，压缩成功后随 TASK_COMPLETED_EVENT 返回
+export interface CompressionResult {
+  outputPath: string;
+  originalSize: number;
+  compressedSize: number;
+  ratio: number; // 压缩后 / 原始
+  elapsedSecs: number;
+  speed: number | null; // 每秒编码的媒体时长（倍速）
+  bitrate: number | null; // bit/s
+  width: number | null;
+  height: number | null;
+  codec: string | null;
+  ffmpegArgs: string[];
+  quality?: QualityReport | null; // 开启画质校验时才有
+}
+
+// 画质校验结果，worstFrames 按分数从低到高排列
+export interface MetricSummary {
+  average: number;
+  min: number;
+  worstFrames: { frame: number; score: number }[];
+}
+
+export interface QualityReport {
+  psnr: MetricSummary | null;
+  ssim: MetricSummary | null;
+  vmaf: MetricSummary | null; // FFmpeg 没有 libvmaf 时为 null
+}
+
+// 编码过程中的实时数据，来自 PROGRESS_EVENT
+export interface EncodeStats {
+  fps?: number;
+  speed?: number;
+  etaSecs?: number;
+  bytesWritten?: number;
+  bitrateKbps?: number;
+}
+
 export interface Task {
   id: string;
   inputFile: string;
   outputFile: string;
-  status: 'pending' | 'compressing' | 'completed' | 'failed' | 'finishing';
+  status: 'pending' | 'compressing' | 'completed' | 'failed' | 'finishing' | 'cancelled';
   progress: number;
+  pass?: number; // 两遍编码（目标文件大小模式）当前所在的遍数
+  stats?: EncodeStats;
   type: ActiveView;
   error?: string;
+  errorKind?: string; // CompressError kind from the backend, e.g. 'EncodeFailed'
   compressedSize?: number; // Added: 压缩后文件大小 (bytes)
   sizeChangeText?: string; // Added: 大小变化描述 (例如: "减少了 20.50%")
+  result?: CompressionResult;
 }
 
 interface TaskListProps {
   tasks: Task[];
   getFileName: (path: string) => string; // Pass getFileName as a prop
+  onCancel?: (taskId: string) => void; // Shown as a cancel button on running tasks
 }
 
 const formatBytes = (bytes: number, decimals = 2) => {
@@ -30,7 +73,41 @@ const formatBytes = (bytes: number, decimals = 2) => {
   return parseFloat((bytes / Math.pow(k, i)).toFixed(dm)) + ' ' + sizes[i];
 };
 
-export const TaskList: React.FC<TaskListProps> = ({ tasks, getFileName }) => {
+const formatDuration = (totalSeconds: number) => {
+  const seconds = Math.max(0, Math.round(totalSeconds));
+  const h = Math.floor(seconds / 3600);
+  const m = Math.floor((seconds % 3600) / 60);
+  const s = seconds % 60;
+  const mmss = `${String(m).padStart(2, '0')}:${String(s).padStart(2, '0')}`;
+  return h > 0 ? `${h}:${mmss}` : mmss;
+};
+
+const formatStats = (stats?: EncodeStats) => {
+  if (!stats) return '';
+  const parts: string[] = [];
+  if (stats.speed !== undefined) parts.push(`${stats.speed.toFixed(2)}x`);
+  if (stats.fps !== undefined) parts.push(`${stats.fps.toFixed(0)} fps`);
+  if (stats.bitrateKbps !== undefined) parts.push(`${stats.bitrateKbps.toFixed(0)} kbps`);
+  if (stats.bytesWritten !== undefined) parts.push(formatBytes(stats.bytesWritten));
+  if (stats.etaSecs !== undefined) parts.push(`剩余 ${formatDuration(stats.etaSecs)}`);
+  return parts.join(' · ');
+};
+
+const formatQuality = (quality?: QualityReport | null) => {
+  if (!quality) return '';
+  const parts: string[] = [];
+  const describe = (name: string, metric: MetricSummary | null, digits: number) => {
+    if (!metric) return;
+    const worst = metric.worstFrames[0];
+    parts.push(`${name} 平均 ${metric.average.toFixed(digits)}，最低 ${metric.min.toFixed(digits)}${worst ? ` (第 ${worst.frame} 帧)` : ''}`);
+  };
+  describe('VMAF', quality.vmaf, 2);
+  describe('SSIM', quality.ssim, 4);
+  describe('PSNR', quality.psnr, 2);
+  return parts.join(' · ');
+};
+
+export const TaskList: React.FC<TaskListProps> = ({ tasks, getFileName, onCancel }) => {
   if (tasks.length === 0) {
     return <p className="text-gray-500 dark:text-gray-400">当前没有任务。</p>;
   }
@@ -43,17 +120,31 @@ export const TaskList: React.FC<TaskListProps> = ({ tasks, getFileName }) => {
             <span className="font-medium text-gray-800 dark:text-gray-100 truncate max-w-md" title={task.inputFile}>
               {getFileName(task.inputFile)} ({task.type})
             </span>
-            <span className={`px-2 py-1 text-xs rounded-full ${
-              task.status === 'completed' ? 'bg-green-100 dark:bg-green-700 text-green-700 dark:text-green-100' :
-              task.status === 'failed' ? 'bg-red-100 dark:bg-red-700 text-red-700 dark:text-red-100' :
-              task.status === 'pending' ? 'bg-yellow-100 dark:bg-yellow-700 text-yellow-700 dark:text-yellow-100' :
-              'bg-blue-100 dark:bg-blue-700 text-blue-700 dark:text-blue-100' // compressing or finishing
-            }`}>
-              {task.status}
-            </span>
+            <div className="flex items-center space-x-2">
+              {onCancel && (task.status === 'compressing' || task.status === 'pending') && (
+                <button onClick={() => onCancel(task.id)} className="text-xs text-gray-500 hover:text-red-500 dark:hover:text-red-400">
+                  取消
+                </button>
+              )}
+              <span className={`px-2 py-1 text-xs rounded-full ${
+                task.status === 'completed' ? 'bg-green-100 dark:bg-green-700 text-green-700 dark:text-green-100' :
+                task.status === 'failed' ? 'bg-red-100 dark:bg-red-700 text-red-700 dark:text-red-100' :
+                task.status === 'pending' ? 'bg-yellow-100 dark:bg-yellow-700 text-yellow-700 dark:text-yellow-100' :
+                task.status === 'cancelled' ? 'bg-gray-100 dark:bg-gray-700 text-gray-700 dark:text-gray-100' :
+                'bg-blue-100 dark:bg-blue-700 text-blue-700 dark:text-blue-100' // compressing or finishing
+              }`}>
+                {task.status}
+              </span>
+            </div>
           </div>
           {(task.status === 'compressing' || task.status === 'finishing' || task.status === 'completed') && (
-            <ProgressBar progress={task.progress} infoText={`${task.progress.toFixed(0)}%`} />
+            <ProgressBar
+              progress={task.progress}
+              infoText={task.pass && task.status !== 'completed' ? `${task.progress.toFixed(0)}% (第 ${task.pass}/2 遍)` : `${task.progress.toFixed(0)}%`}
+            />
+          )}
+          {task.status === 'compressing' && formatStats(task.stats) && (
+            <p className="text-xs text-gray-500 dark:text-gray-400 mt-1">{formatStats(task.stats)}</p>
           )}
           {task.status === 'failed' && task.error && (
             <p className="text-xs text-red-500 dark:text-red-400 mt-1" title={task.error}>
@@ -75,6 +166,11 @@ export const TaskList: React.FC<TaskListProps> = ({ tasks, getFileName }) => {
                   大小变化: {task.sizeChangeText}
                 </p>
               )}
+              {formatQuality(task.result?.quality) && (
+                <p className="text-xs text-green-500 dark:text-green-400 mt-1">
+                  画质: {formatQuality(task.result?.quality)}
+                </p>
+              )}
             </>
           )}
         </div>
@@ -0,0 +1,53 @@
+// 与后端 CompressError 对应，按 kind 区分错误类型
+export type CompressError =
+  | { kind: 'FfmpegNotFound'; program: string }
+  | { kind: 'InputMissing'; path: string }
+  | { kind: 'ProbeFailed'; path: string; reason: string }
+  | { kind: 'EncodeFailed'; exitCode: number | null; stderrTail: string }
+  | { kind: 'InvalidSettings'; field: string; reason: string }
+  | { kind: 'Cancelled'; taskId: string }
+  | { kind: 'NoFileSelected' }
+  | { kind: 'TaskNotFound'; taskId: string }
+  | { kind: 'TaskConflict'; taskId: string; reason: string }
+  | { kind: 'Io'; reason: string }
+  | { kind: 'OutputExists'; path: string }
+  | { kind: 'OutputSkipped'; path: string }
+  | { kind: 'NoMediaFiles'; path: string };
+
+export const isCompressError = (error: unknown): error is CompressError =>
+  typeof error === 'object' && error !== null && typeof (error as { kind?: unknown }).kind === 'string';
+
+// 把后端错误转换成界面上显示的文字
+export const describeError = (error: unknown): string => {
+  if (!isCompressError(error)) {
+    return String(error);
+  }
+  switch (error.kind) {
+    case 'FfmpegNotFound':
+      return `找不到 ${error.program}，请确认已安装并加入 PATH`;
+    case 'InputMissing':
+      return `文件不存在: ${error.path}`;
+    case 'ProbeFailed':
+      return `无法读取媒体信息: ${error.reason}`;
+    case 'EncodeFailed':
+      return `FFmpeg 执行失败${error.exitCode !== null ? ` (退出码 ${error.exitCode})` : ''}: ${error.stderrTail}`;
+    case 'InvalidSettings':
+      return `参数 ${error.field} 无效: ${error.reason}`;
+    case 'Cancelled':
+      return '任务已取消';
+    case 'NoFileSelected':
+      return '未选择文件';
+    case 'TaskNotFound':
+      return `任务不存在: ${error.taskId}`;
+    case 'TaskConflict':
+      return `任务 ${error.taskId}: ${error.reason}`;
+    case 'Io':
+      return error.reason;
+    case 'OutputExists':
+      return `输出文件已存在: ${error.path}`;
+    case 'OutputSkipped':
+      return `输出文件已存在，已跳过: ${error.path}`;
+    case 'NoMediaFiles':
+      return `文件夹中没有媒体文件: ${error.path}`;
+  }
+};